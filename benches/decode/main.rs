@@ -0,0 +1,43 @@
+//! Compares the prefix-table [`Decoder`] with the original decoder on
+//! strike-sized and storm-sized messages.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use popuppp::blitzortung::{Decoder, encode};
+use std::hint::black_box;
+
+#[path = "../../src/reference_decode.rs"]
+mod reference_decode;
+
+const FIXTURES: &str = include_str!("../../fixtures/strikes.jsonl");
+
+fn corpus() -> Vec<(&'static str, String)> {
+    let strike = FIXTURES.lines().next().unwrap();
+    vec![
+        ("strike", encode(strike)),
+        ("fixtures", encode(FIXTURES)),
+        ("storm", encode(&FIXTURES.repeat(100))),
+    ]
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for (name, encoded) in corpus() {
+        let mut decoder = Decoder::new();
+        assert_eq!(
+            decoder.decode(&encoded).unwrap(),
+            reference_decode::decode(&encoded)
+        );
+
+        group.throughput(Throughput::Bytes(encoded.len() as u64));
+        group.bench_with_input(BenchmarkId::new("reference", name), &encoded, |b, input| {
+            b.iter(|| reference_decode::decode(black_box(input)))
+        });
+        group.bench_with_input(BenchmarkId::new("decoder", name), &encoded, |b, input| {
+            b.iter(|| decoder.decode(black_box(input)).map(str::len))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+//! Inserting into a full [`StrikeStore`], which has to evict a strike for
+//! every one it takes, at the sizes a busy summer evening produces.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use popuppp::blitzortung::LightningStrike;
+use popuppp::store::{Retention, StrikeStore};
+use std::hint::black_box;
+
+const FIXTURES: &str = include_str!("../fixtures/strikes.jsonl");
+
+fn strikes() -> Vec<LightningStrike> {
+    FIXTURES
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let templates = strikes();
+    let mut group = c.benchmark_group("store");
+    group.throughput(Throughput::Elements(1));
+    for size in [1_000, 100_000, 500_000] {
+        let mut store = StrikeStore::new(Retention {
+            max_count: size,
+            max_age: None,
+        });
+        let mut time = 0u64;
+        let mut next = || {
+            let mut strike = templates[(time / 1_000_000) as usize % templates.len()].clone();
+            time += 1_000_000;
+            strike.time = time;
+            strike
+        };
+        for _ in 0..size {
+            store.insert(next());
+        }
+
+        group.bench_function(BenchmarkId::new("insert_full", size), |b| {
+            b.iter(|| store.insert(black_box(next())))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert);
+criterion_main!(benches);
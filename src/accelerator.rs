@@ -0,0 +1,252 @@
+//! Keyboard shortcuts written the way menus show them, e.g. `cmd+shift+k`
+//! or `ctrl+alt+space`, and the macOS keycodes and modifier flags they
+//! stand for.
+
+use std::fmt;
+use std::str::FromStr;
+
+use egui::Key;
+use serde::Deserialize;
+
+/// Modifier flags, as both `NSEvent` and `CGEvent` have them.
+pub const SHIFT: u64 = 0x20000;
+pub const CONTROL: u64 = 0x40000;
+pub const OPTION: u64 = 0x80000;
+pub const COMMAND: u64 = 0x100000;
+/// The modifiers an accelerator can ask for; caps lock, fn and the like
+/// are not compared.
+pub const MODIFIERS: u64 = SHIFT | CONTROL | OPTION | COMMAND;
+
+/// macOS virtual keycodes and the egui keys they are.
+const KEYCODES: &[(u16, Key)] = &[
+    // Letters
+    (0, Key::A),
+    (1, Key::S),
+    (2, Key::D),
+    (3, Key::F),
+    (4, Key::H),
+    (5, Key::G),
+    (6, Key::Z),
+    (7, Key::X),
+    (8, Key::C),
+    (9, Key::V),
+    (11, Key::B),
+    (12, Key::Q),
+    (13, Key::W),
+    (14, Key::E),
+    (15, Key::R),
+    (17, Key::T),
+    (16, Key::Y),
+    (32, Key::U),
+    (34, Key::I),
+    (31, Key::O),
+    (35, Key::P),
+    (37, Key::L),
+    (38, Key::J),
+    (40, Key::K),
+    (45, Key::N),
+    (46, Key::M),
+    // Numbers
+    (18, Key::Num1),
+    (19, Key::Num2),
+    (20, Key::Num3),
+    (21, Key::Num4),
+    (23, Key::Num5),
+    (22, Key::Num6),
+    (26, Key::Num7),
+    (28, Key::Num8),
+    (25, Key::Num9),
+    (29, Key::Num0),
+    // Special keys
+    (36, Key::Enter),
+    (48, Key::Tab),
+    (49, Key::Space),
+    (51, Key::Backspace),
+    (53, Key::Escape),
+    // Arrow keys
+    (123, Key::ArrowLeft),
+    (124, Key::ArrowRight),
+    (125, Key::ArrowDown),
+    (126, Key::ArrowUp),
+];
+
+/// The egui key for a macOS keycode.
+pub fn key_for_keycode(keycode: u16) -> Option<Key> {
+    KEYCODES
+        .iter()
+        .find(|(code, _)| *code == keycode)
+        .map(|(_, key)| *key)
+}
+
+/// The macOS keycode for an egui key.
+pub fn keycode_for_key(key: Key) -> Option<u16> {
+    KEYCODES
+        .iter()
+        .find(|(_, k)| *k == key)
+        .map(|(code, _)| *code)
+}
+
+/// A key and the exact modifiers that go with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Accelerator {
+    pub keycode: u16,
+    /// [`MODIFIERS`] flags.
+    pub modifiers: u64,
+}
+
+impl Accelerator {
+    /// Whether a key press is this accelerator: the same key, with these
+    /// modifiers and no others held.
+    pub fn matches(&self, keycode: u16, flags: u64) -> bool {
+        keycode == self.keycode && flags & MODIFIERS == self.modifiers
+    }
+}
+
+impl FromStr for Accelerator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or_default();
+        let mut modifiers = 0;
+        for part in parts {
+            let flag = match part.to_ascii_lowercase().as_str() {
+                "cmd" | "command" | "super" => COMMAND,
+                "shift" => SHIFT,
+                "alt" | "opt" | "option" => OPTION,
+                "ctrl" | "control" => CONTROL,
+                "" => return Err(format!("'{}' has an empty part", s)),
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a modifier, use cmd, shift, alt or ctrl",
+                        part
+                    ));
+                }
+            };
+            if modifiers & flag != 0 {
+                return Err(format!("'{}' is in '{}' twice", part, s));
+            }
+            modifiers |= flag;
+        }
+        if key.is_empty() {
+            return Err(format!("'{}' has no key", s));
+        }
+        let keycode = parse_key(key).ok_or_else(|| format!("'{}' is not a key", key))?;
+        // Without one the hotkey would swallow the key everywhere
+        if modifiers == 0 {
+            return Err(format!(
+                "'{}' needs a modifier, e.g. cmd+shift+{}",
+                s,
+                key.to_ascii_lowercase()
+            ));
+        }
+        Ok(Self { keycode, modifiers })
+    }
+}
+
+/// A key by its egui name, in any case, e.g. `k`, `space` or `esc`.
+fn parse_key(name: &str) -> Option<u16> {
+    KEYCODES
+        .iter()
+        .find(|(_, key)| key.name().eq_ignore_ascii_case(name))
+        .map(|(code, _)| *code)
+        .or_else(|| {
+            // The aliases egui knows, like Esc and Return, are capitalized
+            let mut chars = name.chars();
+            let first = chars.next()?.to_ascii_uppercase();
+            let name = first.to_string() + &chars.as_str().to_ascii_lowercase();
+            keycode_for_key(Key::from_name(&name)?)
+        })
+}
+
+impl TryFrom<String> for Accelerator {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Accelerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (flag, name) in [
+            (COMMAND, "Cmd"),
+            (CONTROL, "Ctrl"),
+            (OPTION, "Alt"),
+            (SHIFT, "Shift"),
+        ] {
+            if self.modifiers & flag != 0 {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match key_for_keycode(self.keycode) {
+            Some(key) => f.write_str(key.name()),
+            None => write!(f, "keycode {}", self.keycode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accelerators_parse_in_any_case_and_order() {
+        let hotkey: Accelerator = "cmd+shift+k".parse().unwrap();
+        assert_eq!(
+            hotkey,
+            Accelerator {
+                keycode: 40,
+                modifiers: COMMAND | SHIFT,
+            }
+        );
+        assert_eq!(" Shift + CMD + K ".parse(), Ok(hotkey));
+        assert_eq!(hotkey.to_string(), "Cmd+Shift+K");
+
+        let hotkey: Accelerator = "ctrl+alt+space".parse().unwrap();
+        assert_eq!((hotkey.keycode, hotkey.modifiers), (49, CONTROL | OPTION));
+        assert_eq!(hotkey.to_string(), "Ctrl+Alt+Space");
+        assert_eq!(hotkey.to_string().parse(), Ok(hotkey));
+
+        assert_eq!("option+esc".parse::<Accelerator>().unwrap().keycode, 53);
+        assert_eq!("cmd+return".parse::<Accelerator>().unwrap().keycode, 36);
+        assert_eq!("control+left".parse::<Accelerator>().unwrap().keycode, 123);
+        assert_eq!("command+1".parse::<Accelerator>().unwrap().keycode, 18);
+
+        // Every key in the table parses back from its name
+        for (keycode, key) in KEYCODES {
+            let hotkey: Accelerator = format!("cmd+{}", key.name()).parse().unwrap();
+            assert_eq!(hotkey.keycode, *keycode);
+            assert_eq!(key_for_keycode(*keycode), Some(*key));
+        }
+    }
+
+    #[test]
+    fn bad_accelerators_say_what_is_wrong() {
+        for (accelerator, expected) in [
+            ("", "has no key"),
+            ("cmd+shift", "'shift' is not a key"),
+            ("cmd+shift+", "has no key"),
+            ("cmd++k", "empty part"),
+            ("hyper+k", "'hyper' is not a modifier"),
+            ("cmd+cmd+k", "'cmd' is in 'cmd+cmd+k' twice"),
+            ("cmd+f13", "'f13' is not a key"),
+            ("k", "needs a modifier, e.g. cmd+shift+k"),
+        ] {
+            let error = accelerator.parse::<Accelerator>().unwrap_err();
+            assert!(error.contains(expected), "{accelerator}: {error}");
+        }
+    }
+
+    #[test]
+    fn modifiers_have_to_match_exactly() {
+        let hotkey: Accelerator = "cmd+shift+k".parse().unwrap();
+        assert!(hotkey.matches(40, COMMAND | SHIFT));
+        // Caps lock (0x10000) and the like do not count
+        assert!(hotkey.matches(40, COMMAND | SHIFT | 0x10000));
+        assert!(!hotkey.matches(40, COMMAND | SHIFT | OPTION));
+        assert!(!hotkey.matches(40, COMMAND));
+        assert!(!hotkey.matches(38, COMMAND | SHIFT));
+    }
+}
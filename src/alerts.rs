@@ -0,0 +1,274 @@
+//! Proximity alerts: rules about strikes near home, checked as strikes
+//! arrive, and an all-clear once it has been quiet for a while.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+use crate::blitzortung::LightningStrike;
+use crate::geo::Location;
+
+/// "`min_strikes` strikes within `radius_km` of home in `window`".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlertRule {
+    pub radius_km: f64,
+    pub min_strikes: usize,
+    /// Ignored when a single strike is enough.
+    pub window: Duration,
+}
+
+impl AlertRule {
+    /// Any strike within `radius_km`.
+    pub fn any_within(radius_km: f64) -> Self {
+        Self {
+            radius_km,
+            min_strikes: 1,
+            window: Duration::ZERO,
+        }
+    }
+
+    /// `min_strikes` strikes within `radius_km` in `window`.
+    pub fn strikes_within(min_strikes: usize, radius_km: f64, window: Duration) -> Self {
+        Self {
+            radius_km,
+            min_strikes,
+            window,
+        }
+    }
+}
+
+impl std::fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min_strikes <= 1 {
+            write!(f, "any strike within {:.0} km", self.radius_km)
+        } else {
+            write!(
+                f,
+                "{} strikes within {:.0} km in {} min",
+                self.min_strikes,
+                self.radius_km,
+                self.window.as_secs() / 60
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertSettings {
+    pub rules: Vec<AlertRule>,
+    /// Call the all-clear after this long without a strike inside any rule's
+    /// radius.
+    pub all_clear_after: Duration,
+    /// Bring the popup up when an alert goes off.
+    pub pop_up: bool,
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                AlertRule::any_within(20.0),
+                AlertRule::strikes_within(5, 50.0, Duration::from_secs(10 * 60)),
+            ],
+            all_clear_after: Duration::from_secs(30 * 60),
+            pop_up: true,
+        }
+    }
+}
+
+/// An alert that went off and has not been cleared yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// The rule that set it off.
+    pub rule: AlertRule,
+    pub raised_at: SystemTime,
+    /// The most recent strike inside the radius of any rule.
+    pub last_strike: SystemTime,
+    /// Closest strike so far, and which way it is from home.
+    pub nearest_km: f64,
+    pub bearing: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertEvent {
+    Raised(Alert),
+    AllClear,
+}
+
+/// Checks strikes against [`AlertSettings`] and keeps track of the alert
+/// in progress.
+#[derive(Debug, Clone, Default)]
+pub struct AlertMonitor {
+    pub settings: AlertSettings,
+    /// Time and distance of recent strikes inside the largest radius.
+    nearby: VecDeque<(SystemTime, f64)>,
+    active: Option<Alert>,
+}
+
+impl AlertMonitor {
+    pub fn new(settings: AlertSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// The alert in progress, if any.
+    pub fn active(&self) -> Option<&Alert> {
+        self.active.as_ref()
+    }
+
+    /// Forget every strike seen so far, e.g. after home moved.
+    pub fn reset(&mut self) {
+        self.nearby.clear();
+        self.active = None;
+    }
+
+    /// Check a strike that just arrived. Returns [`AlertEvent::Raised`] when
+    /// it sets off an alert; while one is in progress, strikes only update it.
+    pub fn check(&mut self, home: Location, strike: &LightningStrike) -> Option<AlertEvent> {
+        let there = Location::of(strike);
+        let km = home.distance_km(&there);
+        let max_radius = self
+            .settings
+            .rules
+            .iter()
+            .map(|rule| rule.radius_km)
+            .fold(0.0, f64::max);
+        if km > max_radius {
+            return None;
+        }
+
+        let time = strike.timestamp();
+        self.nearby.push_back((time, km));
+        let longest = self
+            .settings
+            .rules
+            .iter()
+            .map(|rule| rule.window)
+            .max()
+            .unwrap_or_default();
+        if let Some(cutoff) = time.checked_sub(longest) {
+            while self.nearby.front().is_some_and(|&(t, _)| t < cutoff) {
+                self.nearby.pop_front();
+            }
+        }
+
+        if let Some(alert) = &mut self.active {
+            alert.last_strike = alert.last_strike.max(time);
+            if km < alert.nearest_km {
+                alert.nearest_km = km;
+                alert.bearing = home.bearing_to(&there);
+            }
+            return None;
+        }
+
+        let rule = *self.settings.rules.iter().find(|rule| {
+            let since = time.checked_sub(rule.window).unwrap_or(time);
+            let count = self
+                .nearby
+                .iter()
+                .filter(|&&(t, d)| d <= rule.radius_km && t >= since && t <= time)
+                .count();
+            km <= rule.radius_km && count >= rule.min_strikes.max(1)
+        })?;
+        let alert = Alert {
+            rule,
+            raised_at: time,
+            last_strike: time,
+            nearest_km: km,
+            bearing: home.bearing_to(&there),
+        };
+        self.active = Some(alert.clone());
+        Some(AlertEvent::Raised(alert))
+    }
+
+    /// Returns [`AlertEvent::AllClear`] once nothing has come near for
+    /// `all_clear_after` as of `now`, ending the alert.
+    pub fn tick(&mut self, now: SystemTime) -> Option<AlertEvent> {
+        let alert = self.active.as_ref()?;
+        let quiet = now.duration_since(alert.last_strike).unwrap_or_default();
+        if quiet < self.settings.all_clear_after {
+            return None;
+        }
+        self.active = None;
+        Some(AlertEvent::AllClear)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_at;
+    use std::time::UNIX_EPOCH;
+
+    const HOME: Location = Location { lat: 0.0, lon: 0.0 };
+
+    /// A strike `secs` into the test, `km` north of home.
+    fn north(secs: u64, km: f64) -> LightningStrike {
+        strike_at(secs, HOME.destination(0.0, km).lat, 0.0)
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn a_single_close_strike_raises_an_alert() {
+        let mut monitor = AlertMonitor::default();
+        assert_eq!(monitor.check(HOME, &north(0, 100.0)), None);
+        assert_eq!(monitor.check(HOME, &north(1, 30.0)), None);
+
+        let Some(AlertEvent::Raised(alert)) = monitor.check(HOME, &north(2, 15.0)) else {
+            panic!("no alert");
+        };
+        assert_eq!(alert.rule, AlertRule::any_within(20.0));
+        assert!((alert.nearest_km - 15.0).abs() < 0.01);
+        assert_eq!(alert.bearing, 0.0);
+
+        // Further strikes update the alert instead of raising new ones
+        assert_eq!(monitor.check(HOME, &north(3, 5.0)), None);
+        let active = monitor.active().unwrap();
+        assert!((active.nearest_km - 5.0).abs() < 0.01);
+        assert_eq!(active.last_strike, at(3));
+    }
+
+    #[test]
+    fn several_strikes_in_a_window_raise_an_alert() {
+        let mut monitor = AlertMonitor::new(AlertSettings {
+            rules: vec![AlertRule::strikes_within(3, 50.0, Duration::from_secs(60))],
+            ..Default::default()
+        });
+        assert_eq!(monitor.check(HOME, &north(0, 40.0)), None);
+        assert_eq!(monitor.check(HOME, &north(30, 40.0)), None);
+        // The first one is out of the window by now
+        assert_eq!(monitor.check(HOME, &north(70, 40.0)), None);
+        assert!(matches!(
+            monitor.check(HOME, &north(80, 45.0)),
+            Some(AlertEvent::Raised(_))
+        ));
+        assert_eq!(
+            monitor.active().unwrap().rule.to_string(),
+            "3 strikes within 50 km in 1 min"
+        );
+    }
+
+    #[test]
+    fn quiet_spells_sound_the_all_clear() {
+        let mut monitor = AlertMonitor::default();
+        assert_eq!(monitor.tick(at(0)), None);
+        monitor.check(HOME, &north(100, 10.0));
+        // Strikes further out than every rule do not keep the alert going
+        monitor.check(HOME, &north(1000, 500.0));
+
+        assert_eq!(monitor.tick(at(100 + 29 * 60)), None);
+        assert_eq!(monitor.tick(at(100 + 30 * 60)), Some(AlertEvent::AllClear));
+        assert_eq!(monitor.active(), None);
+        assert_eq!(monitor.tick(at(100 + 31 * 60)), None);
+
+        // And the next close strike starts over
+        assert!(matches!(
+            monitor.check(HOME, &north(4000, 10.0)),
+            Some(AlertEvent::Raised(_))
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+//! Areas to keep strikes from, applied as strikes arrive.
+//!
+//! The Blitzortung handshake (`{"a":111}`) has no way to subscribe to part
+//! of the world, every server sends every strike, so the filtering happens
+//! on our side.
+
+use std::time::SystemTime;
+
+use crate::blitzortung::LightningStrike;
+use crate::geo::Location;
+
+/// A latitude/longitude box. `west > east` describes a box that crosses
+/// the antimeridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl BoundingBox {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let in_lon = if self.west <= self.east {
+            (self.west..=self.east).contains(&lon)
+        } else {
+            lon >= self.west || lon <= self.east
+        };
+        in_lon && (self.south..=self.north).contains(&lat)
+    }
+}
+
+impl std::fmt::Display for BoundingBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.west, self.south, self.east, self.north
+        )
+    }
+}
+
+impl std::str::FromStr for BoundingBox {
+    type Err = String;
+
+    /// Parses `west,south,east,north`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<f64> = s
+            .split(',')
+            .map(|p| p.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid bounding box '{}'", s))?;
+        let [west, south, east, north] = parts[..] else {
+            return Err(format!("bounding box '{}' needs W,S,E,N", s));
+        };
+        if !(-90.0..=90.0).contains(&south) || !(-90.0..=90.0).contains(&north) || south > north {
+            return Err(format!("invalid latitudes in bounding box '{}'", s));
+        }
+        if !(-180.0..=180.0).contains(&west) || !(-180.0..=180.0).contains(&east) {
+            return Err(format!("invalid longitudes in bounding box '{}'", s));
+        }
+        Ok(Self {
+            west,
+            south,
+            east,
+            north,
+        })
+    }
+}
+
+/// Part of the world strikes are wanted from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Area {
+    Box(BoundingBox),
+    Circle {
+        center: Location,
+        radius_km: f64,
+    },
+    /// Vertices in order; the last one connects back to the first.
+    Polygon(Vec<Location>),
+}
+
+impl Area {
+    pub fn contains(&self, location: Location) -> bool {
+        match self {
+            Area::Box(bbox) => bbox.contains(location.lat, location.lon),
+            Area::Circle { center, radius_km } => center.distance_km(&location) <= *radius_km,
+            Area::Polygon(vertices) => polygon_contains(vertices, location),
+        }
+    }
+
+    /// The boundary, as a closed ring of points close enough together to
+    /// draw with straight lines.
+    pub fn outline(&self) -> Vec<Location> {
+        match self {
+            Area::Box(bbox) => {
+                // Parallels and meridians are straight in both projections,
+                // the edges only need enough points to wrap around the
+                // antimeridian where the box does
+                let width = (bbox.east - bbox.west).rem_euclid(360.0);
+                let along = |lat: f64, reverse: bool| {
+                    (0..=8).map(move |i| {
+                        let i = if reverse { 8 - i } else { i };
+                        let lon = bbox.west + width * i as f64 / 8.0;
+                        Location::new(lat, (lon + 540.0).rem_euclid(360.0) - 180.0)
+                    })
+                };
+                along(bbox.south, false)
+                    .chain(along(bbox.north, true))
+                    .chain(along(bbox.south, false).take(1))
+                    .collect()
+            }
+            Area::Circle { center, radius_km } => (0..=72)
+                .map(|step| center.destination(step as f64 * 5.0, *radius_km))
+                .collect(),
+            Area::Polygon(vertices) => vertices.iter().chain(vertices.first()).copied().collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Area {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Area::Box(bbox) => write!(f, "box {}", bbox),
+            Area::Circle { center, radius_km } => write!(f, "circle {} {}", center, radius_km),
+            Area::Polygon(vertices) => {
+                write!(f, "polygon")?;
+                for vertex in vertices {
+                    write!(f, " {}", vertex)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Area {
+    type Err = String;
+
+    /// Parses `box W,S,E,N`, `circle LAT,LON KM` or
+    /// `polygon LAT,LON LAT,LON LAT,LON ...`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let kind = words.next().unwrap_or_default();
+        let rest: Vec<_> = words.collect();
+        match (kind, &rest[..]) {
+            ("box", [bbox]) => Ok(Area::Box(bbox.parse()?)),
+            ("circle", [center, radius]) => {
+                let radius_km = radius
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|km| *km > 0.0)
+                    .ok_or_else(|| format!("invalid radius '{}'", radius))?;
+                Ok(Area::Circle {
+                    center: center.parse()?,
+                    radius_km,
+                })
+            }
+            ("polygon", vertices) if vertices.len() >= 3 => Ok(Area::Polygon(
+                vertices
+                    .iter()
+                    .map(|vertex| vertex.parse())
+                    .collect::<Result<_, _>>()?,
+            )),
+            ("polygon", _) => Err(format!("polygon '{}' needs at least 3 points", s)),
+            _ => Err(format!(
+                "invalid area '{}', expected box W,S,E,N, circle LAT,LON KM or polygon LAT,LON ...",
+                s
+            )),
+        }
+    }
+}
+
+/// Which strikes to keep: those in any of the areas, or all of them when
+/// off or there are none.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AreaFilter {
+    pub enabled: bool,
+    pub areas: Vec<Area>,
+}
+
+impl AreaFilter {
+    pub fn matches(&self, strike: &LightningStrike) -> bool {
+        !self.enabled
+            || self.areas.is_empty()
+            || self
+                .areas
+                .iter()
+                .any(|area| area.contains(Location::of(strike)))
+    }
+}
+
+/// Even-odd rule, with the longitudes unwrapped so edges across the
+/// antimeridian go the short way round.
+fn polygon_contains(vertices: &[Location], location: Location) -> bool {
+    let Some(first) = vertices.first() else {
+        return false;
+    };
+    let mut lon = first.lon;
+    let points: Vec<_> = vertices
+        .iter()
+        .map(|vertex| {
+            lon += (vertex.lon - lon + 540.0).rem_euclid(360.0) - 180.0;
+            (vertex.lat, lon)
+        })
+        .collect();
+
+    [location.lon, location.lon + 360.0, location.lon - 360.0]
+        .into_iter()
+        .any(|x| {
+            let mut inside = false;
+            for (i, &(lat_i, lon_i)) in points.iter().enumerate() {
+                let (lat_j, lon_j) = points[(i + points.len() - 1) % points.len()];
+                if (lat_i > location.lat) != (lat_j > location.lat)
+                    && x < (lon_j - lon_i) * (location.lat - lat_i) / (lat_j - lat_i) + lon_i
+                {
+                    inside = !inside;
+                }
+            }
+            inside
+        })
+}
+
+/// Which strikes to print, export or look up: those in a box, in any of
+/// some areas and within a time range.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StrikeFilter {
+    pub bbox: Option<BoundingBox>,
+    /// Strikes in any of these pass, when there are any.
+    pub areas: Vec<Area>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl StrikeFilter {
+    pub fn matches(&self, strike: &LightningStrike) -> bool {
+        let time = strike.timestamp();
+        self.bbox
+            .is_none_or(|bbox| bbox.contains(strike.lat, strike.lon))
+            && (self.areas.is_empty()
+                || self
+                    .areas
+                    .iter()
+                    .any(|area| area.contains(Location::of(strike))))
+            && self.since.is_none_or(|since| time >= since)
+            && self.until.is_none_or(|until| time < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_at;
+
+    #[test]
+    fn areas_contain_what_is_inside_them() {
+        let circle: Area = "circle 45,9 50".parse().unwrap();
+        assert!(circle.contains(Location::new(45.4, 9.0)));
+        assert!(!circle.contains(Location::new(45.5, 9.0)));
+
+        // A triangle over the Alps, and a square across the antimeridian
+        let triangle: Area = "polygon 45,6 45,14 48,10".parse().unwrap();
+        assert!(triangle.contains(Location::new(46.0, 10.0)));
+        assert!(!triangle.contains(Location::new(47.5, 7.0)));
+        let pacific: Area = "polygon -10,170 -10,-170 10,-170 10,170".parse().unwrap();
+        assert!(pacific.contains(Location::new(0.0, 179.5)));
+        assert!(pacific.contains(Location::new(0.0, -175.0)));
+        assert!(!pacific.contains(Location::new(0.0, 0.0)));
+        assert!(!pacific.contains(Location::new(0.0, 160.0)));
+
+        let bbox: Area = "box 170,-10,-170,10".parse().unwrap();
+        assert!(bbox.contains(Location::new(0.0, -179.0)));
+        let outline = bbox.outline();
+        assert_eq!(outline.first(), outline.last());
+        assert!(outline.iter().all(|point| point.lon.abs() >= 170.0));
+    }
+
+    #[test]
+    fn areas_are_written_as_they_are_parsed() {
+        for text in [
+            "box -10,35,30,60",
+            "circle 45.4600,9.1900 25.5",
+            "polygon 45.0000,6.0000 45.0000,14.0000 48.0000,10.0000",
+        ] {
+            assert_eq!(text.parse::<Area>().unwrap().to_string(), text);
+        }
+        for bad in [
+            "",
+            "square 1,2,3,4",
+            "box 1,2,3",
+            "circle 45,9",
+            "circle 45,9 -1",
+            "polygon 45,6 45,14",
+            "polygon 45,6 45,14 nowhere",
+        ] {
+            assert!(bad.parse::<Area>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn filters_keep_strikes_in_any_area() {
+        let mut filter = AreaFilter {
+            enabled: true,
+            areas: Vec::new(),
+        };
+        let milan = strike_at(0, 45.46, 9.19);
+        let sydney = strike_at(0, -33.87, 151.21);
+        assert!(filter.matches(&milan) && filter.matches(&sydney));
+
+        filter.areas.push("box -10,35,30,60".parse().unwrap());
+        filter.areas.push("circle 51.5,-0.13 100".parse().unwrap());
+        assert!(filter.matches(&milan) && !filter.matches(&sydney));
+        filter.enabled = false;
+        assert!(filter.matches(&sydney));
+    }
+}
@@ -1,9 +1,11 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 // Blitzortung WebSocket servers
-pub const BLITZSERVERS: [&'static str; 3] = [
+pub const BLITZSERVERS: [&str; 3] = [
     "wss://ws1.blitzortung.org",
     "wss://ws7.blitzortung.org",
     "wss://ws8.blitzortung.org",
@@ -13,9 +15,9 @@ pub const BLITZSERVERS: [&'static str; 3] = [
 pub const BLITZ_HANDSHAKE: &[u8] = b"{\"a\":111}";
 
 /// Lightning strike data structure matching Blitzortung's format
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct LightningStrike {
-    /// Timestamp in microseconds since epoch
+    /// Timestamp in nanoseconds since epoch
     pub time: u64,
     /// Latitude
     pub lat: f64,
@@ -41,7 +43,14 @@ pub struct LightningStrike {
     pub latc: u32,
 }
 
-#[derive(Debug, Deserialize)]
+impl LightningStrike {
+    /// When the strike happened.
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(self.time)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct SignalData {
     /// Station ID (?)
     pub sta: u32,
@@ -53,42 +62,390 @@ pub struct SignalData {
     pub status: u32,
 }
 
+/// Why a Blitzortung message could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// There was nothing to decode.
+    Empty,
+    /// The character at `position` is neither a literal nor a dictionary code
+    /// defined so far.
+    InvalidCode { position: usize, code: u32 },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty message"),
+            DecodeError::InvalidCode { position, code } => {
+                write!(f, "invalid code {} at position {}", code, position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 /// LZW decoder for Blitzortung compressed messages
-pub fn decode(input: &str) -> String {
-    if input.is_empty() {
-        return String::new();
+pub fn decode(input: &str) -> Result<String, DecodeError> {
+    let mut out = String::with_capacity(input.len() * 2);
+    Decoder::new().decode_into(input, &mut out)?;
+    Ok(out)
+}
+
+/// Marks a phrase reference as a single literal character rather than a
+/// dictionary code.
+const LITERAL: u32 = 1 << 31;
+
+/// A dictionary entry: the phrase `prefix` followed by `last`.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    prefix: u32,
+    last: char,
+    first: char,
+}
+
+/// Reusable LZW decoder.
+///
+/// Phrases are stored as a prefix reference plus their last character, so
+/// the dictionary grows by one small entry per input character and nothing
+/// is cloned. Keep one `Decoder` around and feed it every message: once its
+/// buffers have grown to the size of the largest message, decoding does not
+/// allocate.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Entry for code `256 + i` at index `i`.
+    table: Vec<Entry>,
+    /// A phrase being expanded, last character first.
+    scratch: Vec<char>,
+    /// Output of [`Decoder::decode`].
+    out: String,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    let data: Vec<char> = input.chars().collect();
-    let mut dictionary: HashMap<u32, Vec<char>> = HashMap::new();
-    let mut result = String::with_capacity(input.len() * 2);
+    /// Decode `input`, returning a view into the decoder's own buffer.
+    pub fn decode(&mut self, input: &str) -> Result<&str, DecodeError> {
+        let mut out = std::mem::take(&mut self.out);
+        out.clear();
+        let result = self.decode_into(input, &mut out);
+        self.out = out;
+        result.map(|()| self.out.as_str())
+    }
 
-    let curr_char = data[0];
-    let mut old_phrase = vec![curr_char];
-    result.push(curr_char);
+    /// Decode `input` and append the result to `out`. On error `out` is left
+    /// as it was.
+    ///
+    /// Every code is one whole character, so a message cut short still
+    /// decodes, to the start of the original; whether that is a complete
+    /// strike is for the caller to judge.
+    pub fn decode_into(&mut self, input: &str, out: &mut String) -> Result<(), DecodeError> {
+        let start = out.len();
+        let result = self.decode_phrases(input, out);
+        if result.is_err() {
+            out.truncate(start);
+        }
+        result
+    }
+
+    fn decode_phrases(&mut self, input: &str, out: &mut String) -> Result<(), DecodeError> {
+        self.table.clear();
+
+        let mut chars = input.chars();
+        let Some(first) = chars.next() else {
+            return Err(DecodeError::Empty);
+        };
+        if first as u32 >= 256 {
+            return Err(DecodeError::InvalidCode {
+                position: 0,
+                code: first as u32,
+            });
+        }
+        out.push(first);
+        let mut prev = first as u32 | LITERAL;
+
+        for (position, c) in chars.enumerate() {
+            let curr_code = c as u32;
+            let next_code = 256 + self.table.len() as u32;
+
+            let (phrase, first) = if curr_code < 256 {
+                out.push(c);
+                (curr_code | LITERAL, c)
+            } else if curr_code < next_code {
+                self.write_phrase(curr_code, out);
+                (curr_code, self.first_char(curr_code))
+            } else if curr_code == next_code {
+                // KwKwK: the code being defined right now, i.e. the previous
+                // phrase followed by its own first character.
+                let first = self.first_char(prev);
+                self.write_phrase(prev, out);
+                out.push(first);
+                (next_code, first)
+            } else {
+                return Err(DecodeError::InvalidCode {
+                    position: position + 1,
+                    code: curr_code,
+                });
+            };
+
+            self.table.push(Entry {
+                prefix: prev,
+                last: first,
+                first: self.first_char(prev),
+            });
+            prev = phrase;
+        }
+
+        Ok(())
+    }
+
+    fn first_char(&self, phrase: u32) -> char {
+        if phrase & LITERAL != 0 {
+            literal(phrase)
+        } else {
+            self.table[(phrase - 256) as usize].first
+        }
+    }
+
+    fn write_phrase(&mut self, mut phrase: u32, out: &mut String) {
+        self.scratch.clear();
+        while phrase & LITERAL == 0 {
+            let entry = self.table[(phrase - 256) as usize];
+            self.scratch.push(entry.last);
+            phrase = entry.prefix;
+        }
+        self.scratch.push(literal(phrase));
+        out.extend(self.scratch.iter().rev());
+    }
+}
+
+fn literal(phrase: u32) -> char {
+    char::from_u32(phrase & !LITERAL).expect("literals are chars")
+}
+
+/// Dictionary codes stop short of the UTF-16 surrogates, which cannot be
+/// represented as `char`s.
+const MAX_CODE: u32 = 0xD800;
+
+/// LZW encoder producing the format [`decode`] reads: code points below 256
+/// are literals and everything from 256 up references the dictionary.
+///
+/// Characters from U+0100 up cannot be literals in this format, so they are
+/// written as JSON `\uXXXX` escapes first. Text that only uses Latin-1 comes
+/// back from [`decode`] unchanged; other JSON comes back as an equivalent
+/// document.
+pub fn encode(input: &str) -> String {
+    let input = escape_non_latin1(input);
+    let mut chars = input.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+
+    // (prefix code, next char) -> code of the extended phrase
+    let mut dictionary: HashMap<(u32, char), u32> = HashMap::new();
+    let mut output = String::with_capacity(input.len() / 2);
+    let mut phrase = first as u32;
     let mut code: u32 = 256;
 
-    for i in 1..data.len() {
-        let curr_code = data[i] as u32;
+    for c in chars {
+        if let Some(&extended) = dictionary.get(&(phrase, c)) {
+            phrase = extended;
+            continue;
+        }
 
-        let phrase = if curr_code < 256 {
-            vec![data[i]]
+        output.push(char::from_u32(phrase).expect("codes stay below the surrogate range"));
+        if code < MAX_CODE {
+            dictionary.insert((phrase, c), code);
+            code += 1;
+        }
+        phrase = c as u32;
+    }
+    output.push(char::from_u32(phrase).expect("codes stay below the surrogate range"));
+
+    output
+}
+
+fn escape_non_latin1(input: &str) -> Cow<'_, str> {
+    if input.chars().all(|c| (c as u32) < 256) {
+        return Cow::Borrowed(input);
+    }
+
+    let mut escaped = String::with_capacity(input.len() + 16);
+    let mut units = [0u16; 2];
+    for c in input.chars() {
+        if (c as u32) < 256 {
+            escaped.push(c);
         } else {
-            dictionary.get(&curr_code).cloned().unwrap_or_else(|| {
-                let mut new_phrase = old_phrase.clone();
-                new_phrase.push(old_phrase[0]);
-                new_phrase
+            for unit in c.encode_utf16(&mut units) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference_decode as reference;
+    use proptest::prelude::*;
+    use serde_json::Value;
+
+    /// Arbitrary JSON documents with arbitrary Unicode in keys and strings.
+    /// Floats are left out as they do not survive a text round trip exactly.
+    fn json_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::Bool),
+            any::<i64>().prop_map(Value::from),
+            any::<String>().prop_map(Value::String),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+                prop::collection::hash_map(any::<String>(), inner, 0..8)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    #[test]
+    fn kwkwk_codes_round_trip() {
+        // "aa" gets code 256 and is emitted before the decoder has seen it.
+        assert_eq!(encode("aaa"), "a\u{100}");
+        assert_eq!(decode("a\u{100}").unwrap(), "aaa");
+        for n in 1..64 {
+            let s = "ab".repeat(n) + &"a".repeat(n);
+            assert_eq!(decode(&encode(&s)).unwrap(), s);
+        }
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        for line in include_str!("../fixtures/strikes.jsonl").lines() {
+            let encoded = encode(line);
+            assert!(encoded.chars().count() < line.chars().count());
+            assert_eq!(decode(&encoded).unwrap(), line);
+        }
+    }
+
+    #[test]
+    fn dictionary_stops_before_the_surrogates() {
+        // Enough distinct pairs to run the dictionary past U+D800
+        let mut rng = 0x2545_f491_u32;
+        let s: String = (0..200_000)
+            .map(|_| {
+                rng ^= rng << 13;
+                rng ^= rng >> 17;
+                rng ^= rng << 5;
+                char::from(b'a' + (rng % 26) as u8)
             })
-        };
+            .collect();
+        assert_eq!(decode(&encode(&s)).unwrap(), s);
+    }
 
-        result.extend(phrase.iter());
+    #[test]
+    fn matches_the_reference_decoder_on_fixtures() {
+        let mut decoder = Decoder::new();
+        for line in include_str!("../fixtures/strikes.jsonl").lines() {
+            let encoded = encode(line);
+            assert_eq!(
+                decoder.decode(&encoded).unwrap(),
+                reference::decode(&encoded)
+            );
+        }
+        let all = include_str!("../fixtures/strikes.jsonl").repeat(20);
+        let encoded = encode(&all);
+        assert_eq!(
+            decoder.decode(&encoded).unwrap(),
+            reference::decode(&encoded)
+        );
+    }
+
+    #[test]
+    fn errors_are_distinguished() {
+        assert_eq!(decode(""), Err(DecodeError::Empty));
+        // After "ab" only 256 is defined and 257 would be KwKwK; 258 is too far.
+        assert_eq!(
+            decode("ab\u{102}"),
+            Err(DecodeError::InvalidCode {
+                position: 2,
+                code: 258
+            })
+        );
+        assert_eq!(
+            decode("\u{100}a"),
+            Err(DecodeError::InvalidCode {
+                position: 0,
+                code: 256
+            })
+        );
 
-        let mut new_dict_entry = old_phrase.clone();
-        new_dict_entry.push(phrase[0]);
-        dictionary.insert(code, new_dict_entry);
-        code += 1;
-        old_phrase = phrase;
+        let line = include_str!("../fixtures/strikes.jsonl")
+            .lines()
+            .next()
+            .unwrap();
+        let encoded = encode(line);
+        let cut: String = encoded.chars().take(encoded.chars().count() / 2).collect();
+        let decoded = decode(&cut).unwrap();
+        assert!(decoded.len() < line.len() && line.starts_with(&decoded));
+        // Whatever the text is, JSON or not
+        assert_eq!(decode(r#"{"a":"#).unwrap(), r#"{"a":"#);
     }
 
-    result
+    #[test]
+    fn failed_decodes_leave_the_output_alone() {
+        let mut decoder = Decoder::new();
+        let mut out = String::from("kept");
+        assert!(decoder.decode_into("ab\u{105}", &mut out).is_err());
+        assert_eq!(out, "kept");
+        assert_eq!(decoder.decode("abc").unwrap(), "abc");
+    }
+
+    #[test]
+    fn non_latin1_is_escaped() {
+        let json = r#"{"name":"Zürich ⚡ 雷"}"#;
+        let decoded = decode(&encode(json)).unwrap();
+        assert_eq!(decoded, r#"{"name":"Zürich \u26a1 \u96f7"}"#);
+        assert_eq!(
+            serde_json::from_str::<Value>(&decoded).unwrap(),
+            serde_json::from_str::<Value>(json).unwrap()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn latin1_round_trips_exactly(s in "[\\x00-\\xff]*") {
+            if s.is_empty() {
+                prop_assert_eq!(decode(&encode(&s)), Err(DecodeError::Empty));
+            } else {
+                prop_assert_eq!(decode(&encode(&s)).unwrap(), s);
+            }
+        }
+
+        #[test]
+        fn repetitive_input_round_trips(s in "(ab|a|b|abc){1,64}") {
+            prop_assert_eq!(decode(&encode(&s)).unwrap(), s);
+        }
+
+        #[test]
+        fn matches_the_reference_decoder(s in "[a-c\\x{100}-\\x{120}]{0,200}") {
+            // Most of these contain out-of-range codes, which only the
+            // reference decoder accepts; everything else must match.
+            let mut decoder = Decoder::new();
+            if let Ok(decoded) = decoder.decode(&s) {
+                prop_assert_eq!(decoded, reference::decode(&s));
+            }
+        }
+
+        #[test]
+        fn unicode_json_round_trips(value in json_value()) {
+            let text = serde_json::to_string(&value).unwrap();
+            let decoded = decode(&encode(&text)).unwrap();
+            prop_assert!(decoded.chars().all(|c| (c as u32) < 256));
+            prop_assert_eq!(serde_json::from_str::<Value>(&decoded).unwrap(), value);
+        }
+    }
 }
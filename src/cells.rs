@@ -0,0 +1,633 @@
+//! Storm cells: recent strikes grouped by DBSCAN over a sliding window, and
+//! followed from one clustering to the next so each keeps its id and track.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+use crate::blitzortung::LightningStrike;
+use crate::geo::{EARTH_RADIUS_KM, Location};
+
+/// Cluster again once the feed has moved on this far.
+const RECLUSTER_EVERY: Duration = Duration::from_secs(10);
+
+/// How far back a cell's track goes.
+const TRACK_FOR: Duration = Duration::from_secs(30 * 60);
+
+/// A track needs this many centroids, this far apart, to fit a motion to.
+const MIN_TRACK_POINTS: usize = 3;
+const MIN_TRACK_SPAN: Duration = Duration::from_secs(2 * 60);
+
+/// Cells slower than this, or than their speed error, are not going
+/// anywhere in particular.
+const STATIONARY_KMH: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellSettings {
+    /// Strikes this close together are neighbours.
+    pub radius_km: f64,
+    /// A strike with this many neighbours, itself included, is the core of
+    /// a cell.
+    pub min_strikes: usize,
+    /// Only strikes this much older than the newest one are clustered.
+    pub window: Duration,
+}
+
+impl Default for CellSettings {
+    fn default() -> Self {
+        Self {
+            radius_km: 10.0,
+            min_strikes: 8,
+            window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StormCell {
+    /// Stays the same while the cell moves and changes shape.
+    pub id: u64,
+    pub centroid: Location,
+    /// Strikes in the window.
+    pub strikes: usize,
+    /// Strikes per minute over the time they span, at least a minute.
+    pub rate_per_min: f64,
+    /// Distance from the centroid to the furthest strike.
+    pub radius_km: f64,
+    pub first_seen: SystemTime,
+    pub last_strike: SystemTime,
+    /// Past centroids, oldest first, each at the mean time of the strikes
+    /// it was made of.
+    pub track: Vec<(SystemTime, Location)>,
+}
+
+/// Groups strikes into [`StormCell`]s as they arrive.
+#[derive(Debug, Clone, Default)]
+pub struct CellTracker {
+    settings: CellSettings,
+    /// Strikes in the window, oldest first.
+    points: VecDeque<(SystemTime, Location)>,
+    /// Largest first.
+    cells: Vec<StormCell>,
+    next_id: u64,
+    /// Newest strike at the last clustering, `None` when it is due.
+    clustered_at: Option<SystemTime>,
+}
+
+impl CellTracker {
+    pub fn new(settings: CellSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    pub fn settings(&self) -> CellSettings {
+        self.settings
+    }
+
+    /// Switch to `settings`, clustering again on the next update.
+    pub fn set_settings(&mut self, settings: CellSettings) {
+        self.settings = settings;
+        self.clustered_at = None;
+    }
+
+    /// The cells as of the last clustering, largest first.
+    pub fn cells(&self) -> &[StormCell] {
+        &self.cells
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.cells.clear();
+        self.clustered_at = None;
+    }
+
+    pub fn insert(&mut self, strike: &LightningStrike) {
+        let point = (strike.timestamp(), Location::of(strike));
+        if self.points.back().is_none_or(|&(last, _)| last <= point.0) {
+            self.points.push_back(point);
+        } else {
+            let at = self.points.partition_point(|&(time, _)| time <= point.0);
+            self.points.insert(at, point);
+        }
+        if let Some(cutoff) = self
+            .points
+            .back()
+            .and_then(|&(newest, _)| newest.checked_sub(self.settings.window))
+        {
+            self.drop_before(cutoff);
+        }
+    }
+
+    /// Drop strikes that fell out of the window as of `now`, for when the
+    /// feed goes quiet.
+    pub fn expire(&mut self, now: SystemTime) {
+        if let Some(cutoff) = now.checked_sub(self.settings.window)
+            && self.drop_before(cutoff) > 0
+        {
+            self.clustered_at = None;
+        }
+    }
+
+    /// Cluster again if the feed has moved on since the last time. Returns
+    /// whether it did.
+    pub fn update(&mut self) -> bool {
+        let newest = self.points.back().map(|&(time, _)| time);
+        let due = match (self.clustered_at, newest) {
+            (None, _) => true,
+            (Some(at), Some(newest)) => newest >= at + RECLUSTER_EVERY,
+            (Some(_), None) => false,
+        };
+        if due {
+            self.cluster();
+        }
+        due
+    }
+
+    /// Cluster the strikes in the window now, matching the clusters up with
+    /// the cells they were before.
+    pub fn cluster(&mut self) {
+        let locations: Vec<_> = self.points.iter().map(|&(_, location)| location).collect();
+        let mut clusters = dbscan(
+            &locations,
+            self.settings.radius_km,
+            self.settings.min_strikes,
+        );
+        clusters.sort_by_key(|members| std::cmp::Reverse(members.len()));
+
+        let mut previous = std::mem::take(&mut self.cells);
+        for members in clusters {
+            let centroid = centroid(members.iter().map(|&i| locations[i]));
+            let times: Vec<_> = members.iter().map(|&i| self.points[i].0).collect();
+            let (first, last) = (*times.iter().min().unwrap(), *times.iter().max().unwrap());
+            let span = last.duration_since(first).unwrap_or_default();
+            let radius_km = members
+                .iter()
+                .map(|&i| centroid.distance_km(&locations[i]))
+                .fold(0.0, f64::max);
+
+            // The nearest previous cell this one overlaps, if any
+            let matched = previous
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| (i, cell.centroid.distance_km(&centroid), cell.radius_km))
+                .filter(|&(_, km, radius)| km <= radius + self.settings.radius_km)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _, _)| i);
+            let mut cell = match matched {
+                Some(i) => previous.swap_remove(i),
+                None => {
+                    self.next_id += 1;
+                    StormCell {
+                        id: self.next_id,
+                        centroid,
+                        strikes: 0,
+                        rate_per_min: 0.0,
+                        radius_km: 0.0,
+                        first_seen: first,
+                        last_strike: last,
+                        track: Vec::new(),
+                    }
+                }
+            };
+            cell.centroid = centroid;
+            cell.strikes = members.len();
+            cell.rate_per_min = members.len() as f64 / span.as_secs_f64().max(60.0) * 60.0;
+            cell.radius_km = radius_km;
+            cell.last_strike = last;
+
+            let mean_time = first + span_mean(&times, first);
+            if cell.track.last().is_none_or(|&(time, _)| time < mean_time) {
+                cell.track.push((mean_time, centroid));
+            }
+            if let Some(cutoff) = mean_time.checked_sub(TRACK_FOR) {
+                cell.track.retain(|&(time, _)| time >= cutoff);
+            }
+            self.cells.push(cell);
+        }
+        self.clustered_at = Some(
+            self.points
+                .back()
+                .map_or(SystemTime::UNIX_EPOCH, |&(time, _)| time),
+        );
+    }
+
+    fn drop_before(&mut self, cutoff: SystemTime) -> usize {
+        let before = self.points.len();
+        while self.points.front().is_some_and(|&(time, _)| time < cutoff) {
+            self.points.pop_front();
+        }
+        before - self.points.len()
+    }
+}
+
+impl StormCell {
+    /// Velocity fitted to the track by least squares, once the track is
+    /// long enough to tell.
+    pub fn motion(&self) -> Option<Motion> {
+        let &(at, last) = self.track.last()?;
+        let first = self.track.first()?.0;
+        if self.track.len() < MIN_TRACK_POINTS
+            || at.duration_since(first).unwrap_or_default() < MIN_TRACK_SPAN
+        {
+            return None;
+        }
+
+        // Hours before the latest centroid, and km east and north of it
+        let points: Vec<_> = self
+            .track
+            .iter()
+            .map(|&(time, location)| {
+                let hours = -at.duration_since(time).unwrap_or_default().as_secs_f64() / 3600.0;
+                let (east, north) = offset_km(last, location);
+                (hours, east, north)
+            })
+            .collect();
+        let n = points.len() as f64;
+        let mean = |f: fn(&(f64, f64, f64)) -> f64| points.iter().map(f).sum::<f64>() / n;
+        let (t, x, y) = (mean(|p| p.0), mean(|p| p.1), mean(|p| p.2));
+        let stt: f64 = points.iter().map(|p| (p.0 - t).powi(2)).sum();
+        let east_kmh = points.iter().map(|p| (p.0 - t) * (p.1 - x)).sum::<f64>() / stt;
+        let north_kmh = points.iter().map(|p| (p.0 - t) * (p.2 - y)).sum::<f64>() / stt;
+
+        // Standard error of the speed, from how far the centroids stray off
+        // the fitted line
+        let residuals: f64 = points
+            .iter()
+            .map(|p| {
+                let dx = p.1 - (x + east_kmh * (p.0 - t));
+                let dy = p.2 - (y + north_kmh * (p.0 - t));
+                dx * dx + dy * dy
+            })
+            .sum();
+        let speed_error_kmh = (residuals / 2.0 / (n - 2.0).max(1.0) / stt).sqrt();
+
+        // Where the fitted line puts the cell at the latest centroid
+        let (east, north) = (x - east_kmh * t, y - north_kmh * t);
+        let position = last.destination(east.atan2(north).to_degrees(), east.hypot(north));
+        Some(Motion {
+            at,
+            position,
+            east_kmh,
+            north_kmh,
+            speed_error_kmh,
+        })
+    }
+}
+
+/// Which way and how fast a [`StormCell`] is going.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motion {
+    /// Where the cell was at `at`, according to the fit.
+    pub at: SystemTime,
+    pub position: Location,
+    pub east_kmh: f64,
+    pub north_kmh: f64,
+    /// Standard error of the speed.
+    pub speed_error_kmh: f64,
+}
+
+/// When a cell would get to a place, give or take `error`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Eta {
+    pub arrival: Duration,
+    pub error: Duration,
+}
+
+impl Motion {
+    pub fn speed_kmh(&self) -> f64 {
+        self.east_kmh.hypot(self.north_kmh)
+    }
+
+    /// The compass bearing the cell is heading towards.
+    pub fn heading(&self) -> f64 {
+        self.east_kmh
+            .atan2(self.north_kmh)
+            .to_degrees()
+            .rem_euclid(360.0)
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        let speed = self.speed_kmh();
+        speed < STATIONARY_KMH || speed < self.speed_error_kmh
+    }
+
+    /// How long from `now` until a cell `radius_km` across its middle
+    /// reaches `place`, if it keeps going as it is. `None` if it is
+    /// stationary, moving away or going to miss.
+    pub fn eta(&self, place: Location, radius_km: f64, now: SystemTime) -> Option<Eta> {
+        if self.is_stationary() {
+            return None;
+        }
+        let (east, north) = offset_km(self.position, place);
+        let speed = self.speed_kmh();
+        let (ue, un) = (self.east_kmh / speed, self.north_kmh / speed);
+        // Distance along the track to the closest approach, and how close
+        // that is
+        let along = east * ue + north * un;
+        let miss = (east * un - north * ue).abs();
+        if miss > radius_km || (along < 0.0 && east.hypot(north) > radius_km) {
+            return None;
+        }
+
+        let hours = (along - (radius_km * radius_km - miss * miss).sqrt()).max(0.0) / speed;
+        let elapsed = now.duration_since(self.at).unwrap_or_default();
+        let arrival = Duration::from_secs_f64(hours * 3600.0).saturating_sub(elapsed);
+        let error = Duration::from_secs_f64(hours * 3600.0 * self.speed_error_kmh / speed);
+        Some(Eta { arrival, error })
+    }
+}
+
+/// How far east and north `to` is from `from`, in km, on a plane tangent at
+/// `from`. Good enough across a storm.
+fn offset_km(from: Location, to: Location) -> (f64, f64) {
+    let km_per_degree = EARTH_RADIUS_KM.to_radians();
+    let dlon = (to.lon - from.lon + 540.0).rem_euclid(360.0) - 180.0;
+    (
+        dlon * km_per_degree * from.lat.to_radians().cos(),
+        (to.lat - from.lat) * km_per_degree,
+    )
+}
+
+/// Mean of `times`, as an offset from `first`.
+fn span_mean(times: &[SystemTime], first: SystemTime) -> Duration {
+    let total: f64 = times
+        .iter()
+        .map(|time| time.duration_since(first).unwrap_or_default().as_secs_f64())
+        .sum();
+    Duration::from_secs_f64(total / times.len() as f64)
+}
+
+/// The mean position of `locations`, averaged on the sphere so cells on the
+/// antimeridian do not end up on the other side of the world.
+fn centroid(locations: impl Iterator<Item = Location>) -> Location {
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for location in locations {
+        let (lat, lon) = (location.lat.to_radians(), location.lon.to_radians());
+        x += lat.cos() * lon.cos();
+        y += lat.cos() * lon.sin();
+        z += lat.sin();
+    }
+    Location::new(z.atan2(x.hypot(y)).to_degrees(), y.atan2(x).to_degrees())
+}
+
+/// DBSCAN: clusters of `locations`, as indices, where every strike is within
+/// `radius_km` of a core strike that has at least `min_strikes` neighbours.
+/// Strikes in no cluster are noise and left out.
+fn dbscan(locations: &[Location], radius_km: f64, min_strikes: usize) -> Vec<Vec<usize>> {
+    let grid = Grid::new(locations, radius_km);
+    let mut cluster_of: Vec<Option<usize>> = vec![None; locations.len()];
+    let mut visited = vec![false; locations.len()];
+    let mut clusters = Vec::new();
+
+    for i in 0..locations.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+        let neighbours = grid.neighbours(locations, i);
+        if neighbours.len() < min_strikes {
+            // Noise, unless a core strike claims it later
+            continue;
+        }
+
+        let id = clusters.len();
+        let mut members = vec![i];
+        cluster_of[i] = Some(id);
+        let mut queue = neighbours;
+        while let Some(j) = queue.pop() {
+            if cluster_of[j].is_none() {
+                cluster_of[j] = Some(id);
+                members.push(j);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+            let more = grid.neighbours(locations, j);
+            if more.len() >= min_strikes {
+                queue.extend(more.into_iter().filter(|&k| cluster_of[k].is_none()));
+            }
+        }
+        clusters.push(members);
+    }
+    clusters
+}
+
+/// Buckets of strikes a neighbourhood wide, so finding neighbours does not
+/// mean looking at every strike.
+struct Grid {
+    radius_km: f64,
+    /// Bucket size, in degrees.
+    step: f64,
+    /// Buckets around a circle of latitude.
+    columns: i64,
+    buckets: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl Grid {
+    fn new(locations: &[Location], radius_km: f64) -> Self {
+        let step = (radius_km / EARTH_RADIUS_KM).to_degrees().max(1e-6);
+        let mut grid = Self {
+            radius_km,
+            step,
+            columns: (360.0 / step).ceil() as i64,
+            buckets: HashMap::new(),
+        };
+        for (i, &location) in locations.iter().enumerate() {
+            grid.buckets.entry(grid.key(location)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn key(&self, location: Location) -> (i64, i64) {
+        let row = (location.lat / self.step).floor() as i64;
+        let column = ((location.lon + 180.0) / self.step).floor() as i64;
+        (row, column.rem_euclid(self.columns))
+    }
+
+    /// Strikes within the radius of strike `i`, itself included.
+    fn neighbours(&self, locations: &[Location], i: usize) -> Vec<usize> {
+        let here = locations[i];
+        let (row, column) = self.key(here);
+        // Degrees of longitude shrink towards the poles, so look further
+        // east and west there
+        let lat = (here.lat.abs() + self.step).min(89.0).to_radians();
+        let reach = (1.0 / lat.cos()).ceil() as i64;
+        let columns: Vec<i64> = if 2 * reach + 1 >= self.columns {
+            (0..self.columns).collect()
+        } else {
+            (-reach..=reach)
+                .map(|dc| (column + dc).rem_euclid(self.columns))
+                .collect()
+        };
+
+        let mut neighbours = Vec::new();
+        for r in row - 1..=row + 1 {
+            for &c in &columns {
+                let Some(bucket) = self.buckets.get(&(r, c)) else {
+                    continue;
+                };
+                neighbours.extend(
+                    bucket
+                        .iter()
+                        .filter(|&&j| here.distance_km(&locations[j]) <= self.radius_km),
+                );
+            }
+        }
+        neighbours
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_at;
+
+    /// `count` strikes from `secs` on, one a second, scattered up to a few
+    /// km around `center`.
+    fn burst(secs: u64, count: u64, center: Location) -> Vec<LightningStrike> {
+        (0..count)
+            .map(|n| {
+                let spot = center.destination(n as f64 * 137.5, (n % 5) as f64);
+                strike_at(secs + n, spot.lat, spot.lon)
+            })
+            .collect()
+    }
+
+    fn tracker_with(strikes: impl IntoIterator<Item = LightningStrike>) -> CellTracker {
+        let mut tracker = CellTracker::default();
+        for strike in strikes {
+            tracker.insert(&strike);
+        }
+        tracker.cluster();
+        tracker
+    }
+
+    #[test]
+    fn nearby_strikes_make_a_cell_and_stray_ones_do_not() {
+        let west = Location::new(45.0, 9.0);
+        let east = Location::new(45.0, 12.0);
+        let mut strikes = burst(0, 30, west);
+        strikes.extend(burst(0, 10, east));
+        // Strays, far from everything and each other
+        strikes.extend((0..5).map(|n| strike_at(n, 40.0 + n as f64, 0.0)));
+        let tracker = tracker_with(strikes);
+
+        let cells = tracker.cells();
+        assert_eq!(cells.len(), 2);
+        assert_eq!((cells[0].strikes, cells[1].strikes), (30, 10));
+        assert!(cells[0].centroid.distance_km(&west) < 1.0);
+        assert!(cells[1].centroid.distance_km(&east) < 1.0);
+        assert!(
+            (3.0..=4.5).contains(&cells[0].radius_km),
+            "{}",
+            cells[0].radius_km
+        );
+        // 30 strikes in 29 seconds, counted over a minute at least
+        assert_eq!(cells[0].rate_per_min, 30.0);
+    }
+
+    #[test]
+    fn cells_keep_their_id_as_they_move() {
+        let start = Location::new(0.0, 179.99);
+        let mut tracker = tracker_with(burst(0, 20, start));
+        let id = tracker.cells()[0].id;
+
+        // 5 km east every minute, across the antimeridian
+        for minute in 1..=3 {
+            let moved = start.destination(90.0, 5.0 * minute as f64);
+            for strike in burst(minute * 60, 20, moved) {
+                tracker.insert(&strike);
+            }
+            assert!(tracker.update());
+            assert!(!tracker.update());
+        }
+        let cell = &tracker.cells()[0];
+        assert_eq!((tracker.cells().len(), cell.id), (1, id));
+        assert_eq!(cell.track.len(), 4);
+        assert!(cell.centroid.lon < -179.9, "{}", cell.centroid);
+
+        // A new cell far away gets a new id
+        let mut tracker = tracker.clone();
+        for strike in burst(250, 20, Location::new(10.0, 10.0)) {
+            tracker.insert(&strike);
+        }
+        tracker.cluster();
+        let ids: Vec<_> = tracker.cells().iter().map(|cell| cell.id).collect();
+        assert_eq!(ids, [id, id + 1]);
+    }
+
+    /// A cell that has been going at `kmh` towards `heading` for half an
+    /// hour, with a centroid every 5 minutes, ending up at `end` at `secs`.
+    fn moving_cell(end: Location, secs: u64, heading: f64, kmh: f64) -> StormCell {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let track = (0..=6)
+            .rev()
+            .map(|n| {
+                let back = end.destination(heading + 180.0, kmh * n as f64 / 12.0);
+                (at(secs - n * 300), back)
+            })
+            .collect();
+        StormCell {
+            id: 3,
+            centroid: end,
+            strikes: 100,
+            rate_per_min: 5.0,
+            radius_km: 10.0,
+            first_seen: at(secs - 1800),
+            last_strike: at(secs),
+            track,
+        }
+    }
+
+    #[test]
+    fn cells_moving_towards_home_have_an_eta() {
+        let home = Location::new(45.0, 9.0);
+        // 45 km south-west of home, heading north-east at 30 km/h
+        let cell = moving_cell(home.destination(225.0, 45.0), 3600, 45.0, 30.0);
+        let motion = cell.motion().unwrap();
+        assert!((motion.speed_kmh() - 30.0).abs() < 0.1, "{motion:?}");
+        assert!((motion.heading() - 45.0).abs() < 0.5, "{motion:?}");
+        assert!(motion.speed_error_kmh < 0.1);
+        assert!(motion.position.distance_km(&cell.centroid) < 0.1);
+
+        // 35 km to go at 30 km/h is 70 minutes, 10 of which have passed
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(3600 + 600);
+        let eta = motion.eta(home, cell.radius_km, now).unwrap();
+        assert_eq!(eta.arrival.as_secs() / 60, 60);
+        assert!(eta.error < Duration::from_secs(60));
+
+        // Not if it goes by 20 km off, or away from home
+        let aside = Location::new(45.0, 9.0).destination(135.0, 20.0);
+        assert_eq!(motion.eta(aside, cell.radius_km, now), None);
+        let behind = cell.centroid.destination(225.0, 30.0);
+        assert_eq!(motion.eta(behind, cell.radius_km, now), None);
+        // ... and right away once it is there
+        let eta = motion.eta(cell.centroid, cell.radius_km, now).unwrap();
+        assert_eq!(eta.arrival, Duration::ZERO);
+    }
+
+    #[test]
+    fn short_or_stationary_tracks_have_no_eta() {
+        let home = Location::new(0.0, 0.0);
+        let mut cell = moving_cell(home.destination(0.0, 50.0), 3600, 180.0, 30.0);
+        cell.track.drain(..5);
+        assert_eq!(cell.motion(), None);
+
+        let cell = moving_cell(home.destination(0.0, 50.0), 3600, 180.0, 2.0);
+        let motion = cell.motion().unwrap();
+        assert!(motion.is_stationary());
+        assert_eq!(motion.eta(home, 10.0, SystemTime::UNIX_EPOCH), None);
+    }
+
+    #[test]
+    fn old_strikes_leave_the_window() {
+        let mut tracker = tracker_with(burst(0, 20, Location::new(0.0, 0.0)));
+        assert_eq!(tracker.cells().len(), 1);
+
+        tracker.expire(SystemTime::UNIX_EPOCH + Duration::from_secs(16 * 60));
+        assert!(tracker.update());
+        assert!(tracker.cells().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, mpsc};
+use std::thread;
+use std::time::Instant;
+
+use crate::blitzortung::{BLITZ_HANDSHAKE, BLITZSERVERS, DecodeError, Decoder, LightningStrike};
+use crate::database::StrikeWriter;
+use crate::recording::ReplaySpeed;
+use crate::store::Retention;
+use crate::websocket::{Backoff, WebSocketEvent, WebSocketHandle, WebSocketManager};
+
+/// Structured events produced from a Blitzortung feed.
+#[derive(Debug)]
+pub enum BlitzEvent {
+    /// Connected to `url` and sent the handshake.
+    Connected { url: String },
+    /// Playing back the recording at `path` instead of a live feed.
+    ReplayStarted { path: PathBuf },
+    /// The recording at `path` could not be played.
+    ReplayFailed { path: PathBuf, error: String },
+    /// Connecting to `url` failed.
+    ConnectFailed { url: String, error: String },
+    /// The connection is gone.
+    Disconnected,
+    /// The connection dropped; retry number `attempt` dials `url` at `retry_at`.
+    Reconnecting {
+        attempt: u32,
+        url: String,
+        retry_at: Instant,
+    },
+    /// A decoded lightning strike.
+    Strike(LightningStrike),
+    /// A text frame that is not a valid LZW message.
+    DecodeFailed { frame: String, error: DecodeError },
+    /// A frame that decoded fine but is not a [`LightningStrike`].
+    InvalidStrike { decoded: String, error: String },
+    /// A frame that decoded to JSON stopping half way, i.e. a message cut
+    /// short. Its LZW stream is fine, so the decoder cannot tell.
+    Truncated { decoded: String },
+    /// Transport errors and frames Blitzortung never sends.
+    ProtocolError(String),
+}
+
+impl BlitzEvent {
+    /// Map a raw transport event to a Blitzortung event, decoding text frames
+    /// with `decoder`.
+    pub fn from_websocket(event: WebSocketEvent, decoder: &mut Decoder) -> Self {
+        match event {
+            WebSocketEvent::Connected { url } => BlitzEvent::Connected { url },
+            WebSocketEvent::ReplayStarted { path } => BlitzEvent::ReplayStarted { path },
+            WebSocketEvent::ReplayFailed { path, error } => {
+                BlitzEvent::ReplayFailed { path, error }
+            }
+            WebSocketEvent::ConnectFailed { url, error } => {
+                BlitzEvent::ConnectFailed { url, error }
+            }
+            WebSocketEvent::Disconnected => BlitzEvent::Disconnected,
+            WebSocketEvent::Reconnecting {
+                attempt,
+                url,
+                retry_at,
+            } => BlitzEvent::Reconnecting {
+                attempt,
+                url,
+                retry_at,
+            },
+            WebSocketEvent::Text(text) => match decoder.decode(&text) {
+                Ok(decoded) => match serde_json::from_str::<LightningStrike>(decoded) {
+                    Ok(strike) => BlitzEvent::Strike(strike),
+                    Err(e) if e.is_eof() => BlitzEvent::Truncated {
+                        decoded: decoded.to_string(),
+                    },
+                    Err(e) => BlitzEvent::InvalidStrike {
+                        decoded: decoded.to_string(),
+                        error: e.to_string(),
+                    },
+                },
+                Err(error) => BlitzEvent::DecodeFailed { frame: text, error },
+            },
+            WebSocketEvent::Binary(data) => {
+                BlitzEvent::ProtocolError(format!("unexpected binary frame ({} bytes)", data.len()))
+            }
+            WebSocketEvent::Error(e) => BlitzEvent::ProtocolError(e),
+        }
+    }
+}
+
+/// A Blitzortung-aware client on top of [`WebSocketManager`].
+///
+/// Frames are decoded on a dedicated thread so consumers only ever see
+/// [`BlitzEvent`]s. That thread also writes strikes to the database, when
+/// there is one, whether or not anyone is reading the events.
+#[derive(Debug)]
+pub struct BlitzortungClient {
+    ws: WebSocketHandle,
+    events: mpsc::Receiver<BlitzEvent>,
+    writer: Arc<Mutex<Option<StrikeWriter>>>,
+}
+
+impl BlitzortungClient {
+    pub fn new() -> Self {
+        let (ws, ws_events) = WebSocketManager::new().into_split();
+        let (tx, rx) = mpsc::channel::<BlitzEvent>();
+        let writer = Arc::new(Mutex::new(None::<StrikeWriter>));
+
+        // Ends when the websocket worker stops, i.e. when the client is dropped.
+        let persist = Arc::clone(&writer);
+        thread::spawn(move || {
+            let mut decoder = Decoder::new();
+            for event in ws_events {
+                let event = BlitzEvent::from_websocket(event, &mut decoder);
+                if let BlitzEvent::Strike(strike) = &event
+                    && let Some(writer) = lock_writer(&persist).as_mut()
+                {
+                    writer.write(strike);
+                }
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+            tracing::debug!("decoder thread stopped");
+        });
+
+        Self {
+            ws,
+            events: rx,
+            writer,
+        }
+    }
+
+    /// Connect to Blitzortung, failing over between [`BLITZSERVERS`] and
+    /// reconnecting until [`disconnect`](Self::disconnect) is called.
+    pub fn connect(&self) {
+        self.connect_to(&BLITZSERVERS, Backoff::default());
+    }
+
+    /// Like [`connect`](Self::connect) with a custom server list and backoff.
+    /// The handshake is re-sent after every reconnect.
+    pub fn connect_to(&self, servers: &[&str], backoff: Backoff) {
+        self.ws.connect_supervised(
+            servers.iter().map(|s| s.to_string()).collect(),
+            Some(BLITZ_HANDSHAKE.to_vec()),
+            backoff,
+        );
+    }
+
+    pub fn disconnect(&self) {
+        self.ws.disconnect();
+    }
+
+    /// Play back a recorded session instead of connecting.
+    pub fn replay(&self, path: PathBuf, speed: ReplaySpeed) {
+        self.ws.replay(path, speed);
+    }
+
+    /// Record the raw frames of this session to `path`, across reconnects.
+    pub fn start_recording(&self, path: PathBuf) {
+        self.ws.start_recording(path);
+    }
+
+    pub fn stop_recording(&self) {
+        self.ws.stop_recording();
+    }
+
+    /// Write every strike to `writer` before handing it on, across
+    /// reconnects and replays.
+    pub fn start_persisting(&self, writer: StrikeWriter) {
+        *lock_writer(&self.writer) = Some(writer);
+    }
+
+    pub fn stop_persisting(&self) {
+        *lock_writer(&self.writer) = None;
+    }
+
+    /// Change how much the database keeps, if strikes are being persisted.
+    pub fn set_history_retention(&self, retention: Retention) {
+        if let Some(writer) = lock_writer(&self.writer).as_mut() {
+            writer.set_retention(retention);
+        }
+    }
+
+    pub fn try_recv_event(&self) -> Option<BlitzEvent> {
+        self.events.try_recv().ok()
+    }
+
+    pub fn recv_event_blocking(&self) -> Result<BlitzEvent, mpsc::RecvError> {
+        self.events.recv()
+    }
+}
+
+impl Default for BlitzortungClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A writer that panicked while holding the lock is still usable, so the
+/// decoder thread keeps going and the feed does not stop.
+fn lock_writer(writer: &Mutex<Option<StrikeWriter>>) -> MutexGuard<'_, Option<StrikeWriter>> {
+    writer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRIKE_JSON: &str = r#"{"time":1754911234567890000,"lat":45.1234,"lon":9.5678,"alt":0,"pol":0,"mds":12000,"mcg":210,"status":0,"region":1,"sig":[{"sta":1234,"time":2345678,"lat":46.1,"lon":9.2,"alt":300,"status":4}],"delay":3.2,"lonc":1,"latc":1}"#;
+
+    fn from_ws(event: WebSocketEvent) -> BlitzEvent {
+        BlitzEvent::from_websocket(event, &mut Decoder::new())
+    }
+
+    #[test]
+    fn text_frames_become_strikes() {
+        // Plain ASCII is a valid LZW stream that decodes to itself.
+        let event = from_ws(WebSocketEvent::Text(STRIKE_JSON.to_string()));
+        match event {
+            BlitzEvent::Strike(strike) => {
+                assert_eq!(strike.lat, 45.1234);
+                assert_eq!(strike.sig.len(), 1);
+            }
+            other => panic!("expected a strike, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn json_that_is_not_a_strike_is_invalid() {
+        let event = from_ws(WebSocketEvent::Text("{\"nope\":1}".to_string()));
+        assert!(matches!(event, BlitzEvent::InvalidStrike { .. }));
+    }
+
+    #[test]
+    fn broken_frames_are_reported() {
+        let half = &STRIKE_JSON[..STRIKE_JSON.len() / 2];
+        match from_ws(WebSocketEvent::Text(half.to_string())) {
+            BlitzEvent::Truncated { decoded } => assert_eq!(decoded, half),
+            other => panic!("expected a truncated message, got {other:?}"),
+        }
+        assert!(matches!(
+            from_ws(WebSocketEvent::Text(String::new())),
+            BlitzEvent::DecodeFailed {
+                error: DecodeError::Empty,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn connection_states_are_not_confused() {
+        assert!(matches!(
+            from_ws(WebSocketEvent::Disconnected),
+            BlitzEvent::Disconnected
+        ));
+        assert!(matches!(
+            from_ws(WebSocketEvent::Connected {
+                url: "wss://example".into()
+            }),
+            BlitzEvent::Connected { .. }
+        ));
+        // Replays say so, rather than passing for a server
+        assert!(matches!(
+            from_ws(WebSocketEvent::ReplayStarted {
+                path: "session.blzrec".into()
+            }),
+            BlitzEvent::ReplayStarted { .. }
+        ));
+        assert!(matches!(
+            from_ws(WebSocketEvent::ReplayFailed {
+                path: "session.blzrec".into(),
+                error: "not found".into(),
+            }),
+            BlitzEvent::ReplayFailed { .. }
+        ));
+        assert!(matches!(
+            from_ws(WebSocketEvent::Binary(vec![1, 2, 3])),
+            BlitzEvent::ProtocolError(_)
+        ));
+    }
+
+    #[test]
+    fn a_writer_that_panicked_is_still_usable() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = BlitzortungClient::new();
+        let writer = StrikeWriter::open(&dir.path().join("strikes.db"), Retention::default());
+        client.start_persisting(writer.unwrap());
+
+        let lock = Arc::clone(&client.writer);
+        let _ = thread::spawn(move || {
+            let _guard = lock.lock().unwrap();
+            panic!("writer failed");
+        })
+        .join();
+        assert!(client.writer.is_poisoned());
+
+        client.set_history_retention(Retention::default());
+        client.stop_persisting();
+        assert!(lock_writer(&client.writer).is_none());
+    }
+}
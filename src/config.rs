@@ -0,0 +1,296 @@
+//! `config.toml`: the hotkey, the popup window and the feed, tunable without
+//! a rebuild. Checked when it is loaded, and loaded again whenever it
+//! changes.
+//!
+//! ```toml
+//! hotkey = "cmd+shift+k"
+//!
+//! [window]
+//! width = 400
+//! height = 300
+//! level = 3                     # NSFloatingWindowLevel
+//!
+//! [feed]
+//! servers = ["wss://ws1.blitzortung.org", "wss://ws7.blitzortung.org", "wss://ws8.blitzortung.org"]
+//! max_strikes = 100000
+//! ```
+//!
+//! Every key is optional; whatever is left out keeps its default.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use crate::accelerator::{self, Accelerator};
+use crate::blitzortung::BLITZSERVERS;
+use crate::store::Retention;
+
+/// Where the config lives.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("popuppp").join("config.toml"))
+}
+
+/// Cmd+Shift+K.
+pub const DEFAULT_HOTKEY: Accelerator = Accelerator {
+    keycode: 40,
+    modifiers: accelerator::COMMAND | accelerator::SHIFT,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The global hotkey that shows and hides the popup.
+    pub hotkey: Accelerator,
+    pub window: WindowConfig,
+    pub feed: FeedConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hotkey: DEFAULT_HOTKEY,
+            window: WindowConfig::default(),
+            feed: FeedConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /// Size of a new window, in points.
+    pub width: f64,
+    pub height: f64,
+    /// The `NSWindowLevel` to float at.
+    pub level: isize,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 400.0,
+            height: 300.0,
+            level: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedConfig {
+    /// Blitzortung servers, tried in turn.
+    pub servers: Vec<String>,
+    /// The most strikes the popup keeps in memory; its own retention
+    /// setting can only go lower.
+    pub max_strikes: usize,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            servers: BLITZSERVERS.iter().map(|s| s.to_string()).collect(),
+            max_strikes: Retention::default().max_count,
+        }
+    }
+}
+
+impl Config {
+    /// Parse and check a config file's contents.
+    pub fn from_toml(toml: &str) -> Result<Self, String> {
+        let config: Config = toml::from_str(toml).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The config at `path`, or the defaults if there is none.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(toml) => Self::from_toml(&toml).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let window = &self.window;
+        for (name, value) in [("width", window.width), ("height", window.height)] {
+            if !(200.0..=4000.0).contains(&value) {
+                return Err(format!(
+                    "window.{} {} is out of range, it goes from 200 to 4000",
+                    name, value
+                ));
+            }
+        }
+        if !(0..=1000).contains(&window.level) {
+            return Err(format!(
+                "window.level {} is out of range, it goes from 0 (normal) to 1000 (screen saver)",
+                window.level
+            ));
+        }
+
+        let feed = &self.feed;
+        if feed.servers.is_empty() {
+            return Err("feed.servers needs at least one server".to_string());
+        }
+        if let Some(server) = feed
+            .servers
+            .iter()
+            .find(|s| !s.starts_with("ws://") && !s.starts_with("wss://"))
+        {
+            return Err(format!(
+                "feed.servers: '{}' is not a ws:// or wss:// URL",
+                server
+            ));
+        }
+        if !(1..=10_000_000).contains(&feed.max_strikes) {
+            return Err(format!(
+                "feed.max_strikes {} is out of range, it goes from 1 to 10000000",
+                feed.max_strikes
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the config again whenever the file changes, going by its
+/// modification time.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    config: Config,
+    error: Option<String>,
+}
+
+impl ConfigWatcher {
+    /// Watch `path`, starting from the defaults until the first [`poll`](Self::poll).
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            config: Config::default(),
+            error: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The config in effect: the last one that loaded.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// What was wrong with the file when it was last loaded, if anything.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Load the file if it changed since last time. A file that does not
+    /// check out is reported and the config in effect is kept.
+    pub fn poll(&mut self) -> Option<Result<&Config, String>> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match Config::load(&self.path) {
+            Ok(config) => {
+                self.config = config;
+                self.error = None;
+                Some(Ok(&self.config))
+            }
+            Err(error) => {
+                self.error = Some(error.clone());
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn configs_fill_in_the_defaults() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.hotkey.to_string(), "Cmd+Shift+K");
+        assert_eq!((config.window.width, config.window.height), (400.0, 300.0));
+        assert_eq!(config.feed.servers, BLITZSERVERS);
+
+        let config = Config::from_toml(
+            r#"
+            hotkey = "ctrl+alt+space"
+
+            [feed]
+            servers = ["ws://localhost:8080"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.hotkey, "ctrl+alt+space".parse().unwrap());
+        assert_eq!(config.feed.servers, ["ws://localhost:8080"]);
+        assert_eq!(config.window, WindowConfig::default());
+        assert_eq!(config.feed.max_strikes, 100_000);
+    }
+
+    #[test]
+    fn bad_configs_say_what_is_wrong() {
+        for (toml, expected) in [
+            ("hotkey = 40", "line 1"),
+            ("hotkey = \"k\"", "'k' needs a modifier"),
+            ("hotkey = \"hyper+k\"", "'hyper' is not a modifier"),
+            ("[window]\nwidht = 500", "unknown field `widht`"),
+            ("[window]\nheight = 50", "window.height 50"),
+            ("[window]\nlevel = -1", "window.level -1"),
+            ("[feed]\nservers = []", "feed.servers"),
+            (
+                "[feed]\nservers = [\"https://example.com\"]",
+                "'https://example.com'",
+            ),
+            ("[feed]\nmax_strikes = 0", "feed.max_strikes 0"),
+        ] {
+            let error = Config::from_toml(toml).unwrap_err();
+            assert!(error.contains(expected), "{toml}: {error}");
+        }
+    }
+
+    #[test]
+    fn changed_files_are_loaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut watcher = ConfigWatcher::new(path.clone());
+        // Nothing to load yet
+        assert!(watcher.poll().is_none());
+
+        let write = |toml: &str, secs: u64| {
+            std::fs::write(&path, toml).unwrap();
+            // File systems with coarse timestamps would miss quick edits
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .unwrap();
+        };
+        write("[window]\nlevel = 8", 1);
+        assert_eq!(watcher.poll().unwrap().unwrap().window.level, 8);
+        assert!(watcher.poll().is_none());
+
+        // A broken edit keeps what worked
+        write("[window]\nlevel = 8000", 2);
+        assert!(watcher.poll().unwrap().is_err());
+        assert_eq!(watcher.config().window.level, 8);
+        assert!(watcher.error().unwrap().contains("window.level 8000"));
+
+        write("[window]\nlevel = 0", 3);
+        assert_eq!(watcher.poll().unwrap().unwrap().window.level, 0);
+        assert_eq!(watcher.error(), None);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap().unwrap(), &Config::default());
+    }
+}
@@ -0,0 +1,495 @@
+//! Strike history on disk, in SQLite, so it survives the popup hiding and
+//! the app restarting.
+//!
+//! Every strike goes into one table, with an R-tree next to it for the
+//! bounding box queries. Triggers keep the two in step, so deleting from
+//! `strikes` is all pruning has to do.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension, Row, ToSql, params, params_from_iter};
+use tracing::{debug, info, warn};
+
+use crate::areas::StrikeFilter;
+use crate::blitzortung::LightningStrike;
+use crate::store::Retention;
+
+pub use rusqlite::Error;
+
+/// Why a strike database could not be opened.
+#[derive(Debug)]
+pub enum DatabaseError {
+    Sqlite(Error),
+    /// Its directory could not be created.
+    Io(std::io::Error),
+    /// A later version of the app wrote it; it is left alone.
+    NewerSchema {
+        found: i32,
+        supported: i32,
+    },
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::Sqlite(e) => write!(f, "{}", e),
+            DatabaseError::Io(e) => write!(f, "{}", e),
+            DatabaseError::NewerSchema { found, supported } => write!(
+                f,
+                "database schema {} is newer than this version understands ({})",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseError::Sqlite(e) => Some(e),
+            DatabaseError::Io(e) => Some(e),
+            DatabaseError::NewerSchema { .. } => None,
+        }
+    }
+}
+
+impl From<Error> for DatabaseError {
+    fn from(e: Error) -> Self {
+        DatabaseError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(e: std::io::Error) -> Self {
+        DatabaseError::Io(e)
+    }
+}
+
+/// Bumped whenever the schema changes.
+const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS strikes (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    alt REAL NOT NULL,
+    pol INTEGER NOT NULL,
+    mds INTEGER NOT NULL,
+    mcg INTEGER NOT NULL,
+    status INTEGER NOT NULL,
+    region INTEGER NOT NULL,
+    delay REAL,
+    lonc INTEGER NOT NULL,
+    latc INTEGER NOT NULL,
+    sig TEXT NOT NULL,
+    -- Reconnects and replays deliver strikes we already have
+    UNIQUE (time, lat, lon)
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS strike_locations
+    USING rtree(id, min_lat, max_lat, min_lon, max_lon);
+CREATE TRIGGER IF NOT EXISTS strikes_located AFTER INSERT ON strikes BEGIN
+    INSERT INTO strike_locations VALUES (new.id, new.lat, new.lat, new.lon, new.lon);
+END;
+CREATE TRIGGER IF NOT EXISTS strikes_unlocated AFTER DELETE ON strikes BEGIN
+    DELETE FROM strike_locations WHERE id = old.id;
+END;
+";
+
+const COLUMNS: &str = "time, lat, lon, alt, pol, mds, mcg, status, region, delay, lonc, latc, sig";
+
+/// How often a [`StrikeWriter`] prunes the database.
+pub const PRUNE_EVERY: Duration = Duration::from_secs(10 * 60);
+
+/// What the database keeps unless told otherwise: a couple of busy days.
+pub const DEFAULT_RETENTION: Retention = Retention {
+    max_count: 2_000_000,
+    max_age: Some(Duration::from_secs(2 * 24 * 3600)),
+};
+
+/// Where the popup keeps its history, in the user's data directory.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("popuppp").join("strikes.sqlite"))
+}
+
+/// A connection to a strike database. The transport thread writes through
+/// one, the UI reads through another.
+#[derive(Debug)]
+pub struct StrikeDatabase {
+    conn: Connection,
+}
+
+impl StrikeDatabase {
+    /// Open the database at `path`, creating it and its directory if need be.
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        // Readers and the writer do not block each other
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        Self::init(conn)
+    }
+
+    /// A throwaway database, for tests.
+    pub fn open_in_memory() -> Result<Self, DatabaseError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, DatabaseError> {
+        let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(DatabaseError::NewerSchema {
+                found: version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        // Only takes effect before the first table is created, which is
+        // the point: pruning can then hand pages back a few at a time
+        conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        Ok(Self { conn })
+    }
+
+    /// Store `strike`. Returns `false` if it was already there.
+    pub fn insert(&self, strike: &LightningStrike) -> Result<bool, Error> {
+        let sig = serde_json::to_string(&strike.sig)
+            .map_err(|e| Error::ToSqlConversionFailure(Box::new(e)))?;
+        let inserted = self
+            .conn
+            .prepare_cached(&format!(
+                "INSERT OR IGNORE INTO strikes ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                COLUMNS
+            ))?
+            .execute(params![
+                nanos_to_sql(strike.time),
+                strike.lat,
+                strike.lon,
+                strike.alt,
+                strike.pol,
+                strike.mds,
+                strike.mcg,
+                strike.status,
+                strike.region,
+                strike.delay,
+                strike.lonc,
+                strike.latc,
+                sig,
+            ])?;
+        Ok(inserted > 0)
+    }
+
+    pub fn len(&self) -> Result<usize, Error> {
+        self.conn
+            .query_row("SELECT count(*) FROM strikes", [], |row| row.get(0))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+
+    /// When the oldest stored strike happened.
+    pub fn oldest(&self) -> Result<Option<SystemTime>, Error> {
+        let time: Option<i64> = self
+            .conn
+            .query_row("SELECT min(time) FROM strikes", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+        Ok(time.map(|t| UNIX_EPOCH + Duration::from_nanos(t.max(0) as u64)))
+    }
+
+    /// The strikes `filter` lets through, oldest first. The time range and
+    /// bounding box narrow the search in SQL; with a `limit`, only the most
+    /// recent matches are returned.
+    pub fn query(
+        &self,
+        filter: &StrikeFilter,
+        limit: Option<usize>,
+    ) -> Result<Vec<LightningStrike>, Error> {
+        let since = filter.since.map_or(0, |t| nanos_to_sql(nanos(t)));
+        let until = filter.until.map_or(i64::MAX, |t| nanos_to_sql(nanos(t)));
+        let mut sql = format!(
+            "SELECT {} FROM strikes WHERE time >= ?1 AND time < ?2",
+            COLUMNS
+        );
+        let mut values: Vec<Box<dyn ToSql>> = vec![Box::new(since), Box::new(until)];
+        if let Some(bbox) = filter.bbox {
+            values.extend([bbox.south, bbox.north, bbox.west, bbox.east].map(|v| Box::new(v) as _));
+            // The R-tree rounds outwards, the exact check happens below
+            let lon = if bbox.west <= bbox.east {
+                "max_lon >= ?5 AND min_lon <= ?6"
+            } else {
+                "(max_lon >= ?5 OR min_lon <= ?6)"
+            };
+            sql.push_str(&format!(
+                " AND id IN (SELECT id FROM strike_locations WHERE max_lat >= ?3 AND min_lat <= ?4 AND {})",
+                lon
+            ));
+        }
+        sql.push_str(" ORDER BY time DESC");
+
+        let mut statement = self.conn.prepare_cached(&sql)?;
+        let rows = statement.query_map(params_from_iter(values), strike_from_row)?;
+        let mut strikes = Vec::new();
+        for strike in rows {
+            if limit.is_some_and(|limit| strikes.len() >= limit) {
+                break;
+            }
+            let strike = strike?;
+            if filter.matches(&strike) {
+                strikes.push(strike);
+            }
+        }
+        strikes.reverse();
+        Ok(strikes)
+    }
+
+    /// Delete whatever `retention` no longer covers as of `now`, then hand
+    /// the freed pages back to the file system. Returns how many strikes
+    /// went.
+    pub fn prune(&self, retention: Retention, now: SystemTime) -> Result<usize, Error> {
+        let mut deleted = 0;
+        if let Some(cutoff) = retention.max_age.and_then(|age| now.checked_sub(age)) {
+            deleted += self.conn.execute(
+                "DELETE FROM strikes WHERE time < ?1",
+                [nanos_to_sql(nanos(cutoff))],
+            )?;
+        }
+        let cutoff: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT time FROM strikes ORDER BY time DESC LIMIT 1 OFFSET ?1",
+                [retention.max_count],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(cutoff) = cutoff {
+            deleted += self
+                .conn
+                .execute("DELETE FROM strikes WHERE time <= ?1", [cutoff])?;
+        }
+        if deleted > 0 {
+            self.conn.execute_batch("PRAGMA incremental_vacuum")?;
+        }
+        Ok(deleted)
+    }
+}
+
+/// Writes strikes to a [`StrikeDatabase`] as they arrive and prunes it
+/// every [`PRUNE_EVERY`], so it never grows past its retention for long.
+#[derive(Debug)]
+pub struct StrikeWriter {
+    db: StrikeDatabase,
+    retention: Retention,
+    pruned_at: Option<Instant>,
+}
+
+impl StrikeWriter {
+    pub fn new(db: StrikeDatabase, retention: Retention) -> Self {
+        Self {
+            db,
+            retention,
+            pruned_at: None,
+        }
+    }
+
+    /// Open the database at `path` for writing.
+    pub fn open(path: &Path, retention: Retention) -> Result<Self, DatabaseError> {
+        Ok(Self::new(StrikeDatabase::open(path)?, retention))
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Switch to `retention`, applied at the next strike.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.pruned_at = None;
+    }
+
+    /// Store `strike`, pruning first if it is time to. Errors are logged
+    /// rather than returned: a full disk should not stop the feed.
+    pub fn write(&mut self, strike: &LightningStrike) {
+        if self.pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_EVERY) {
+            self.pruned_at = Some(Instant::now());
+            match self.db.prune(self.retention, SystemTime::now()) {
+                Ok(0) => {}
+                Ok(deleted) => info!(deleted, "pruned strike history"),
+                Err(error) => warn!(%error, "pruning strike history failed"),
+            }
+        }
+        match self.db.insert(strike) {
+            Ok(true) => {}
+            Ok(false) => debug!(time = strike.time, "strike already stored"),
+            Err(error) => warn!(%error, "storing strike failed"),
+        }
+    }
+}
+
+fn strike_from_row(row: &Row<'_>) -> Result<LightningStrike, Error> {
+    let sig: String = row.get(12)?;
+    Ok(LightningStrike {
+        time: row.get::<_, i64>(0)?.max(0) as u64,
+        lat: row.get(1)?,
+        lon: row.get(2)?,
+        alt: row.get(3)?,
+        pol: row.get(4)?,
+        mds: row.get(5)?,
+        mcg: row.get(6)?,
+        status: row.get(7)?,
+        region: row.get(8)?,
+        delay: row.get(9)?,
+        lonc: row.get(10)?,
+        latc: row.get(11)?,
+        sig: serde_json::from_str(&sig).map_err(|e| {
+            Error::FromSqlConversionFailure(12, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+    })
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// SQLite integers are signed; nanoseconds since the epoch fit until 2262.
+fn nanos_to_sql(nanos: u64) -> i64 {
+    i64::try_from(nanos).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::{strike_at, strike_fixture_records};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn times(strikes: &[LightningStrike]) -> Vec<u64> {
+        strikes.iter().map(|s| s.time / 1_000_000_000).collect()
+    }
+
+    #[test]
+    fn strikes_come_back_as_they_went_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history").join("strikes.sqlite");
+        let fixtures = strike_fixture_records();
+        {
+            let db = StrikeDatabase::open(&path).unwrap();
+            for strike in &fixtures {
+                assert!(db.insert(strike).unwrap());
+            }
+            // Seen again after a reconnect
+            assert!(!db.insert(&fixtures[0]).unwrap());
+        }
+
+        let db = StrikeDatabase::open(&path).unwrap();
+        assert_eq!(db.len().unwrap(), fixtures.len());
+        let mut expected = fixtures.clone();
+        expected.sort_by_key(|s| s.time);
+        assert_eq!(db.query(&StrikeFilter::default(), None).unwrap(), expected);
+        assert_eq!(db.oldest().unwrap(), Some(expected[0].timestamp()));
+
+        // Written by a later version
+        db.conn.pragma_update(None, "user_version", 99).unwrap();
+        drop(db);
+        assert!(matches!(
+            StrikeDatabase::open(&path),
+            Err(DatabaseError::NewerSchema {
+                found: 99,
+                supported: SCHEMA_VERSION
+            })
+        ));
+        // Somewhere no directory can go
+        assert!(matches!(
+            StrikeDatabase::open(&path.join("strikes.sqlite")),
+            Err(DatabaseError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn strikes_can_be_queried_by_time_and_place() {
+        let db = StrikeDatabase::open_in_memory().unwrap();
+        for (secs, lat, lon) in [
+            (0, 45.0, 9.0),
+            (10, 45.5, 9.5),
+            (20, -33.9, 151.2),
+            (30, 0.0, 179.5),
+            (40, 0.0, -179.5),
+            (50, 46.0, 10.0),
+        ] {
+            db.insert(&strike_at(secs, lat, lon)).unwrap();
+        }
+
+        let query = |filter: &str| {
+            let filter = StrikeFilter {
+                bbox: Some(filter.parse().unwrap()),
+                ..Default::default()
+            };
+            times(&db.query(&filter, None).unwrap())
+        };
+        assert_eq!(query("5,40,15,50"), [0, 10, 50]);
+        assert_eq!(query("170,-10,-170,10"), [30, 40]);
+
+        let filter = StrikeFilter {
+            since: Some(at(10)),
+            until: Some(at(50)),
+            ..Default::default()
+        };
+        assert_eq!(times(&db.query(&filter, None).unwrap()), [10, 20, 30, 40]);
+        // The most recent ones, still oldest first
+        assert_eq!(times(&db.query(&filter, Some(2)).unwrap()), [30, 40]);
+
+        let filter = StrikeFilter {
+            areas: vec!["circle 45,9 100".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(times(&db.query(&filter, None).unwrap()), [0, 10]);
+    }
+
+    #[test]
+    fn old_strikes_are_pruned() {
+        let db = StrikeDatabase::open_in_memory().unwrap();
+        for secs in 0..100 {
+            db.insert(&strike_at(secs, 0.0, 0.0)).unwrap();
+        }
+
+        let retention = Retention {
+            max_count: 1000,
+            max_age: Some(Duration::from_secs(60)),
+        };
+        assert_eq!(db.prune(retention, at(100)).unwrap(), 40);
+        assert_eq!(db.oldest().unwrap(), Some(at(40)));
+        assert_eq!(db.prune(retention, at(100)).unwrap(), 0);
+
+        let retention = Retention {
+            max_count: 10,
+            max_age: None,
+        };
+        assert_eq!(db.prune(retention, at(100)).unwrap(), 50);
+        assert_eq!(db.len().unwrap(), 10);
+        // The R-tree was pruned along with them
+        let filter = StrikeFilter {
+            bbox: Some("-1,-1,1,1".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            times(&db.query(&filter, None).unwrap()),
+            (90..100).collect::<Vec<_>>()
+        );
+        let located: usize = db
+            .conn
+            .query_row("SELECT count(*) FROM strike_locations", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(located, 10);
+    }
+}
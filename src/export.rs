@@ -0,0 +1,296 @@
+//! Writing strikes out for GIS tools: GeoJSON, CSV and KML.
+//!
+//! Strikes are written one at a time between a header and a footer, so a
+//! live feed can be exported as it arrives as well as the stored history.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::blitzortung::LightningStrike;
+use crate::utils::format_utc;
+
+pub const CSV_HEADER: &str = "time,lat,lon,alt,pol,mds,mcg,status,region,delay,lonc,latc,stations";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A FeatureCollection of points.
+    GeoJson,
+    Csv,
+    /// A Placemark per strike, the fields as ExtendedData.
+    Kml,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] =
+        [ExportFormat::GeoJson, ExportFormat::Csv, ExportFormat::Kml];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::GeoJson => "geojson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Kml => "kml",
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExportFormat::GeoJson => "GeoJSON",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Kml => "KML",
+        })
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ExportFormat::ALL
+            .into_iter()
+            .find(|format| format.extension() == s)
+            .ok_or_else(|| format!("unknown export format '{}'", s))
+    }
+}
+
+/// What comes before the first strike. `signals` adds a column for the
+/// station list to CSV.
+pub fn write_header(out: &mut impl Write, format: ExportFormat, signals: bool) -> io::Result<()> {
+    match format {
+        ExportFormat::GeoJson => write!(out, r#"{{"type":"FeatureCollection","features":["#),
+        ExportFormat::Csv if signals => writeln!(out, "{},sig", CSV_HEADER),
+        ExportFormat::Csv => writeln!(out, "{}", CSV_HEADER),
+        ExportFormat::Kml => write!(
+            out,
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                "\n",
+                r#"<kml xmlns="http://www.opengis.net/kml/2.2"><Document><name>Lightning strikes</name>"#,
+                "\n"
+            )
+        ),
+    }
+}
+
+/// One strike, the `index`th written. With `signals`, the stations that
+/// picked it up are included too.
+pub fn write_strike(
+    out: &mut impl Write,
+    format: ExportFormat,
+    strike: &LightningStrike,
+    index: usize,
+    signals: bool,
+) -> io::Result<()> {
+    let time = format_utc(strike.timestamp());
+    let delay = strike.delay.map(|d| d.to_string()).unwrap_or_default();
+    match format {
+        ExportFormat::GeoJson => {
+            let mut properties = json!({
+                "time": time,
+                "alt": strike.alt,
+                "pol": strike.pol,
+                "mds": strike.mds,
+                "mcg": strike.mcg,
+                "status": strike.status,
+                "region": strike.region,
+                "delay": strike.delay,
+                "lonc": strike.lonc,
+                "latc": strike.latc,
+                "stations": strike.sig.len(),
+            });
+            if signals {
+                properties["sig"] = json!(strike.sig);
+            }
+            let feature = json!({
+                "type": "Feature",
+                "geometry": {"type": "Point", "coordinates": [strike.lon, strike.lat]},
+                "properties": properties,
+            });
+            if index > 0 {
+                write!(out, ",")?;
+            }
+            writeln!(out)?;
+            serde_json::to_writer(&mut *out, &feature)?;
+            Ok(())
+        }
+        ExportFormat::Csv => {
+            write!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                time,
+                strike.lat,
+                strike.lon,
+                strike.alt,
+                strike.pol,
+                strike.mds,
+                strike.mcg,
+                strike.status,
+                strike.region,
+                delay,
+                strike.lonc,
+                strike.latc,
+                strike.sig.len()
+            )?;
+            if signals {
+                let sig = serde_json::to_string(&strike.sig)?;
+                write!(out, ",\"{}\"", sig.replace('"', "\"\""))?;
+            }
+            writeln!(out)
+        }
+        ExportFormat::Kml => {
+            write!(
+                out,
+                "<Placemark><name>{0}</name><TimeStamp><when>{0}</when></TimeStamp><ExtendedData>",
+                time
+            )?;
+            let mut fields = vec![
+                ("alt", strike.alt.to_string()),
+                ("pol", strike.pol.to_string()),
+                ("mds", strike.mds.to_string()),
+                ("mcg", strike.mcg.to_string()),
+                ("status", strike.status.to_string()),
+                ("region", strike.region.to_string()),
+                ("delay", delay),
+                ("lonc", strike.lonc.to_string()),
+                ("latc", strike.latc.to_string()),
+                ("stations", strike.sig.len().to_string()),
+            ];
+            if signals {
+                fields.push(("sig", serde_json::to_string(&strike.sig)?));
+            }
+            for (name, value) in fields {
+                write!(
+                    out,
+                    r#"<Data name="{}"><value>{}</value></Data>"#,
+                    name,
+                    xml_escape(&value)
+                )?;
+            }
+            writeln!(
+                out,
+                "</ExtendedData><Point><coordinates>{},{},{}</coordinates></Point></Placemark>",
+                strike.lon, strike.lat, strike.alt
+            )
+        }
+    }
+}
+
+/// What comes after the last strike.
+pub fn write_footer(out: &mut impl Write, format: ExportFormat) -> io::Result<()> {
+    match format {
+        ExportFormat::GeoJson => writeln!(out, "\n]}}"),
+        ExportFormat::Csv => Ok(()),
+        ExportFormat::Kml => writeln!(out, "</Document></kml>"),
+    }
+}
+
+/// Write a whole document of `strikes`. Returns how many there were.
+pub fn export<'a>(
+    out: &mut impl Write,
+    format: ExportFormat,
+    strikes: impl IntoIterator<Item = &'a LightningStrike>,
+    signals: bool,
+) -> io::Result<usize> {
+    write_header(out, format, signals)?;
+    let mut count = 0;
+    for strike in strikes {
+        write_strike(out, format, strike, count, signals)?;
+        count += 1;
+    }
+    write_footer(out, format)?;
+    out.flush()?;
+    Ok(count)
+}
+
+/// [`export`] to a new file at `path`.
+pub fn export_file<'a>(
+    path: &Path,
+    format: ExportFormat,
+    strikes: impl IntoIterator<Item = &'a LightningStrike>,
+    signals: bool,
+) -> io::Result<usize> {
+    let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+    export(&mut out, format, strikes, signals)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_fixture_records;
+
+    fn exported(format: ExportFormat, signals: bool) -> String {
+        let mut out = Vec::new();
+        let count = export(&mut out, format, &strike_fixture_records(), signals).unwrap();
+        assert_eq!(count, strike_fixture_records().len());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn geojson_is_a_feature_collection() {
+        let strikes = strike_fixture_records();
+        let collection: serde_json::Value =
+            serde_json::from_str(&exported(ExportFormat::GeoJson, true)).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), strikes.len());
+
+        let first = &features[0];
+        assert_eq!(first["geometry"]["coordinates"], json!([8.97965, 46.35573]));
+        assert_eq!(first["properties"]["time"], "2025-08-11T11:20:00.381Z");
+        assert_eq!(first["properties"]["delay"], 2.4);
+        assert_eq!(first["properties"]["sig"], json!(strikes[0].sig));
+
+        let collection: serde_json::Value =
+            serde_json::from_str(&exported(ExportFormat::GeoJson, false)).unwrap();
+        assert!(collection["features"][0]["properties"].get("sig").is_none());
+
+        let mut empty = Vec::new();
+        export(&mut empty, ExportFormat::GeoJson, [], false).unwrap();
+        let empty: serde_json::Value = serde_json::from_slice(&empty).unwrap();
+        assert_eq!(empty["features"], json!([]));
+    }
+
+    #[test]
+    fn csv_has_a_row_per_strike() {
+        let csv = exported(ExportFormat::Csv, false);
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        assert_eq!(
+            lines.next(),
+            Some("2025-08-11T11:20:00.381Z,46.35573,8.97965,0,1,5968,255,2,1,2.4,1,0,2")
+        );
+        assert_eq!(lines.count(), strike_fixture_records().len() - 1);
+
+        // The station list is one quoted field
+        let csv = exported(ExportFormat::Csv, true);
+        let row = csv.lines().nth(1).unwrap();
+        let (_, sig) = row.split_once(",\"").unwrap();
+        let sig = sig.strip_suffix('"').unwrap().replace("\"\"", "\"");
+        let sig: Vec<crate::blitzortung::SignalData> = serde_json::from_str(&sig).unwrap();
+        assert_eq!(sig, strike_fixture_records()[0].sig);
+    }
+
+    #[test]
+    fn kml_has_a_placemark_per_strike() {
+        let kml = exported(ExportFormat::Kml, true);
+        assert!(kml.starts_with("<?xml"));
+        assert!(kml.trim_end().ends_with("</Document></kml>"));
+        assert_eq!(
+            kml.matches("<Placemark>").count(),
+            strike_fixture_records().len()
+        );
+        assert!(kml.contains("<coordinates>8.97965,46.35573,0</coordinates>"));
+        assert!(kml.contains(r#"<Data name="sig"><value>[{&quot;sta&quot;:"#));
+        assert_eq!(xml_escape(r#"<a & "b">"#), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+}
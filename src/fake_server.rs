@@ -0,0 +1,235 @@
+//! A local stand-in for the Blitzortung WebSocket servers, so the transport,
+//! the decoder and the app can be tested without the network.
+
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
+
+use crate::blitzortung::{BLITZ_HANDSHAKE, LightningStrike, encode};
+
+/// Strike JSON captured in the shape Blitzortung sends, one per line.
+pub(crate) const STRIKE_FIXTURES: &str = include_str!("../fixtures/strikes.jsonl");
+
+pub(crate) fn strike_fixtures() -> Vec<String> {
+    STRIKE_FIXTURES.lines().map(str::to_string).collect()
+}
+
+/// [`strike_fixtures`], parsed.
+pub(crate) fn strike_fixture_records() -> Vec<LightningStrike> {
+    STRIKE_FIXTURES
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// A strike `secs` after the epoch at `lat`/`lon`, with nothing else set.
+pub(crate) fn strike_at(secs: u64, lat: f64, lon: f64) -> LightningStrike {
+    LightningStrike {
+        time: secs * 1_000_000_000,
+        lat,
+        lon,
+        alt: 0.0,
+        pol: 0,
+        mds: 0,
+        mcg: 0,
+        status: 0,
+        region: 0,
+        sig: Vec::new(),
+        delay: None,
+        lonc: 0,
+        latc: 0,
+    }
+}
+
+/// What the fake server sends once a client has shaken hands.
+#[derive(Debug, Clone)]
+pub(crate) struct FakeServerConfig {
+    /// Strike JSON to send, LZW-encoded, in order.
+    pub(crate) strikes: Vec<String>,
+    /// Pause before each strike frame.
+    pub(crate) interval: Duration,
+    /// Start over from the first strike after the last one.
+    pub(crate) repeat: bool,
+    /// Wait for `{"a":111}` before streaming.
+    pub(crate) require_handshake: bool,
+    /// Follow every n-th strike with a frame cut short and ending in a code
+    /// the dictionary does not have, so it cannot be decoded.
+    pub(crate) malformed_every: Option<usize>,
+    /// Follow every n-th strike with a frame that decodes to JSON which is
+    /// not a strike.
+    pub(crate) invalid_every: Option<usize>,
+    /// Follow every n-th strike with a ping.
+    pub(crate) ping_every: Option<usize>,
+    /// Follow every n-th strike with a binary frame.
+    pub(crate) binary_every: Option<usize>,
+    /// Drop the TCP connection, without a close frame, after n strikes.
+    pub(crate) close_after: Option<usize>,
+}
+
+impl Default for FakeServerConfig {
+    fn default() -> Self {
+        Self {
+            strikes: strike_fixtures(),
+            interval: Duration::from_millis(1),
+            repeat: false,
+            require_handshake: true,
+            malformed_every: None,
+            invalid_every: None,
+            ping_every: None,
+            binary_every: None,
+            close_after: None,
+        }
+    }
+}
+
+/// A fake Blitzortung server listening on a random local port. It runs on
+/// its own thread and stops with the test process.
+#[derive(Debug)]
+pub(crate) struct FakeServer {
+    pub(crate) url: String,
+    drop_tx: watch::Sender<u64>,
+    handshakes: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl FakeServer {
+    pub(crate) fn start(config: FakeServerConfig) -> Self {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (drop_tx, drop_rx) = watch::channel(0u64);
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let server_handshakes = handshakes.clone();
+        let server_connections = connections.clone();
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to create Tokio runtime");
+            rt.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                while let Ok((stream, _)) = listener.accept().await {
+                    server_connections.fetch_add(1, Ordering::SeqCst);
+                    let session = Session {
+                        config: config.clone(),
+                        drop_rx: drop_rx.clone(),
+                        handshakes: server_handshakes.clone(),
+                    };
+                    tokio::spawn(session.serve(stream));
+                }
+            });
+        });
+
+        Self {
+            url,
+            drop_tx,
+            handshakes,
+            connections,
+        }
+    }
+
+    /// Abruptly drop every open connection.
+    pub(crate) fn drop_connections(&self) {
+        self.drop_tx.send_modify(|generation| *generation += 1);
+    }
+
+    /// Number of handshakes received so far.
+    pub(crate) fn handshakes(&self) -> usize {
+        self.handshakes.load(Ordering::SeqCst)
+    }
+
+    /// Number of TCP connections accepted so far.
+    pub(crate) fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+struct Session {
+    config: FakeServerConfig,
+    drop_rx: watch::Receiver<u64>,
+    handshakes: Arc<AtomicUsize>,
+}
+
+impl Session {
+    async fn serve(mut self, stream: TcpStream) {
+        let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+            return;
+        };
+        // Only drops requested after this connection was accepted apply to it
+        self.drop_rx.mark_unchanged();
+
+        if self.config.require_handshake && !self.wait_for_handshake(&mut ws).await {
+            return;
+        }
+        self.handshakes.fetch_add(1, Ordering::SeqCst);
+
+        let mut sent = 0usize;
+        let count = self.config.strikes.len();
+        while count > 0 && (self.config.repeat || sent < count) {
+            tokio::select! {
+                _ = tokio::time::sleep(self.config.interval) => {}
+                _ = self.drop_rx.changed() => return,
+            }
+            if self.config.close_after == Some(sent) {
+                return;
+            }
+
+            let encoded = encode(&self.config.strikes[sent % count]);
+            sent += 1;
+            let mut frames = vec![Message::Text(encoded.clone())];
+            if every(self.config.malformed_every, sent) {
+                let half = encoded.chars().count() / 2;
+                let mut frame: String = encoded.chars().take(half).collect();
+                frame.push('\u{D7FF}');
+                frames.push(Message::Text(frame));
+            }
+            if every(self.config.invalid_every, sent) {
+                frames.push(Message::Text(encode(r#"{"time":"yesterday"}"#)));
+            }
+            if every(self.config.ping_every, sent) {
+                frames.push(Message::Ping(b"fake".to_vec()));
+            }
+            if every(self.config.binary_every, sent) {
+                frames.push(Message::Binary(vec![0xde, 0xad, 0xbe, 0xef]));
+            }
+            for frame in frames {
+                if ws.send(frame).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        // Out of strikes: keep the connection open until the client leaves or we are told to drop
+        loop {
+            tokio::select! {
+                msg = ws.next() => if !matches!(msg, Some(Ok(_))) { return },
+                _ = self.drop_rx.changed() => return,
+            }
+        }
+    }
+
+    async fn wait_for_handshake(&mut self, ws: &mut WebSocketStream<TcpStream>) -> bool {
+        loop {
+            tokio::select! {
+                msg = ws.next() => match msg {
+                    Some(Ok(Message::Binary(data))) if data == BLITZ_HANDSHAKE => return true,
+                    Some(Ok(Message::Text(text))) if text.as_bytes() == BLITZ_HANDSHAKE => return true,
+                    Some(Ok(_)) => {}
+                    _ => return false,
+                },
+                _ = self.drop_rx.changed() => return false,
+            }
+        }
+    }
+}
+
+fn every(n: Option<usize>, sent: usize) -> bool {
+    matches!(n, Some(n) if n > 0 && sent.is_multiple_of(n))
+}
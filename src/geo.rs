@@ -0,0 +1,149 @@
+//! Great-circle distances and bearings on a spherical Earth.
+//!
+//! A sphere is off by up to half a percent against the ellipsoid, far less
+//! than the few hundred metres Blitzortung locates strikes to.
+
+use serde::{Deserialize, Serialize};
+
+use crate::blitzortung::LightningStrike;
+
+/// Mean Earth radius (IUGG).
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// A point on the Earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Location {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self { lat, lon }
+    }
+
+    pub fn of(strike: &LightningStrike) -> Self {
+        Self::new(strike.lat, strike.lon)
+    }
+
+    /// Great-circle distance to `other`, by the haversine formula.
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+
+    /// Initial compass bearing towards `other`, in degrees from north in
+    /// `0.0..360.0`.
+    pub fn bearing_to(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlon = (other.lon - self.lon).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    /// Where you end up going `distance_km` from here, starting out on
+    /// `bearing` degrees.
+    pub fn destination(&self, bearing: f64, distance_km: f64) -> Location {
+        let lat1 = self.lat.to_radians();
+        let theta = bearing.to_radians();
+        let delta = distance_km / EARTH_RADIUS_KM;
+        let lat2 = (lat1.sin() * delta.cos() + lat1.cos() * delta.sin() * theta.cos()).asin();
+        let dlon =
+            (theta.sin() * delta.sin() * lat1.cos()).atan2(delta.cos() - lat1.sin() * lat2.sin());
+        Location::new(
+            lat2.to_degrees(),
+            (self.lon + dlon.to_degrees() + 540.0).rem_euclid(360.0) - 180.0,
+        )
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.4},{:.4}", self.lat, self.lon)
+    }
+}
+
+impl std::str::FromStr for Location {
+    type Err = String;
+
+    /// Parses `LAT,LON` in decimal degrees, e.g. `45.46,9.19`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid location '{}', expected LAT,LON", s);
+        let (lat, lon) = s.split_once(',').ok_or_else(invalid)?;
+        let lat: f64 = lat.trim().parse().map_err(|_| invalid())?;
+        let lon: f64 = lon.trim().parse().map_err(|_| invalid())?;
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(format!("location '{}' is out of range", s));
+        }
+        Ok(Location::new(lat, lon))
+    }
+}
+
+/// The 16-point compass name of `bearing`, e.g. `NNE`.
+pub fn compass_point(bearing: f64) -> &'static str {
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+    POINTS[((bearing.rem_euclid(360.0) / 22.5).round() as usize) % 16]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location {
+        lat: 51.5074,
+        lon: -0.1278,
+    };
+    const PARIS: Location = Location {
+        lat: 48.8566,
+        lon: 2.3522,
+    };
+
+    #[test]
+    fn distances_and_bearings() {
+        let km = LONDON.distance_km(&PARIS);
+        assert!((km - 343.5).abs() < 1.0, "{km}");
+        assert_eq!(PARIS.distance_km(&PARIS), 0.0);
+        let bearing = LONDON.bearing_to(&PARIS);
+        assert!((bearing - 148.1).abs() < 0.5, "{bearing}");
+        assert_eq!(compass_point(bearing), "SSE");
+
+        // Half way round the world, across the antimeridian
+        let far = Location::new(0.0, 179.0).distance_km(&Location::new(0.0, -179.0));
+        assert!((far - 222.4).abs() < 0.5, "{far}");
+        let antipode = Location::new(10.0, 20.0).distance_km(&Location::new(-10.0, -160.0));
+        assert!((antipode - std::f64::consts::PI * EARTH_RADIUS_KM).abs() < 0.01);
+    }
+
+    #[test]
+    fn destinations_invert_distance_and_bearing() {
+        let there = LONDON.destination(LONDON.bearing_to(&PARIS), LONDON.distance_km(&PARIS));
+        assert!(there.distance_km(&PARIS) < 0.01);
+
+        let east = Location::new(0.0, 179.5).destination(90.0, 111.2);
+        assert!((east.lon + 179.5).abs() < 0.01, "{east}");
+    }
+
+    #[test]
+    fn compass_points_and_parsing() {
+        assert_eq!(compass_point(0.0), "N");
+        assert_eq!(compass_point(359.0), "N");
+        assert_eq!(compass_point(45.0), "NE");
+        assert_eq!(compass_point(260.0), "W");
+
+        assert_eq!("45.46, 9.19".parse(), Ok(Location::new(45.46, 9.19)));
+        assert!("45.46".parse::<Location>().is_err());
+        assert!("91,0".parse::<Location>().is_err());
+        let home = Location::new(-33.8688, 151.2093);
+        assert_eq!(
+            home.to_string().parse::<Location>().map(|l| l.lat),
+            Ok(-33.8688)
+        );
+    }
+}
@@ -0,0 +1,662 @@
+//! `--headless`: the Blitzortung pipeline without any UI, streaming one
+//! strike per line to stdout. Runs wherever tokio does, Linux servers
+//! included.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::areas::StrikeFilter;
+use crate::blitzortung::{BLITZSERVERS, LightningStrike};
+use crate::client::{BlitzEvent, BlitzortungClient};
+use crate::database::{self, DatabaseError, StrikeDatabase, StrikeWriter};
+use crate::export::{self, ExportFormat};
+use crate::logging::{self, LogConfig};
+use crate::recording::ReplaySpeed;
+use crate::utils::{format_utc, parse_utc};
+use crate::websocket::Backoff;
+use tracing::{info, warn};
+
+const USAGE: &str = "\
+Usage: popuppp --headless [options]
+
+Streams Blitzortung lightning strikes to stdout, one per line.
+
+Options:
+  --format <format>          json, csv, text, geojson or kml (default: text).
+                             GeoJSON and KML are closed off when the stream
+                             ends, at --limit or the end of a --replay
+  --sig                      Include the station list in csv, geojson and kml
+  --bbox <W,S,E,N>           Only strikes inside this box, in degrees. W > E
+                             wraps around the antimeridian.
+  --area <area>              Only strikes inside <area>; repeat to allow several
+  --since <time>             Only strikes at or after <time>
+  --until <time>             Only strikes before <time>
+  --limit <n>                Exit after printing <n> strikes
+  --server <url>             Connect to <url> instead of the Blitzortung
+                             servers; repeat to fail over between several
+  --replay <file>            Play back a recording instead of connecting
+  --speed <1x|10x|max>       Replay speed (default: 1x)
+  --record <file>            Record the raw session to <file>
+  --db <file>                Also store every strike in the SQLite database
+                             <file>, keeping the last two days
+  --history                  Print what --db holds instead of reading a feed
+  --log <filter>             Log filter, e.g. debug or info,popuppp::websocket=trace
+                             (default: $RUST_LOG or info). Logs go to stderr.
+  --log-dir <dir>            Also log to daily-rotated files in <dir>
+  -h, --help                 Show this help
+
+<area> is `box W,S,E,N`, `circle LAT,LON KM` or `polygon LAT,LON LAT,LON ...`,
+quoted as one argument.
+
+<time> is an RFC 3339 UTC timestamp (2025-08-11T11:20:00Z), Unix seconds
+(1754911200) or an age relative to now (90s, 15m, 2h, 1d).
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Text,
+    GeoJson,
+    Kml,
+}
+
+impl OutputFormat {
+    /// The export format that writes this one, for those that are
+    /// documents rather than a line per strike.
+    fn export(self) -> Option<ExportFormat> {
+        match self {
+            OutputFormat::Csv => Some(ExportFormat::Csv),
+            OutputFormat::GeoJson => Some(ExportFormat::GeoJson),
+            OutputFormat::Kml => Some(ExportFormat::Kml),
+            OutputFormat::Json | OutputFormat::Text => None,
+        }
+    }
+}
+
+/// Where strikes come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Live {
+        servers: Vec<String>,
+    },
+    Replay {
+        path: PathBuf,
+        speed: ReplaySpeed,
+    },
+    /// The strikes stored in a database.
+    History {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub format: OutputFormat,
+    pub filter: StrikeFilter,
+    pub limit: Option<usize>,
+    pub source: Source,
+    pub record: Option<PathBuf>,
+    /// Store every strike here.
+    pub database: Option<PathBuf>,
+    pub log: LogConfig,
+    /// Include the station list, in the formats that can.
+    pub signals: bool,
+}
+
+impl Options {
+    /// Parse the command line, minus the program name. Relative times are
+    /// resolved against `now`.
+    pub fn parse(args: &[String], now: SystemTime) -> Result<Self, String> {
+        let mut format = OutputFormat::Text;
+        let mut filter = StrikeFilter::default();
+        let mut limit = None;
+        let mut servers = Vec::new();
+        let mut replay = None;
+        let mut speed = None;
+        let mut record = None;
+        let mut database = None;
+        let mut history = false;
+        let mut log = LogConfig::default();
+        let mut signals = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--headless" => {}
+                "--format" => {
+                    format = match value()?.as_str() {
+                        "json" => OutputFormat::Json,
+                        "csv" => OutputFormat::Csv,
+                        "text" => OutputFormat::Text,
+                        "geojson" => OutputFormat::GeoJson,
+                        "kml" => OutputFormat::Kml,
+                        other => return Err(format!("unknown format '{}'", other)),
+                    }
+                }
+                "--bbox" => filter.bbox = Some(value()?.parse()?),
+                "--area" => filter.areas.push(value()?.parse()?),
+                "--since" => filter.since = Some(parse_time(value()?, now)?),
+                "--until" => filter.until = Some(parse_time(value()?, now)?),
+                "--limit" => {
+                    let n = value()?;
+                    limit = Some(n.parse().map_err(|_| format!("invalid limit '{}'", n))?);
+                }
+                "--server" => servers.push(value()?.clone()),
+                "--replay" => replay = Some(PathBuf::from(value()?)),
+                "--speed" => speed = Some(value()?.parse()?),
+                "--record" => record = Some(PathBuf::from(value()?)),
+                "--db" => database = Some(PathBuf::from(value()?)),
+                "--history" => history = true,
+                "--log" => log.filter = value()?.clone(),
+                "--log-dir" => log.file = Some(PathBuf::from(value()?)),
+                "--sig" => signals = true,
+                other => return Err(format!("unknown option '{}'", other)),
+            }
+        }
+
+        let source = match replay {
+            _ if history && (replay.is_some() || !servers.is_empty()) => {
+                return Err("--history cannot be combined with --replay or --server".to_string());
+            }
+            _ if history => Source::History {
+                path: database
+                    .take()
+                    .ok_or_else(|| "--history needs --db".to_string())?,
+            },
+            Some(_) if !servers.is_empty() => {
+                return Err("--replay and --server cannot be combined".to_string());
+            }
+            Some(path) => Source::Replay {
+                path,
+                speed: speed.unwrap_or_default(),
+            },
+            None if speed.is_some() => return Err("--speed needs --replay".to_string()),
+            None if servers.is_empty() => Source::Live {
+                servers: BLITZSERVERS.iter().map(|s| s.to_string()).collect(),
+            },
+            None => Source::Live { servers },
+        };
+
+        Ok(Self {
+            format,
+            filter,
+            limit,
+            source,
+            record,
+            database,
+            log,
+            signals,
+        })
+    }
+}
+
+/// Parse a `--since`/`--until` value: RFC 3339, Unix seconds or an age.
+fn parse_time(s: &str, now: SystemTime) -> Result<SystemTime, String> {
+    let invalid = || format!("invalid time '{}'", s);
+    if let Some(time) = parse_utc(s) {
+        return Ok(time);
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs)
+            .map(|d| UNIX_EPOCH + d)
+            .map_err(|_| invalid());
+    }
+
+    let unit = match s.chars().last() {
+        Some('s') => 1.0,
+        Some('m') => 60.0,
+        Some('h') => 3600.0,
+        Some('d') => 86_400.0,
+        _ => return Err(invalid()),
+    };
+    let amount: f64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+    let age = Duration::try_from_secs_f64(amount * unit).map_err(|_| invalid())?;
+    now.checked_sub(age).ok_or_else(invalid)
+}
+
+pub fn write_strike(
+    out: &mut impl Write,
+    format: OutputFormat,
+    strike: &LightningStrike,
+    index: usize,
+    signals: bool,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Csv => export::write_strike(out, ExportFormat::Csv, strike, index, signals),
+        OutputFormat::GeoJson => {
+            export::write_strike(out, ExportFormat::GeoJson, strike, index, signals)
+        }
+        OutputFormat::Kml => export::write_strike(out, ExportFormat::Kml, strike, index, signals),
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *out, strike)?;
+            writeln!(out)
+        }
+        OutputFormat::Text => writeln!(
+            out,
+            "{}  Lat: {:.4}°, Lon: {:.4}°, Alt: {:.0}m, {} stations",
+            format_utc(strike.timestamp()),
+            strike.lat,
+            strike.lon,
+            strike.alt,
+            strike.sig.len()
+        ),
+    }
+}
+
+/// Stream strikes to `out` until the source runs dry or `limit` is reached.
+/// Status and errors go to the log, never to `out`.
+pub fn run(options: &Options, out: &mut impl Write) -> io::Result<()> {
+    let client = BlitzortungClient::new();
+    if let Some(path) = &options.record {
+        client.start_recording(path.clone());
+    }
+    if let Some(path) = &options.database {
+        let writer = StrikeWriter::open(path, database::DEFAULT_RETENTION)
+            .map_err(|e| database_error("open", path, e))?;
+        client.start_persisting(writer);
+    }
+    match &options.source {
+        Source::Live { servers } => {
+            let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+            client.connect_to(&servers, Backoff::default());
+        }
+        Source::Replay { path, speed } => client.replay(path.clone(), *speed),
+        Source::History { path } => return print_history(options, path, out),
+    }
+    let replaying = matches!(options.source, Source::Replay { .. });
+
+    if let Some(format) = options.format.export() {
+        export::write_header(out, format, options.signals)?;
+    }
+
+    let mut printed = 0;
+    while options.limit.is_none_or(|limit| printed < limit) {
+        let Ok(event) = client.recv_event_blocking() else {
+            break;
+        };
+        match event {
+            BlitzEvent::Strike(strike) => {
+                if options.filter.matches(&strike) {
+                    write_strike(out, options.format, &strike, printed, options.signals)?;
+                    out.flush()?;
+                    printed += 1;
+                }
+            }
+            BlitzEvent::Connected { url } => info!(%url, "connected"),
+            BlitzEvent::ReplayStarted { path } => info!(path = %path.display(), "replaying"),
+            BlitzEvent::ReplayFailed { path, error } => {
+                return Err(io::Error::other(format!(
+                    "cannot replay {}: {}",
+                    path.display(),
+                    error
+                )));
+            }
+            BlitzEvent::ConnectFailed { url, error } => {
+                warn!(%url, %error, "connecting failed");
+            }
+            BlitzEvent::Disconnected => {
+                info!("disconnected");
+                // A finished replay does not come back
+                if replaying {
+                    break;
+                }
+            }
+            BlitzEvent::Reconnecting { attempt, url, .. } => {
+                info!(%url, attempt, "reconnecting");
+            }
+            BlitzEvent::DecodeFailed { error, .. } => {
+                warn!(%error, "undecodable frame");
+            }
+            BlitzEvent::InvalidStrike { error, .. } => {
+                warn!(%error, "not a strike");
+            }
+            BlitzEvent::Truncated { decoded } => {
+                warn!(characters = decoded.chars().count(), "truncated message");
+            }
+            BlitzEvent::ProtocolError(error) => warn!(%error, "protocol error"),
+        }
+    }
+
+    client.disconnect();
+    if let Some(format) = options.format.export() {
+        export::write_footer(out, format)?;
+    }
+    out.flush()
+}
+
+/// Print the strikes stored at `path` that pass the filter, oldest first.
+fn print_history(options: &Options, path: &Path, out: &mut impl Write) -> io::Result<()> {
+    let db = StrikeDatabase::open(path).map_err(|e| database_error("read", path, e))?;
+    let strikes = db
+        .query(&options.filter, options.limit)
+        .map_err(|e| database_error("read", path, e.into()))?;
+    info!(count = strikes.len(), path = %path.display(), "printing history");
+
+    if let Some(format) = options.format.export() {
+        export::write_header(out, format, options.signals)?;
+    }
+    for (index, strike) in strikes.iter().enumerate() {
+        write_strike(out, options.format, strike, index, options.signals)?;
+    }
+    if let Some(format) = options.format.export() {
+        export::write_footer(out, format)?;
+    }
+    out.flush()
+}
+
+/// An io::Error for a database that could not be used, of the same kind as
+/// the one underneath if there is one.
+fn database_error(action: &str, path: &Path, error: DatabaseError) -> io::Error {
+    let kind = match &error {
+        DatabaseError::Io(e) => e.kind(),
+        DatabaseError::Sqlite(_) => io::ErrorKind::Other,
+        DatabaseError::NewerSchema { .. } => io::ErrorKind::Unsupported,
+    };
+    io::Error::new(
+        kind,
+        format!("cannot {} {}: {}", action, path.display(), error),
+    )
+}
+
+/// Entry point for `--headless`.
+pub fn main(args: &[String]) -> ExitCode {
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let options = match Options::parse(args, SystemTime::now()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Err(e) = logging::init(options.log.clone()) {
+        eprintln!("error: {}", e);
+        return ExitCode::from(2);
+    }
+
+    match run(&options, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Whoever was reading has had enough, e.g. `| head`
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::areas::BoundingBox;
+    use crate::export::CSV_HEADER;
+    use crate::fake_server::{
+        FakeServer, FakeServerConfig, STRIKE_FIXTURES, strike_fixture_records, strike_fixtures,
+    };
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    /// A strike line as JSON, with numbers in the types we serialize them as.
+    fn strike_json(line: &str) -> serde_json::Value {
+        serde_json::to_value(serde_json::from_str::<LightningStrike>(line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_754_911_200);
+        let options = Options::parse(
+            &args("--headless --format csv --bbox -10,35,30,60 --since 15m --until 1754911200 --limit 3"),
+            now,
+        )
+        .unwrap();
+        assert_eq!(options.format, OutputFormat::Csv);
+        assert_eq!(options.limit, Some(3));
+        assert_eq!(
+            options.filter,
+            StrikeFilter {
+                bbox: Some(BoundingBox {
+                    west: -10.0,
+                    south: 35.0,
+                    east: 30.0,
+                    north: 60.0
+                }),
+                since: Some(now - Duration::from_secs(900)),
+                until: Some(now),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            options.source,
+            Source::Live {
+                servers: BLITZSERVERS.iter().map(|s| s.to_string()).collect()
+            }
+        );
+
+        let options = Options::parse(
+            &args("--replay storm.blzrec --speed max --since 2025-08-11T11:20:00Z --log debug"),
+            now,
+        )
+        .unwrap();
+        assert_eq!(options.format, OutputFormat::Text);
+        assert_eq!(options.filter.since, Some(now));
+        assert_eq!(options.log.filter, "debug");
+        assert_eq!(options.log.file, None);
+        assert_eq!(
+            options.source,
+            Source::Replay {
+                path: "storm.blzrec".into(),
+                speed: ReplaySpeed::Max
+            }
+        );
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        let now = SystemTime::now();
+        for bad in [
+            "--format xml",
+            "--bbox 1,2,3",
+            "--bbox 0,60,10,50",
+            "--bbox 0,0,200,10",
+            "--since yesterday",
+            "--limit",
+            "--speed 2x",
+            "--replay a --server ws://b",
+            "--history",
+            "--db a --history --replay b",
+            "--log",
+            "--verbose",
+        ] {
+            assert!(Options::parse(&args(bad), now).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn strikes_are_filtered() {
+        let strikes = strike_fixture_records();
+        let europe = StrikeFilter {
+            bbox: Some("-10,35,30,60".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(europe.matches(&strikes[0]));
+        // Texas
+        assert!(!europe.matches(&strikes[2]));
+
+        let mut args = args("--area");
+        args.push("circle 29.5,-98.5 500".to_string());
+        let texas = Options::parse(&args, UNIX_EPOCH).unwrap().filter;
+        assert!(!texas.matches(&strikes[0]));
+        assert!(texas.matches(&strikes[2]));
+
+        let pacific: BoundingBox = "170,-60,-120,60".parse().unwrap();
+        assert!(pacific.contains(0.0, 179.0));
+        assert!(pacific.contains(0.0, -150.0));
+        assert!(!pacific.contains(0.0, 0.0));
+
+        let at = strikes[1].timestamp();
+        let window = StrikeFilter {
+            since: Some(at),
+            until: Some(strikes[2].timestamp()),
+            ..Default::default()
+        };
+        let matching: Vec<_> = strikes.iter().filter(|s| window.matches(s)).collect();
+        assert_eq!(matching.len(), 1);
+        assert_eq!(matching[0].timestamp(), at);
+    }
+
+    #[test]
+    fn strikes_are_formatted() {
+        let strike = &strike_fixture_records()[0];
+        let format = |format| {
+            let mut out = Vec::new();
+            write_strike(&mut out, format, strike, 0, false).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            strike_json(&format(OutputFormat::Json)),
+            strike_json(STRIKE_FIXTURES.lines().next().unwrap())
+        );
+
+        let csv = format(OutputFormat::Csv);
+        assert_eq!(
+            csv,
+            "2025-08-11T11:20:00.381Z,46.35573,8.97965,0,1,5968,255,2,1,2.4,1,0,2\n"
+        );
+        assert_eq!(csv.split(',').count(), CSV_HEADER.split(',').count());
+
+        assert_eq!(
+            format(OutputFormat::Text),
+            "2025-08-11T11:20:00.381Z  Lat: 46.3557°, Lon: 8.9796°, Alt: 0m, 2 stations\n"
+        );
+    }
+
+    #[test]
+    fn streams_from_a_server_until_the_limit() {
+        let server = FakeServer::start(FakeServerConfig {
+            malformed_every: Some(2),
+            ..Default::default()
+        });
+        let options = Options::parse(
+            &args(&format!("--format json --limit 5 --server {}", server.url)),
+            SystemTime::now(),
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        run(&options, &mut out).unwrap();
+        let lines: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(strike_json)
+            .collect();
+        let expected: Vec<_> = strike_fixtures()[..5]
+            .iter()
+            .map(|line| strike_json(line))
+            .collect();
+        assert_eq!(lines, expected);
+    }
+
+    /// A recording of all the fixtures, in `dir`.
+    fn record_fixtures(dir: &std::path::Path) -> PathBuf {
+        let path = dir.join("storm.blzrec");
+        let mut recorder = crate::recording::Recorder::create(&path).unwrap();
+        for line in strike_fixtures() {
+            recorder.record_text(&line).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn replays_can_be_exported_as_geojson() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = record_fixtures(dir.path());
+        let options = Options::parse(
+            &args(&format!(
+                "--format geojson --sig --speed max --since 2025-08-11T11:20:01Z --replay {}",
+                path.display()
+            )),
+            SystemTime::now(),
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        run(&options, &mut out).unwrap();
+        let collection: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), strike_fixture_records().len() - 1);
+        assert!(features[0]["properties"]["sig"].is_array());
+    }
+
+    #[test]
+    fn strikes_are_stored_and_printed_from_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = record_fixtures(dir.path());
+        let db = dir.path().join("strikes.sqlite");
+        let options = Options::parse(
+            &args(&format!(
+                "--format json --speed max --replay {} --db {}",
+                recording.display(),
+                db.display()
+            )),
+            SystemTime::now(),
+        )
+        .unwrap();
+        run(&options, &mut io::sink()).unwrap();
+
+        // Texas, from the database, after the feed is long gone
+        let mut args = args(&format!(
+            "--history --db {} --format json --area",
+            db.display()
+        ));
+        args.push("circle 29.5,-98.5 500".to_string());
+        let options = Options::parse(&args, SystemTime::now()).unwrap();
+        assert_eq!(options.source, Source::History { path: db });
+        let mut out = Vec::new();
+        run(&options, &mut out).unwrap();
+        let printed: Vec<LightningStrike> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(printed, [strike_fixture_records()[2].clone()]);
+    }
+
+    #[test]
+    fn replays_end_with_the_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = record_fixtures(dir.path());
+
+        let options = Options::parse(
+            &args(&format!(
+                "--format csv --speed max --bbox -10,35,30,60 --replay {}",
+                path.display()
+            )),
+            SystemTime::now(),
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        run(&options, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut lines = out.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        let europe = strike_fixture_records()
+            .iter()
+            .filter(|s| options.filter.matches(s))
+            .count();
+        assert!(europe > 0);
+        assert_eq!(lines.count(), europe);
+
+        let missing = Options::parse(&args("--replay /nonexistent"), SystemTime::now()).unwrap();
+        assert!(run(&missing, &mut Vec::new()).is_err());
+    }
+}
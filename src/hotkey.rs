@@ -1,23 +1,32 @@
-use crate::trrpy::TrrpyApp;
 use crate::ui::EguiView;
-use crate::utils::*;
 use objc2::rc::Retained;
 use objc2::runtime::{AnyClass, AnyObject};
 use objc2::{DefinedClass, MainThreadMarker, MainThreadOnly, define_class, msg_send, sel};
 use objc2_app_kit::{
-    NSApplication, NSApplicationDelegate, NSBackingStoreType, NSView, NSWindow, NSWindowDelegate,
-    NSWindowStyleMask, NSWorkspace,
+    NSApplication, NSApplicationDelegate, NSBackingStoreType, NSScreen, NSView, NSWindow,
+    NSWindowDelegate, NSWindowStyleMask, NSWorkspace,
 };
 use objc2_foundation::{NSNotification, NSObject, NSObjectProtocol, NSPoint, NSRect, NSSize};
+use popuppp::accelerator::Accelerator;
+use popuppp::config::{self, Config, ConfigWatcher, WindowConfig};
+use popuppp::settings::WindowFrame;
+use popuppp::trrpy::TrrpyApp;
 use std::cell::RefMut;
 use std::ffi::{CStr, c_int, c_uint, c_void};
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicPtr, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tracing::{debug, error, info, warn};
 
 // Global reference to AppDelegate for hotkey dispatching
 pub(crate) static APP_DELEGATE: AtomicPtr<AppDelegate> = AtomicPtr::new(std::ptr::null_mut());
 
 static mut EVENT_TAP: *mut c_void = std::ptr::null_mut();
 
+// The watched config.toml, and the hotkey it sets for the event tap
+static CONFIG: Mutex<Option<ConfigWatcher>> = Mutex::new(None);
+static HOTKEY_KEYCODE: AtomicU16 = AtomicU16::new(config::DEFAULT_HOTKEY.keycode);
+static HOTKEY_MODIFIERS: AtomicU64 = AtomicU64::new(config::DEFAULT_HOTKEY.modifiers);
+
 // --- CoreGraphics FFI for global hotkey registration ---
 #[link(name = "CoreGraphics", kind = "framework")]
 #[link(name = "CoreFoundation", kind = "framework")]
@@ -61,10 +70,6 @@ const K_CG_EVENT_TAP_OPTION_DEFAULT: c_uint = 0;
 const K_CG_EVENT_KEY_DOWN: c_uint = 10;
 const K_CG_KEYCODE_FIELD: c_uint = 9;
 
-// Modifier flags
-const K_CG_EVENT_FLAG_MASK_COMMAND: u64 = 0x100000;
-const K_CG_EVENT_FLAG_MASK_SHIFT: u64 = 0x20000;
-
 // Helper functions
 fn egui_app_from_window(window: &NSWindow) -> Option<RefMut<'_, TrrpyApp>> {
     if let Some(content_view) = window.contentView() {
@@ -77,6 +82,71 @@ fn egui_app_from_window(window: &NSWindow) -> Option<RefMut<'_, TrrpyApp>> {
     None
 }
 
+/// Tell the app where the window is, so it comes back there next time.
+pub(crate) fn remember_frame(window: &NSWindow, app: &mut TrrpyApp) {
+    let frame = window.frame();
+    app.set_window_frame(WindowFrame {
+        x: frame.origin.x,
+        y: frame.origin.y,
+        width: frame.size.width,
+        height: frame.size.height,
+    });
+}
+
+fn set_hotkey(hotkey: &Accelerator) {
+    HOTKEY_KEYCODE.store(hotkey.keycode, Ordering::SeqCst);
+    HOTKEY_MODIFIERS.store(hotkey.modifiers, Ordering::SeqCst);
+    info!("Press {} to toggle the popup window", hotkey);
+}
+
+/// Load config.toml and start watching it. A broken file leaves the
+/// defaults in effect until it is fixed.
+fn load_config() {
+    let Some(path) = config::default_path() else {
+        warn!("no config directory, using the default config");
+        return;
+    };
+    let mut watcher = ConfigWatcher::new(path);
+    match watcher.poll() {
+        Some(Ok(_)) => info!(path = %watcher.path().display(), "loaded config"),
+        Some(Err(error)) => error!(%error, "invalid config, using the defaults"),
+        None => debug!(path = %watcher.path().display(), "no config, using the defaults"),
+    }
+    set_hotkey(&watcher.config().hotkey);
+    *lock_config() = Some(watcher);
+}
+
+/// A panic elsewhere while holding the lock leaves the watcher as usable
+/// as it was.
+fn lock_config() -> MutexGuard<'static, Option<ConfigWatcher>> {
+    CONFIG.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Check config.toml for changes. Returns the window config that was in
+/// effect and the outcome of loading the new file, with the lock already
+/// released so applying it cannot come back for it.
+fn poll_config() -> Option<(WindowConfig, Result<Config, String>)> {
+    let mut watcher = lock_config();
+    let watcher = watcher.as_mut()?;
+    let old = watcher.config().window.clone();
+    let result = watcher.poll()?.cloned();
+    if result.is_ok() {
+        info!(path = %watcher.path().display(), "reloaded config");
+    }
+    Some((old, result))
+}
+
+/// The config in effect, and what is wrong with the file if anything.
+fn current_config() -> (Config, Option<String>) {
+    match lock_config().as_ref() {
+        Some(watcher) => (
+            watcher.config().clone(),
+            watcher.error().map(str::to_string),
+        ),
+        None => (Config::default(), None),
+    }
+}
+
 fn store_app_pid(window: &NSWindow) {
     unsafe {
         let workspace = NSWorkspace::sharedWorkspace();
@@ -85,7 +155,7 @@ fn store_app_pid(window: &NSWindow) {
             let pid: i32 = msg_send![&*app_obj, processIdentifier];
             if let Some(mut app) = egui_app_from_window(window) {
                 app.prev_pid = Some(pid as u32);
-                ll(&format!("🔙 Stored previous app PID in TrrpyApp: {}", pid));
+                debug!("Stored previous app PID in TrrpyApp: {}", pid);
             }
         }
     }
@@ -119,12 +189,14 @@ extern "C" fn event_tap_callback(
             let keycode = CGEventGetIntegerValueField(event, K_CG_KEYCODE_FIELD);
             let flags = CGEventGetFlags(event);
 
-            // Check for Cmd+Shift+K (keycode 40)
-            if keycode == 40
-                && (flags & K_CG_EVENT_FLAG_MASK_COMMAND) != 0
-                && (flags & K_CG_EVENT_FLAG_MASK_SHIFT) != 0
-            {
-                ll("🎯 HOTKEY PRESSED! Toggling window visibility...");
+            // Check for the configured hotkey, Cmd+Shift+K by default, held
+            // with exactly its modifiers
+            let hotkey = Accelerator {
+                keycode: HOTKEY_KEYCODE.load(Ordering::SeqCst),
+                modifiers: HOTKEY_MODIFIERS.load(Ordering::SeqCst),
+            };
+            if hotkey.matches(keycode as u16, flags) {
+                info!("HOTKEY PRESSED! Toggling window visibility...");
 
                 // Dispatch to main thread
                 let delegate_ptr = APP_DELEGATE.load(Ordering::SeqCst);
@@ -133,7 +205,7 @@ extern "C" fn event_tap_callback(
                     let selector = sel!(showEguiWindow);
                     let _: () = msg_send![delegate, performSelectorOnMainThread: selector, withObject: std::ptr::null::<NSObject>(), waitUntilDone:false];
                 } else {
-                    ll("❌ No delegate available for dispatch");
+                    error!("No delegate available for dispatch");
                 }
 
                 // Return null to consume the event (prevent it from propagating)
@@ -147,7 +219,7 @@ extern "C" fn event_tap_callback(
 }
 
 pub(crate) unsafe fn register_hotkey() {
-    ll("🪧 Setting up CGEventTap for global hotkey...");
+    info!("Setting up CGEventTap for global hotkey...");
 
     // Create event mask for key down events
     let event_mask = 1u64 << K_CG_EVENT_KEY_DOWN;
@@ -164,21 +236,23 @@ pub(crate) unsafe fn register_hotkey() {
     };
 
     if event_tap.is_null() {
-        ll("🪧 Failed to create event tap! You may need to grant Accessibility permissions:");
-        ll("\tSystem Settings > Privacy & Security > Accessibility");
-        ll("\tAdd your terminal app or the popup binary to the list.");
+        error!(
+            "Failed to create event tap! You may need to grant Accessibility permissions: \
+             System Settings > Privacy & Security > Accessibility, then add your terminal app \
+             or the popup binary to the list."
+        );
         return;
     }
 
     EVENT_TAP = event_tap;
-    ll("✅ Event tap created successfully!");
+    info!("Event tap created successfully!");
 
     // Create a run loop source for the event tap
     let run_loop_source =
         unsafe { CFMachPortCreateRunLoopSource(std::ptr::null_mut(), event_tap, 0) };
 
     if run_loop_source.is_null() {
-        ll("❌ Failed to create run loop source!");
+        error!("Failed to create run loop source!");
         return;
     }
 
@@ -193,8 +267,29 @@ pub(crate) unsafe fn register_hotkey() {
         CGEventTapEnable(event_tap, true);
     }
 
-    ll("🎯 Global hotkey registered successfully!");
-    ll("🪧 Press Cmd+Shift+K to toggle the popup window");
+    info!("Global hotkey registered successfully!");
+}
+
+/// Poll the app once a second while the popup is hidden, so strikes keep
+/// being collected and proximity alerts can bring it up, and pick up
+/// changes to config.toml.
+fn start_hidden_polling() {
+    std::thread::spawn(|| {
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            let delegate_ptr = APP_DELEGATE.load(Ordering::SeqCst);
+            if delegate_ptr.is_null() {
+                continue;
+            }
+            unsafe {
+                let delegate = &*delegate_ptr;
+                let selector = sel!(pollHiddenPopup);
+                let _: () = msg_send![delegate, performSelectorOnMainThread: selector, withObject: std::ptr::null::<NSObject>(), waitUntilDone:false];
+                let selector = sel!(reloadConfig);
+                let _: () = msg_send![delegate, performSelectorOnMainThread: selector, withObject: std::ptr::null::<NSObject>(), waitUntilDone:false];
+            }
+        }
+    });
 }
 
 // Custom NSWindow subclass to allow borderless window to become key/main window
@@ -228,10 +323,11 @@ define_class!(
     unsafe impl NSWindowDelegate for WindowDelegate {
         #[unsafe(method(windowShouldClose:))]
         fn window_should_close(&self, sender: &NSWindow) -> bool {
-            ll("🚪 Window close requested - hiding window...");
+            debug!("Window close requested - hiding window...");
             sender.orderOut(None);
             // Notify app that popup is now hidden
             if let Some(mut app) = egui_app_from_window(sender) {
+                remember_frame(sender, &mut app);
                 app.set_popup_visible(false);
             }
             false // Don't actually close the window, just hide it
@@ -239,7 +335,7 @@ define_class!(
 
         #[unsafe(method(windowWillClose:))]
         fn window_will_close(&self, _notification: &NSNotification) {
-            ll("🪟 Window will close notification");
+            debug!("Window will close notification");
         }
     }
 );
@@ -271,15 +367,17 @@ define_class!(
     unsafe impl NSApplicationDelegate for AppDelegate {
         #[unsafe(method(applicationDidFinishLaunching:))]
         fn did_finish_launching(&self, notification: &NSNotification) {
-            ll("🪧 Did finish launching!");
-            ll(&format!("🪧 Process ID: {}", std::process::id()));
+            info!("Did finish launching!");
+            info!("Process ID: {}", std::process::id());
             // Do something with the notification
             dbg!(notification);
 
-            // Register the global hotkey (Cmd+Shift+K)
+            // Register the global hotkey, as config.toml has it
+            load_config();
             unsafe {
                 register_hotkey();
             }
+            start_hidden_polling();
         }
 
         #[unsafe(method(applicationShouldTerminate:))]
@@ -287,7 +385,7 @@ define_class!(
             &self,
             _sender: &NSApplication,
         ) -> objc2_app_kit::NSApplicationTerminateReply {
-            ll("🪧 Application should terminate - hiding window and allowing exit...");
+            info!("Application should terminate - hiding window and allowing exit...");
 
             // Hide the window if it exists
             if let Some(ref window) = self.ivars().window {
@@ -299,12 +397,12 @@ define_class!(
 
         #[unsafe(method(applicationWillTerminate:))]
         fn will_terminate(&self, _notification: &NSNotification) {
-            ll("🪧 Application will terminate - final cleanup...");
+            info!("Application will terminate - final cleanup...");
 
             // Allow any pending operations to complete
             std::thread::sleep(std::time::Duration::from_millis(50));
 
-            ll("✅ Final cleanup complete - goodbye!");
+            info!("Final cleanup complete - goodbye!");
         }
 
         #[unsafe(method(showEguiWindow))]
@@ -315,17 +413,18 @@ define_class!(
             // Check if we already have a window
             if let Some(ref window) = self.ivars().window {
                 if (&*window).isVisible() {
-                    ll("🙈 Window is visible, hiding it...");
+                    debug!("Window is visible, hiding it...");
                     (&*window).orderOut(None);
                     restore_focus(&*window);
                     // Notify app that popup is now hidden
                     if let Some(mut app) = egui_app_from_window(&*window) {
+                        remember_frame(window, &mut app);
                         app.set_popup_visible(false);
                     }
                     return;
                 } else {
                     store_app_pid(&*window);
-                    ll("👁️ Window exists but hidden, showing it...");
+                    debug!("Window exists but hidden, showing it...");
                     unsafe {
                         let _: () = objc2::msg_send![self, showExistingWindow: &**window];
                     }
@@ -337,10 +436,10 @@ define_class!(
                 }
             }
 
-            ll("🎯 Creating new egui window...");
+            info!("Creating new egui window...");
 
             // First, activate the application to bring it to focus - use aggressive activation
-            ll("🔍 Activating application with force...");
+            debug!("Activating application with force...");
             let app = NSApplication::sharedApplication(mtm);
 
             // Use the old method that forces activation even when another app is active
@@ -348,7 +447,11 @@ define_class!(
             app.activateIgnoringOtherApps(true);
 
             // Create a borderless window for popup-style UI
-            let frame = NSRect::new(NSPoint::new(0.0, 0.0), NSSize::new(400.0, 300.0));
+            let (config, config_error) = current_config();
+            let frame = NSRect::new(
+                NSPoint::new(0.0, 0.0),
+                NSSize::new(config.window.width, config.window.height),
+            );
             let style_mask = NSWindowStyleMask::Borderless;
             let backing_store_type = NSBackingStoreType::Buffered;
 
@@ -364,8 +467,8 @@ define_class!(
             (&*window).center();
 
             // Set window level to floating to ensure it appears above other apps
-            ll("🔝 Setting window level to floating...");
-            (&*window).setLevel(3); // NSFloatingWindowLevel = 3
+            debug!("Setting window level to floating...");
+            (&*window).setLevel(config.window.level); // NSFloatingWindowLevel = 3
 
             // Enable mouse moved events for borderless window
             (&*window).setAcceptsMouseMovedEvents(true);
@@ -387,6 +490,39 @@ define_class!(
 
             // IMPORTANT: Initialize the egui/wgpu state *after* the view is in the window.
             view.init_state();
+
+            if let Some(mut app) = egui_app_from_window(&window) {
+                app.apply_config(&config);
+                app.set_config_error(config_error);
+            }
+
+            // Put the window back where it was last time, if it has been anywhere
+            let saved_frame = egui_app_from_window(&window).and_then(|app| app.window_frame());
+            // and that is still on a screen; otherwise it stays centered
+            let screens: Vec<WindowFrame> = NSScreen::screens(mtm)
+                .iter()
+                .map(|screen| {
+                    let visible = screen.visibleFrame();
+                    WindowFrame {
+                        x: visible.origin.x,
+                        y: visible.origin.y,
+                        width: visible.size.width,
+                        height: visible.size.height,
+                    }
+                })
+                .collect();
+            match saved_frame {
+                Some(frame) if frame.is_on_screen(&screens) => {
+                    debug!(?frame, "restoring window frame");
+                    let rect = NSRect::new(
+                        NSPoint::new(frame.x, frame.y),
+                        NSSize::new(frame.width, frame.height),
+                    );
+                    (&*window).setFrame_display(rect, true);
+                }
+                Some(frame) => debug!(?frame, "saved window frame is off screen, centering"),
+                None => {}
+            }
             // Store the previous frontmost app PID before activating ourselves
             unsafe {
                 let workspace = NSWorkspace::sharedWorkspace();
@@ -395,7 +531,7 @@ define_class!(
                     let pid: i32 = objc2::msg_send![&*app_obj, processIdentifier]; // TODO check for -1
                     if let Some(state) = view.ivars().state.get() {
                         state.app.borrow_mut().prev_pid = Some(pid as u32);
-                        ll(&format!("🔙 Stored previous app PID in TrrpyApp: {}", pid));
+                        debug!("Stored previous app PID in TrrpyApp: {}", pid);
                     }
                 }
             }
@@ -408,21 +544,21 @@ define_class!(
             }
 
             // Show and focus the window
-            ll("🪟 Making window key and ordering front...");
+            debug!("Making window key and ordering front...");
             (&*window).makeKeyAndOrderFront(None);
 
             // Ensure the window is at the front and focused
-            ll("🔝 Bringing window to front regardless...");
+            debug!("Bringing window to front regardless...");
             unsafe {
                 (&*window).orderFrontRegardless();
             }
 
             // Make the view the first responder so it can receive keyboard events immediately
-            ll("⌨️ Setting first responder...");
+            debug!("Setting first responder...");
             (&*window).makeFirstResponder(Some(&view));
 
             // Additional focus methods to ensure we get focus from other apps
-            ll("🔄 Performing additional focus operations...");
+            debug!("Performing additional focus operations...");
 
             // Bring all app windows to front
             unsafe {
@@ -435,23 +571,26 @@ define_class!(
             }
 
             // Request attention to make the app icon bounce in the dock
-            ll("🔔 Requesting user attention...");
+            debug!("Requesting user attention...");
             app.requestUserAttention(objc2_app_kit::NSRequestUserAttentionType::CriticalRequest);
 
-            // Center the window on screen for better visibility
-            ll("🎯 Centering window...");
-            window.center();
+            // Center the window on screen for better visibility, unless it
+            // has a place of its own
+            if saved_frame.is_none() {
+                info!("Centering window...");
+                window.center();
+            }
 
             // Final activation to ensure focus
             #[allow(deprecated)]
             app.activateIgnoringOtherApps(true);
 
             // Add small delay to allow focus changes to take effect
-            ll("⏱️ Allowing focus changes to process...");
+            debug!("Allowing focus changes to process...");
             std::thread::sleep(std::time::Duration::from_millis(50));
 
             // Final key window operation to ensure focus
-            ll("🔑 Final key window operation...");
+            debug!("Final key window operation...");
             window.makeKeyAndOrderFront(None);
 
             // Notify app that popup is now visible
@@ -463,12 +602,65 @@ define_class!(
                 }
             }
 
-            ll("✅ Window setup and aggressive focusing complete!");
+            info!("Window setup and aggressive focusing complete!");
+        }
+
+        #[unsafe(method(pollHiddenPopup))]
+        fn poll_hidden_popup(&self) {
+            // While shown, the view's draw loop does the polling
+            let Some(ref window) = self.ivars().window else {
+                return;
+            };
+            if (&*window).isVisible() {
+                return;
+            }
+            let show = egui_app_from_window(&*window).is_some_and(|mut app| {
+                app.poll();
+                std::mem::take(&mut app.show_requested)
+            });
+            if show {
+                info!("Proximity alert, showing the popup...");
+                unsafe {
+                    let _: () = objc2::msg_send![self, showEguiWindow];
+                }
+            }
+        }
+
+        #[unsafe(method(reloadConfig))]
+        fn reload_config(&self) {
+            let Some((old, result)) = poll_config() else {
+                return;
+            };
+            let window = self.ivars().window.as_ref();
+            match result {
+                Ok(config) => {
+                    set_hotkey(&config.hotkey);
+                    let Some(window) = window else {
+                        return;
+                    };
+                    if (config.window.width, config.window.height) != (old.width, old.height) {
+                        (&*window)
+                            .setContentSize(NSSize::new(config.window.width, config.window.height));
+                    }
+                    if config.window.level != old.level {
+                        (&*window).setLevel(config.window.level);
+                    }
+                    if let Some(mut app) = egui_app_from_window(window) {
+                        app.apply_config(&config);
+                    }
+                }
+                Err(error) => {
+                    error!(%error, "invalid config, keeping the last one");
+                    if let Some(mut app) = window.and_then(|window| egui_app_from_window(window)) {
+                        app.set_config_error(Some(error));
+                    }
+                }
+            }
         }
 
         #[unsafe(method(showExistingWindow:))]
         fn show_existing_window(&self, window: &NSWindow) {
-            ll("🔍 Showing existing window with aggressive focus...");
+            debug!("Showing existing window with aggressive focus...");
 
             // Get the MainThreadMarker since we are on the main thread.
             let mtm = MainThreadMarker::from(self);
@@ -504,7 +696,7 @@ define_class!(
             app.activateIgnoringOtherApps(true);
             window.makeKeyAndOrderFront(None);
 
-            ll("✅ Existing window focused!");
+            info!("Existing window focused!");
         }
     }
 );
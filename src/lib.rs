@@ -0,0 +1,28 @@
+//! The platform-independent core of the popup: the Blitzortung protocol and
+//! transport, recordings, the headless CLI and the egui app state and UI.
+//! The Cocoa glue that hosts the app lives in the binary.
+
+pub mod accelerator;
+pub mod alerts;
+pub mod areas;
+pub mod blitzortung;
+pub mod cells;
+pub mod client;
+pub mod config;
+pub mod database;
+pub mod export;
+#[cfg(test)]
+mod fake_server;
+pub mod geo;
+pub mod headless;
+pub mod logging;
+pub mod map;
+pub mod recording;
+#[cfg(test)]
+mod reference_decode;
+pub mod settings;
+pub mod stats;
+pub mod store;
+pub mod trrpy;
+pub mod utils;
+pub mod websocket;
@@ -0,0 +1,279 @@
+//! Leveled, structured logging on top of `tracing`.
+//!
+//! Everything logs through the `tracing` macros. This module decides what is
+//! kept, per module, and where it goes: stderr, a rotating file and, on
+//! macOS, unified logging. All of it can be changed while the app runs.
+
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use tracing::Subscriber;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt, reload};
+
+/// Subsystem used for macOS unified logging, e.g. `log stream --predicate
+/// 'subsystem == "org.popuppp"'`.
+pub const OSLOG_SUBSYSTEM: &str = "org.popuppp";
+
+/// How logs are filtered and where they go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    /// `RUST_LOG`-style directives, e.g. `info,popuppp::websocket=debug`.
+    pub filter: String,
+    /// Write to stderr.
+    pub stderr: bool,
+    /// Write to daily-rotated `popuppp.<date>.log` files in this directory.
+    pub file: Option<PathBuf>,
+    /// How many rotated files to keep.
+    pub max_files: usize,
+    /// Write to macOS unified logging. Ignored elsewhere.
+    pub oslog: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
+            stderr: true,
+            file: None,
+            max_files: 7,
+            oslog: false,
+        }
+    }
+}
+
+/// Why logging could not be set up as asked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogError {
+    /// The filter directives do not parse.
+    Filter(String),
+    /// The log directory cannot be used.
+    File(String),
+    /// Logging was set up already, or the new setup could not be applied.
+    Subscriber(String),
+}
+
+impl std::fmt::Display for LogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogError::Filter(e) => write!(f, "invalid log filter: {}", e),
+            LogError::File(e) => write!(f, "cannot log to file: {}", e),
+            LogError::Subscriber(e) => write!(f, "cannot set up logging: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type Sinks = Vec<Box<dyn Layer<Filtered> + Send + Sync>>;
+
+/// Changes the filter and sinks of a subscriber made by [`subscriber`].
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    sinks: reload::Handle<Sinks, Filtered>,
+    config: Mutex<LogConfig>,
+}
+
+impl std::fmt::Debug for LogHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogHandle")
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogHandle {
+    /// The configuration currently in effect.
+    pub fn config(&self) -> LogConfig {
+        self.config
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Switch to `config`. Nothing changes if any part of it is invalid.
+    pub fn reconfigure(&self, config: LogConfig) -> Result<(), LogError> {
+        let filter = parse_filter(&config.filter)?;
+        let sinks = sinks(&config)?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| LogError::Subscriber(e.to_string()))?;
+        self.sinks
+            .reload(sinks)
+            .map_err(|e| LogError::Subscriber(e.to_string()))?;
+        *self.config.lock().unwrap_or_else(|e| e.into_inner()) = config;
+        Ok(())
+    }
+}
+
+static HANDLE: OnceLock<LogHandle> = OnceLock::new();
+
+/// Build a subscriber for `config` without installing it.
+pub fn subscriber(
+    config: LogConfig,
+) -> Result<(impl Subscriber + Send + Sync + 'static, LogHandle), LogError> {
+    let (filter, filter_handle) = reload::Layer::new(parse_filter(&config.filter)?);
+    let (sinks, sinks_handle) = reload::Layer::new(sinks(&config)?);
+    let subscriber = tracing_subscriber::registry().with(filter).with(sinks);
+    let handle = LogHandle {
+        filter: filter_handle,
+        sinks: sinks_handle,
+        config: Mutex::new(config),
+    };
+    Ok((subscriber, handle))
+}
+
+/// Install logging for the whole process, including records from crates
+/// that use `log`. Can only succeed once.
+pub fn init(config: LogConfig) -> Result<&'static LogHandle, LogError> {
+    if HANDLE.get().is_some() {
+        return Err(LogError::Subscriber("already initialized".to_string()));
+    }
+    let (subscriber, handle) = subscriber(config)?;
+    subscriber
+        .try_init()
+        .map_err(|e| LogError::Subscriber(e.to_string()))?;
+    Ok(HANDLE.get_or_init(|| handle))
+}
+
+/// The handle of the logging installed by [`init`], if any.
+pub fn handle() -> Option<&'static LogHandle> {
+    HANDLE.get()
+}
+
+fn parse_filter(directives: &str) -> Result<EnvFilter, LogError> {
+    EnvFilter::builder()
+        .parse(directives)
+        .map_err(|e| LogError::Filter(e.to_string()))
+}
+
+fn sinks(config: &LogConfig) -> Result<Sinks, LogError> {
+    let mut sinks: Sinks = Vec::new();
+    if config.stderr {
+        sinks.push(fmt::layer().with_writer(std::io::stderr).boxed());
+    }
+    if let Some(dir) = &config.file {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("popuppp")
+            .filename_suffix("log")
+            .max_log_files(config.max_files.max(1))
+            .build(dir)
+            .map_err(|e| LogError::File(format!("{}: {}", dir.display(), e)))?;
+        sinks.push(fmt::layer().with_writer(appender).with_ansi(false).boxed());
+    }
+    #[cfg(target_os = "macos")]
+    if config.oslog {
+        sinks.push(tracing_oslog::OsLogger::new(OSLOG_SUBSYSTEM, "default").boxed());
+    }
+    Ok(sinks)
+}
+
+/// A subscriber that writes everything at `filter` to a shared buffer, for
+/// tests that check what was logged.
+#[cfg(test)]
+pub(crate) fn capture(filter: &str) -> (tracing::Dispatch, std::sync::Arc<Mutex<Vec<u8>>>) {
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let writer = Buffer(buffer.clone());
+    let subscriber = tracing_subscriber::registry()
+        .with(parse_filter(filter).unwrap())
+        .with(
+            fmt::layer()
+                .with_writer(move || writer.clone())
+                .with_ansi(false),
+        );
+    (tracing::Dispatch::new(subscriber), buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logged_files(dir: &std::path::Path) -> String {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn filters_and_sinks_can_be_changed_at_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = LogConfig {
+            filter: "warn".to_string(),
+            stderr: false,
+            file: Some(dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let (subscriber, handle) = subscriber(config.clone()).unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("quiet");
+            tracing::warn!(url = "wss://example", "loud");
+            handle
+                .reconfigure(LogConfig {
+                    filter: "warn,popuppp::logging=debug".to_string(),
+                    ..config.clone()
+                })
+                .unwrap();
+            tracing::debug!(attempt = 3, "module specific");
+
+            // A broken filter leaves everything as it was
+            let broken = LogConfig {
+                filter: "popuppp=loud".to_string(),
+                ..config.clone()
+            };
+            assert!(matches!(
+                handle.reconfigure(broken),
+                Err(LogError::Filter(_))
+            ));
+            assert_eq!(handle.config().filter, "warn,popuppp::logging=debug");
+            tracing::debug!("still debugging");
+
+            handle
+                .reconfigure(LogConfig {
+                    file: None,
+                    ..config.clone()
+                })
+                .unwrap();
+            tracing::warn!("gone");
+        });
+
+        let logged = logged_files(dir.path());
+        assert!(!logged.contains("quiet"));
+        assert!(logged.contains("WARN"));
+        assert!(logged.contains("loud url=\"wss://example\""));
+        assert!(logged.contains("module specific attempt=3"));
+        assert!(logged.contains("still debugging"));
+        assert!(!logged.contains("gone"));
+    }
+
+    #[test]
+    fn unusable_log_directories_are_reported() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = LogConfig {
+            file: Some(file.path().join("logs")),
+            ..Default::default()
+        };
+        assert!(matches!(subscriber(config), Err(LogError::File(_))));
+    }
+}
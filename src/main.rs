@@ -1,50 +1,117 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
-use crate::hotkey::{APP_DELEGATE, AppDelegate};
-use objc2::runtime::ProtocolObject;
-use objc2::{MainThreadMarker, rc::Retained};
-use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
+use std::process::ExitCode;
 
-use std::sync::atomic::{AtomicPtr, Ordering};
-
-mod blitzortung;
+#[cfg(target_os = "macos")]
 mod hotkey;
-mod trrpy;
+#[cfg(target_os = "macos")]
 mod ui;
-mod utils;
-mod websocket;
 
-// Global reference to NSApplication for signal handler
-static APP_INSTANCE: AtomicPtr<NSApplication> = AtomicPtr::new(std::ptr::null_mut());
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // The popup needs Cocoa; everywhere else headless is all there is
+    if cfg!(not(target_os = "macos")) || args.iter().any(|a| a == "--headless") {
+        return popuppp::headless::main(&args);
+    }
+    #[cfg(target_os = "macos")]
+    app::run();
+    ExitCode::SUCCESS
+}
+
+#[cfg(target_os = "macos")]
+mod app {
+    use crate::hotkey::{APP_DELEGATE, AppDelegate};
+    use objc2::MainThreadMarker;
+    use objc2::rc::Retained;
+    use objc2::runtime::ProtocolObject;
+    use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
+    use popuppp::logging::{self, LogConfig};
+    use std::ffi::{c_int, c_void};
+    use std::sync::atomic::Ordering;
+    use tracing::info;
 
-fn main() {
-    let mtm: MainThreadMarker = MainThreadMarker::new().unwrap();
+    // libdispatch, part of libSystem
+    #[repr(C)]
+    struct DispatchSourceType {
+        _private: [u8; 0],
+    }
 
-    let app = NSApplication::sharedApplication(mtm);
-    app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
+    unsafe extern "C" {
+        static _dispatch_source_type_signal: DispatchSourceType;
+        // What dispatch_get_main_queue() returns
+        static _dispatch_main_q: u8;
+        fn dispatch_source_create(
+            kind: *const DispatchSourceType,
+            handle: usize,
+            mask: usize,
+            queue: *const c_void,
+        ) -> *mut c_void;
+        fn dispatch_source_set_event_handler_f(
+            source: *mut c_void,
+            handler: extern "C" fn(*mut c_void),
+        );
+        fn dispatch_resume(object: *mut c_void);
+        fn signal(sig: c_int, handler: usize) -> usize;
+    }
 
-    // Store app reference for signal handler
-    APP_INSTANCE.store(
-        Retained::as_ptr(&app) as *mut NSApplication,
-        Ordering::SeqCst,
-    );
+    // SIGINT (Ctrl+C), delivered by libdispatch on the main queue rather than
+    // in a signal handler, so it can log and go through Cocoa like anything else
+    extern "C" fn on_sigint(_context: *mut c_void) {
+        info!("Received SIGINT (Ctrl+C) - initiating proper app termination...");
+        let mtm = MainThreadMarker::new().expect("the main queue runs on the main thread");
+        unsafe { NSApplication::sharedApplication(mtm).terminate(None) };
+    }
 
-    // Set up signal handler for graceful Ctrl+C handling
-    unsafe {
-        utils::setup_signal_handler();
+    unsafe fn setup_signal_handler() {
+        const SIGINT: c_int = 2;
+        const SIG_IGN: usize = 1;
+        unsafe {
+            // The default action would kill us before the source sees it
+            signal(SIGINT, SIG_IGN);
+            let source = dispatch_source_create(
+                &_dispatch_source_type_signal,
+                SIGINT as usize,
+                0,
+                (&raw const _dispatch_main_q).cast(),
+            );
+            // Never released, it is needed for as long as the app runs
+            dispatch_source_set_event_handler_f(source, on_sigint);
+            dispatch_resume(source);
+        }
+        info!("Set up SIGINT handler for graceful Ctrl+C handling");
     }
 
-    // Configure the application delegate
-    let delegate = AppDelegate::new(mtm);
-    let object = ProtocolObject::from_ref(&*delegate);
-    app.setDelegate(Some(object));
+    pub(crate) fn run() {
+        // Console.app shows unified logging; the Debug panel can change this later
+        if let Err(e) = logging::init(LogConfig {
+            oslog: true,
+            ..Default::default()
+        }) {
+            eprintln!("{}", e);
+        }
+
+        let mtm: MainThreadMarker = MainThreadMarker::new().unwrap();
+
+        let app = NSApplication::sharedApplication(mtm);
+        app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
 
-    // Store delegate reference for hotkey dispatching
-    APP_DELEGATE.store(
-        Retained::as_ptr(&delegate) as *mut AppDelegate,
-        Ordering::SeqCst,
-    );
+        // Set up signal handler for graceful Ctrl+C handling
+        unsafe {
+            setup_signal_handler();
+        }
 
-    // Run the app
-    app.run();
+        // Configure the application delegate
+        let delegate = AppDelegate::new(mtm);
+        let object = ProtocolObject::from_ref(&*delegate);
+        app.setDelegate(Some(object));
+
+        // Store delegate reference for hotkey dispatching
+        APP_DELEGATE.store(
+            Retained::as_ptr(&delegate) as *mut AppDelegate,
+            Ordering::SeqCst,
+        );
+
+        // Run the app
+        app.run();
+    }
 }
@@ -0,0 +1,473 @@
+//! A pannable, zoomable world map of strikes, drawn with egui's painter.
+//!
+//! Positions go through a "world" plane first: x is longitude over 360 in
+//! `-0.5..=0.5`, y grows southwards. The view maps that plane onto the
+//! canvas, so panning and zooming never depend on the projection.
+
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Shape, Stroke, Vec2};
+
+use crate::blitzortung::LightningStrike;
+use crate::geo::Location;
+
+/// Bundled low-resolution coastlines, see the file header for the format.
+const COASTLINE: &str = include_str!("../assets/coastline.txt");
+
+/// Web Mercator stops here, where the world becomes square.
+const MERCATOR_MAX_LAT: f64 = 85.051_128_78;
+
+const MIN_ZOOM: f32 = 1.0;
+const MAX_ZOOM: f32 = 256.0;
+
+/// Faded strikes stay this visible.
+const MIN_ALPHA: f32 = 0.15;
+
+const POSITIVE: Color32 = Color32::from_rgb(255, 90, 70);
+const NEGATIVE: Color32 = Color32::from_rgb(80, 170, 255);
+const UNKNOWN: Color32 = Color32::from_rgb(255, 220, 60);
+
+const SEA: Color32 = Color32::from_rgb(12, 24, 40);
+const COAST: Color32 = Color32::from_rgb(110, 140, 120);
+const GRID: Color32 = Color32::from_rgb(30, 48, 68);
+
+/// How latitude and longitude are laid out on the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Projection {
+    /// Plate carrée: degrees map linearly to both axes.
+    #[default]
+    Equirectangular,
+    /// Web Mercator, as used by most web maps. Cut off at ±85°.
+    WebMercator,
+}
+
+impl Projection {
+    pub const ALL: [Projection; 2] = [Projection::Equirectangular, Projection::WebMercator];
+
+    /// World coordinates of `lon`/`lat` in degrees.
+    pub fn project(self, lon: f64, lat: f64) -> Pos2 {
+        let x = lon / 360.0;
+        let y = match self {
+            Projection::Equirectangular => -lat / 360.0,
+            Projection::WebMercator => {
+                let phi = lat.clamp(-MERCATOR_MAX_LAT, MERCATOR_MAX_LAT).to_radians();
+                -(std::f64::consts::FRAC_PI_4 + phi / 2.0).tan().ln() / std::f64::consts::TAU
+            }
+        };
+        Pos2::new(x as f32, y as f32)
+    }
+
+    /// Longitude and latitude in degrees of a world position.
+    pub fn unproject(self, world: Pos2) -> (f64, f64) {
+        let lon = world.x as f64 * 360.0;
+        let y = world.y as f64;
+        let lat = match self {
+            Projection::Equirectangular => -y * 360.0,
+            Projection::WebMercator => (2.0 * (-y * std::f64::consts::TAU).exp().atan()
+                - std::f64::consts::FRAC_PI_2)
+                .to_degrees(),
+        };
+        (lon, lat)
+    }
+
+    /// Height of the whole world in world units; the width is always 1.
+    fn height(self) -> f32 {
+        match self {
+            Projection::Equirectangular => 0.5,
+            Projection::WebMercator => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Projection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Projection::Equirectangular => write!(f, "Equirectangular"),
+            Projection::WebMercator => write!(f, "Mercator"),
+        }
+    }
+}
+
+/// Where the map is looking and how strikes are drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapView {
+    pub projection: Projection,
+    /// Strikes fade out over this long.
+    pub fade: Duration,
+    /// World position at the center of the canvas.
+    center: Pos2,
+    /// 1.0 fits the width of the world into the canvas.
+    zoom: f32,
+}
+
+impl Default for MapView {
+    fn default() -> Self {
+        Self {
+            projection: Projection::default(),
+            fade: Duration::from_secs(10 * 60),
+            center: Pos2::ZERO,
+            zoom: MIN_ZOOM,
+        }
+    }
+}
+
+impl MapView {
+    /// Show the whole world again.
+    pub fn reset(&mut self) {
+        self.center = Pos2::ZERO;
+        self.zoom = MIN_ZOOM;
+    }
+
+    /// Zoom by `factor`, keeping the point under `anchor` where it is.
+    pub fn zoom_by(&mut self, rect: Rect, factor: f32, anchor: Pos2) {
+        let fixed = self.to_world(rect, anchor);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.center = fixed - (anchor - rect.center()) / self.scale(rect);
+        self.clamp_center();
+    }
+
+    /// Move the map by `delta` screen points.
+    pub fn pan(&mut self, rect: Rect, delta: Vec2) {
+        self.center -= delta / self.scale(rect);
+        self.clamp_center();
+    }
+
+    /// Look at `lon`/`lat`, keeping the zoom.
+    pub fn center_on(&mut self, lon: f64, lat: f64) {
+        self.center = self.projection.project(lon, lat);
+        self.clamp_center();
+    }
+
+    pub fn to_screen(&self, rect: Rect, world: Pos2) -> Pos2 {
+        rect.center() + (world - self.center) * self.scale(rect)
+    }
+
+    pub fn to_world(&self, rect: Rect, screen: Pos2) -> Pos2 {
+        self.center + (screen - rect.center()) / self.scale(rect)
+    }
+
+    /// Projection controls and the map itself, filling the available width.
+    /// Strikes are drawn in order, so pass them oldest first to keep fresh
+    /// ones on top. `overlay` draws on top of the strikes.
+    pub fn ui<'a>(
+        &mut self,
+        ui: &mut egui::Ui,
+        strikes: impl IntoIterator<Item = &'a LightningStrike>,
+        now: SystemTime,
+        overlay: impl FnOnce(&MapPainter<'_>),
+    ) -> egui::Response {
+        ui.horizontal(|ui| {
+            let (lon, lat) = self.projection.unproject(self.center);
+            for projection in Projection::ALL {
+                if ui
+                    .selectable_label(self.projection == projection, projection.to_string())
+                    .clicked()
+                {
+                    self.projection = projection;
+                    self.center_on(lon, lat);
+                }
+            }
+            if ui.button("Reset view").clicked() {
+                self.reset();
+            }
+        });
+
+        let size = Vec2::new(
+            ui.available_width(),
+            (ui.available_width() / 2.0).min(220.0),
+        );
+        let (response, painter) = ui.allocate_painter(size, Sense::click_and_drag());
+        let rect = response.rect;
+
+        if response.dragged() {
+            self.pan(rect, response.drag_delta());
+        }
+        if let Some(pointer) = response.hover_pos() {
+            let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta()));
+            let factor = pinch * (scroll * 0.002).exp();
+            if factor != 1.0 {
+                self.zoom_by(rect, factor, pointer);
+            }
+        }
+        if response.double_clicked() {
+            self.reset();
+        }
+
+        painter.rect_filled(rect, 0.0, SEA);
+        self.paint_graticule(&painter, rect);
+        for line in coastlines() {
+            let points = line
+                .iter()
+                .map(|&(lon, lat)| self.to_screen(rect, self.projection.project(lon, lat)))
+                .collect();
+            painter.add(Shape::line(points, Stroke::new(1.0, COAST)));
+        }
+
+        for strike in strikes {
+            let age = now
+                .duration_since(strike.timestamp())
+                .unwrap_or(Duration::ZERO);
+            let alpha = age_alpha(age, self.fade);
+            let pos = self.to_screen(rect, self.projection.project(strike.lon, strike.lat));
+            if rect.expand(4.0).contains(pos) {
+                painter.circle_filled(
+                    pos,
+                    1.5 + 2.0 * alpha,
+                    polarity_color(strike.pol).gamma_multiply(alpha),
+                );
+            }
+        }
+
+        overlay(&MapPainter {
+            view: self,
+            painter: &painter,
+            rect,
+        });
+
+        if let Some(pointer) = response.hover_pos() {
+            let (lon, lat) = self.projection.unproject(self.to_world(rect, pointer));
+            painter.text(
+                rect.left_bottom() + Vec2::new(4.0, -4.0),
+                Align2::LEFT_BOTTOM,
+                format!("{:.2}°, {:.2}°", lat, lon),
+                FontId::monospace(11.0),
+                Color32::LIGHT_GRAY,
+            );
+        }
+
+        response
+    }
+
+    fn paint_graticule(&self, painter: &egui::Painter, rect: Rect) {
+        let stroke = Stroke::new(0.5, GRID);
+        let top = self.projection.project(0.0, 90.0).y;
+        let bottom = self.projection.project(0.0, -90.0).y;
+        for lon in (-180..=180).step_by(30) {
+            let x = self.projection.project(lon as f64, 0.0).x;
+            painter.line_segment(
+                [
+                    self.to_screen(rect, Pos2::new(x, top)),
+                    self.to_screen(rect, Pos2::new(x, bottom)),
+                ],
+                stroke,
+            );
+        }
+        for lat in (-60..=60).step_by(30) {
+            let y = self.projection.project(0.0, lat as f64).y;
+            painter.line_segment(
+                [
+                    self.to_screen(rect, Pos2::new(-0.5, y)),
+                    self.to_screen(rect, Pos2::new(0.5, y)),
+                ],
+                stroke,
+            );
+        }
+    }
+
+    /// Screen points per world unit.
+    fn scale(&self, rect: Rect) -> f32 {
+        rect.width() * self.zoom
+    }
+
+    fn clamp_center(&mut self) {
+        let half_height = self.projection.height() / 2.0;
+        self.center.x = self.center.x.clamp(-0.5, 0.5);
+        self.center.y = self.center.y.clamp(-half_height, half_height);
+    }
+}
+
+/// Draws places onto the map canvas, for overlays.
+pub struct MapPainter<'a> {
+    view: &'a MapView,
+    painter: &'a egui::Painter,
+    rect: Rect,
+}
+
+impl MapPainter<'_> {
+    /// Where `location` is on the screen.
+    pub fn pos(&self, location: Location) -> Pos2 {
+        self.view.to_screen(
+            self.rect,
+            self.view.projection.project(location.lon, location.lat),
+        )
+    }
+
+    pub fn painter(&self) -> &egui::Painter {
+        self.painter
+    }
+
+    /// A cross hair at `location`.
+    pub fn marker(&self, location: Location, color: Color32) {
+        let pos = self.pos(location);
+        let stroke = Stroke::new(1.5, color);
+        self.painter
+            .line_segment([pos - Vec2::X * 5.0, pos + Vec2::X * 5.0], stroke);
+        self.painter
+            .line_segment([pos - Vec2::Y * 5.0, pos + Vec2::Y * 5.0], stroke);
+    }
+
+    /// The circle of everything `radius_km` from `center`, as it looks in
+    /// the current projection.
+    pub fn circle(&self, center: Location, radius_km: f64, stroke: Stroke) {
+        self.line(
+            (0..=72).map(|step| center.destination(step as f64 * 5.0, radius_km)),
+            stroke,
+        );
+    }
+
+    /// Straight lines through `points`, closely spaced enough to look right
+    /// in the current projection.
+    pub fn line(&self, points: impl IntoIterator<Item = Location>, stroke: Stroke) {
+        let mut line = Vec::new();
+        let mut last_lon = None;
+        for point in points {
+            // Lift the pen where the line wraps around the antimeridian
+            if last_lon.is_some_and(|lon: f64| (point.lon - lon).abs() > 180.0) {
+                self.painter
+                    .add(Shape::line(std::mem::take(&mut line), stroke));
+            }
+            last_lon = Some(point.lon);
+            line.push(self.pos(point));
+        }
+        self.painter.add(Shape::line(line, stroke));
+    }
+
+    /// A small label next to `location`.
+    pub fn label(&self, location: Location, text: impl ToString, color: Color32) {
+        self.painter.text(
+            self.pos(location) + Vec2::new(6.0, -6.0),
+            Align2::LEFT_BOTTOM,
+            text,
+            FontId::proportional(11.0),
+            color,
+        );
+    }
+}
+
+/// How visible a strike `age` old is, from 1.0 when new down to a floor
+/// once it is `fade` old.
+pub fn age_alpha(age: Duration, fade: Duration) -> f32 {
+    if fade.is_zero() {
+        return 1.0;
+    }
+    let faded = (age.as_secs_f32() / fade.as_secs_f32()).min(1.0);
+    MIN_ALPHA + (1.0 - faded) * (1.0 - MIN_ALPHA)
+}
+
+/// Red for positive strikes, blue for negative ones and yellow when
+/// Blitzortung does not know.
+pub fn polarity_color(pol: i32) -> Color32 {
+    match pol.signum() {
+        1 => POSITIVE,
+        -1 => NEGATIVE,
+        _ => UNKNOWN,
+    }
+}
+
+/// The bundled coastlines as polylines of (lon, lat) in degrees.
+pub fn coastlines() -> &'static [Vec<(f64, f64)>] {
+    static LINES: OnceLock<Vec<Vec<(f64, f64)>>> = OnceLock::new();
+    LINES.get_or_init(|| parse_polylines(COASTLINE))
+}
+
+fn parse_polylines(text: &str) -> Vec<Vec<(f64, f64)>> {
+    let mut lines = Vec::new();
+    let mut current = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            if current.len() > 1 {
+                lines.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        }
+        let mut numbers = line.split_whitespace().map(str::parse::<f64>);
+        if let (Some(Ok(lon)), Some(Ok(lat))) = (numbers.next(), numbers.next()) {
+            current.push((lon, lat));
+        }
+    }
+    if current.len() > 1 {
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn projections_round_trip() {
+        for projection in Projection::ALL {
+            for (lon, lat) in [(0.0, 0.0), (9.19, 45.46), (-122.4, 37.8), (151.2, -33.9)] {
+                let (back_lon, back_lat) = projection.unproject(projection.project(lon, lat));
+                assert!(close(back_lon, lon) && close(back_lat, lat), "{projection}");
+            }
+        }
+
+        // Mercator is square and stops short of the poles
+        let corner = Projection::WebMercator.project(180.0, 90.0);
+        assert!((corner.x - 0.5).abs() < 1e-6 && (corner.y + 0.5).abs() < 1e-6);
+        let corner = Projection::Equirectangular.project(-180.0, -90.0);
+        assert_eq!(corner, Pos2::new(-0.5, 0.25));
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_pointer() {
+        let rect = Rect::from_min_size(Pos2::new(10.0, 20.0), Vec2::new(400.0, 200.0));
+        let mut view = MapView::default();
+        let pointer = Pos2::new(300.0, 80.0);
+        let before = view.to_world(rect, pointer);
+
+        view.zoom_by(rect, 4.0, pointer);
+        let after = view.to_world(rect, pointer);
+        assert!((before - after).length() < 1e-5);
+
+        // Panning moves the world with the pointer
+        view.pan(rect, Vec2::new(-40.0, 0.0));
+        assert!(view.to_world(rect, pointer).x > after.x);
+
+        // Zooming out further than the whole world is not possible
+        view.zoom_by(rect, 0.01, pointer);
+        view.reset();
+        assert_eq!(view, MapView::default());
+    }
+
+    #[test]
+    fn strikes_fade_with_age_and_show_polarity() {
+        let fade = Duration::from_secs(600);
+        assert_eq!(age_alpha(Duration::ZERO, fade), 1.0);
+        assert!(age_alpha(Duration::from_secs(300), fade) < 1.0);
+        assert_eq!(age_alpha(Duration::from_secs(600), fade), MIN_ALPHA);
+        assert_eq!(age_alpha(Duration::from_secs(6000), fade), MIN_ALPHA);
+
+        assert_eq!(polarity_color(12), POSITIVE);
+        assert_eq!(polarity_color(-3), NEGATIVE);
+        assert_eq!(polarity_color(0), UNKNOWN);
+    }
+
+    #[test]
+    fn bundled_coastlines_cover_the_world() {
+        let lines = coastlines();
+        assert!(lines.len() > 30);
+        let points = lines.iter().flatten();
+        assert!(
+            points
+                .clone()
+                .all(|&(lon, lat)| (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat))
+        );
+        let west = points.clone().map(|p| p.0).fold(f64::MAX, f64::min);
+        let south = points.map(|p| p.1).fold(f64::MAX, f64::min);
+        assert_eq!((west, south), (-180.0, -78.0));
+
+        assert_eq!(
+            parse_polylines("# a\n1 2\n3 4\n\n# b\n5 6\n# c\n7 8\n9 10"),
+            [vec![(1.0, 2.0), (3.0, 4.0)], vec![(7.0, 8.0), (9.0, 10.0)]]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Session recordings: raw Blitzortung frames with their receive times, so a
+//! storm can be played back through the same code paths later.
+//!
+//! The format is a small header followed by one record per frame:
+//!
+//! ```text
+//! header: b"BLZREC" | version: u8 | started_at: u64 (µs since the Unix epoch)
+//! frame:  offset: u64 (µs since started_at) | kind: u8 | len: u32 | payload
+//! ```
+//!
+//! Integers are little endian. `kind` is 0 for text frames and 1 for binary
+//! frames.
+//!
+//! Frames are buffered and written out every [`FLUSH_EVERY`] frames, once
+//! [`FLUSH_INTERVAL`] has passed, and when the recorder is finished or
+//! dropped. A crash loses whatever was still buffered: at most that many
+//! frames, or that long of the feed.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::warn;
+
+const MAGIC: &[u8; 6] = b"BLZREC";
+const VERSION: u8 = 1;
+
+const KIND_TEXT: u8 = 0;
+const KIND_BINARY: u8 = 1;
+
+/// Buffered frames are written out once there are this many...
+pub const FLUSH_EVERY: usize = 64;
+/// ...or once the oldest of them is this old.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Frames larger than this are assumed to be corruption rather than data.
+const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// The payload of a recorded frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FramePayload {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// A frame as it was received, `offset` after the recording started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    pub offset: Duration,
+    pub payload: FramePayload,
+}
+
+/// Appends received frames to a recording file.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+    /// Frames written since the last flush.
+    unflushed: usize,
+    flushed_at: Instant,
+}
+
+impl Recorder {
+    /// Create (or truncate) the recording at `path` and write its header.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&micros(started_at).to_le_bytes())?;
+        writer.flush()?;
+
+        let now = Instant::now();
+        Ok(Self {
+            writer,
+            started: now,
+            unflushed: 0,
+            flushed_at: now,
+        })
+    }
+
+    /// Record a text frame received just now.
+    pub fn record_text(&mut self, text: &str) -> io::Result<()> {
+        self.write_frame(KIND_TEXT, text.as_bytes())
+    }
+
+    /// Record a binary frame received just now.
+    pub fn record_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_frame(KIND_BINARY, data)
+    }
+
+    fn write_frame(&mut self, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(payload.len())
+            .ok()
+            .filter(|len| *len <= MAX_FRAME_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+        self.writer
+            .write_all(&micros(self.started.elapsed()).to_le_bytes())?;
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(payload)?;
+        self.unflushed += 1;
+        if self.unflushed >= FLUSH_EVERY {
+            return self.flush();
+        }
+        self.flush_if_due()
+    }
+
+    /// Write out buffered frames if they have waited [`FLUSH_INTERVAL`].
+    /// Call this now and then, so a quiet feed does not keep the last frames
+    /// in memory.
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.unflushed > 0 && self.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            self.flush()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write out every buffered frame.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.unflushed = 0;
+        self.flushed_at = Instant::now();
+        Ok(())
+    }
+
+    /// Stop recording, writing out what is still buffered.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if self.unflushed > 0
+            && let Err(e) = self.flush()
+        {
+            warn!(error = %e, "could not write out the end of the recording");
+        }
+    }
+}
+
+/// Reads frames back from a recording file, in order.
+#[derive(Debug)]
+pub struct RecordingReader<R = BufReader<File>> {
+    reader: R,
+    /// When the recording was started, as wall-clock time.
+    pub started_at: SystemTime,
+}
+
+impl RecordingReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    /// Check the header of `reader` and position it at the first frame.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a Blitzortung recording"));
+        }
+        let [version] = read_array(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported recording version {}",
+                version
+            )));
+        }
+        let started_at =
+            UNIX_EPOCH + Duration::from_micros(u64::from_le_bytes(read_array(&mut reader)?));
+
+        Ok(Self { reader, started_at })
+    }
+
+    /// The next frame, or `None` at the end of the recording. A recording cut
+    /// short in the middle of a frame ends at the last complete one.
+    pub fn next_frame(&mut self) -> io::Result<Option<RecordedFrame>> {
+        let offset = match read_array(&mut self.reader) {
+            Ok(bytes) => Duration::from_micros(u64::from_le_bytes(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let frame = (|| {
+            let [kind] = read_array(&mut self.reader)?;
+            let len = u32::from_le_bytes(read_array(&mut self.reader)?);
+            if len > MAX_FRAME_LEN {
+                return Err(invalid_data(format!("frame of {} bytes", len)));
+            }
+            let mut payload = vec![0u8; len as usize];
+            self.reader.read_exact(&mut payload)?;
+            let payload = match kind {
+                KIND_TEXT => FramePayload::Text(String::from_utf8(payload).map_err(invalid_data)?),
+                KIND_BINARY => FramePayload::Binary(payload),
+                other => return Err(invalid_data(format!("unknown frame kind {}", other))),
+            };
+            Ok(RecordedFrame { offset, payload })
+        })();
+
+        match frame {
+            Ok(frame) => Ok(Some(frame)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = io::Result<RecordedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+/// How fast a recording is played back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the recorded gaps between frames, divided by the factor.
+    Scaled(f64),
+    /// Emit frames as fast as the consumer takes them.
+    Max,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        ReplaySpeed::Scaled(1.0)
+    }
+}
+
+impl ReplaySpeed {
+    /// How long after the first frame the frame recorded `since_first` after
+    /// it should be played, or `None` to play it right away.
+    pub fn delay(&self, since_first: Duration) -> Option<Duration> {
+        match *self {
+            ReplaySpeed::Scaled(factor) if factor > 0.0 => Some(since_first.div_f64(factor)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplaySpeed::Scaled(factor) => write!(f, "{}x", factor),
+            ReplaySpeed::Max => write!(f, "max"),
+        }
+    }
+}
+
+impl std::str::FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// Parses `max`, `10x` or `10`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(ReplaySpeed::Max);
+        }
+        match s.trim_end_matches(['x', 'X']).parse::<f64>() {
+            Ok(factor) if factor > 0.0 && factor.is_finite() => Ok(ReplaySpeed::Scaled(factor)),
+            _ => Err(format!("invalid replay speed '{}'", s)),
+        }
+    }
+}
+
+fn micros(d: Duration) -> u64 {
+    u64::try_from(d.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.blzrec");
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record_text("a\u{100}").unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        recorder.record_binary(&[0xde, 0xad]).unwrap();
+        recorder.record_text("").unwrap();
+        drop(recorder);
+
+        let reader = RecordingReader::open(&path).unwrap();
+        assert!(reader.started_at <= SystemTime::now());
+        let frames: Vec<_> = reader.map(Result::unwrap).collect();
+        let payloads: Vec<_> = frames.iter().map(|f| f.payload.clone()).collect();
+        assert_eq!(
+            payloads,
+            [
+                FramePayload::Text("a\u{100}".to_string()),
+                FramePayload::Binary(vec![0xde, 0xad]),
+                FramePayload::Text(String::new()),
+            ]
+        );
+        assert!(frames[1].offset >= frames[0].offset + Duration::from_millis(2));
+    }
+
+    #[test]
+    fn truncated_recordings_end_at_the_last_whole_frame() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.blzrec");
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record_text("first").unwrap();
+        recorder.record_text("second").unwrap();
+        drop(recorder);
+
+        let bytes = std::fs::read(&path).unwrap();
+        let cut = &bytes[..bytes.len() - 3];
+        let frames: Vec<_> = RecordingReader::new(cut).unwrap().collect();
+        assert_eq!(frames.len(), 1);
+
+        assert!(RecordingReader::new(&b"not a recording"[..]).is_err());
+    }
+
+    #[test]
+    fn frames_are_written_out_in_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.blzrec");
+        let on_disk = || RecordingReader::open(&path).unwrap().count();
+
+        let mut recorder = Recorder::create(&path).unwrap();
+        for _ in 1..FLUSH_EVERY {
+            recorder.record_text("strike").unwrap();
+        }
+        assert_eq!(on_disk(), 0);
+        recorder.record_text("strike").unwrap();
+        assert_eq!(on_disk(), FLUSH_EVERY);
+
+        // What is left is written out when recording stops...
+        recorder.record_text("strike").unwrap();
+        recorder.flush_if_due().unwrap();
+        assert_eq!(on_disk(), FLUSH_EVERY);
+        recorder.finish().unwrap();
+        assert_eq!(on_disk(), FLUSH_EVERY + 1);
+
+        // ...or when the recorder goes away
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record_binary(&[1]).unwrap();
+        drop(recorder);
+        assert_eq!(on_disk(), 1);
+    }
+
+    #[test]
+    fn replay_speeds() {
+        assert_eq!("max".parse(), Ok(ReplaySpeed::Max));
+        assert_eq!("10x".parse(), Ok(ReplaySpeed::Scaled(10.0)));
+        assert_eq!("0.5".parse(), Ok(ReplaySpeed::Scaled(0.5)));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+
+        let second = Duration::from_secs(1);
+        assert_eq!(ReplaySpeed::default().delay(second), Some(second));
+        assert_eq!(
+            ReplaySpeed::Scaled(4.0).delay(second),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(ReplaySpeed::Max.delay(second), None);
+    }
+}
@@ -0,0 +1,44 @@
+//! The original `blitzortung::decode`, kept as a baseline for the benchmark
+//! and for equivalence tests against the current decoder.
+
+use std::collections::HashMap;
+
+#[allow(clippy::needless_range_loop, clippy::explicit_counter_loop)]
+pub fn decode(input: &str) -> String {
+    if input.is_empty() {
+        return String::new();
+    }
+
+    let data: Vec<char> = input.chars().collect();
+    let mut dictionary: HashMap<u32, Vec<char>> = HashMap::new();
+    let mut result = String::with_capacity(input.len() * 2);
+
+    let curr_char = data[0];
+    let mut old_phrase = vec![curr_char];
+    result.push(curr_char);
+    let mut code: u32 = 256;
+
+    for i in 1..data.len() {
+        let curr_code = data[i] as u32;
+
+        let phrase = if curr_code < 256 {
+            vec![data[i]]
+        } else {
+            dictionary.get(&curr_code).cloned().unwrap_or_else(|| {
+                let mut new_phrase = old_phrase.clone();
+                new_phrase.push(old_phrase[0]);
+                new_phrase
+            })
+        };
+
+        result.extend(phrase.iter());
+
+        let mut new_dict_entry = old_phrase.clone();
+        new_dict_entry.push(phrase[0]);
+        dictionary.insert(code, new_dict_entry);
+        code += 1;
+        old_phrase = phrase;
+    }
+
+    result
+}
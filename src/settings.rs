@@ -0,0 +1,367 @@
+//! The popup's settings, saved as JSON in the user's config directory so
+//! they survive hiding the popup and restarting it.
+//!
+//! Files carry a schema version. Fields added later fall back to their
+//! defaults when missing; anything that changes shape gets a migration, so
+//! old files keep loading.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::alerts::{AlertRule, AlertSettings};
+use crate::cells::CellSettings;
+use crate::database;
+use crate::geo::Location;
+use crate::store::Retention;
+
+/// The version [`Settings`] are written as.
+pub const SETTINGS_VERSION: u64 = 1;
+
+/// Upgrades for older files, oldest first: `MIGRATIONS[n]` turns version
+/// `n + 1` into version `n + 2`.
+const MIGRATIONS: &[fn(&mut Value)] = &[];
+
+/// Where the popup keeps its settings.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("popuppp").join("settings.json"))
+}
+
+/// Everything about the popup worth keeping between runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u64,
+    pub auto_connect: bool,
+    /// Keep collecting while hidden. Off unless asked for: hiding used to
+    /// always disconnect.
+    pub background: bool,
+    /// The Blitzortung server to connect to, or `None` for all of them in
+    /// turn.
+    pub server: Option<String>,
+    /// What the popup keeps in memory, and in the database.
+    pub retention: RetentionSettings,
+    pub history_retention: RetentionSettings,
+    pub home: Option<Location>,
+    pub areas_enabled: bool,
+    /// As [`Area`](crate::areas::Area)s are written, e.g. `circle 45,9 50`.
+    pub areas: Vec<String>,
+    pub nearest_first: bool,
+    pub radius_km: Option<f64>,
+    pub rate_radius_km: f64,
+    pub alerts: AlertRuleSettings,
+    pub cells: CellRuleSettings,
+    pub window: Option<WindowFrame>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            auto_connect: true,
+            background: false,
+            server: None,
+            retention: Retention::default().into(),
+            history_retention: database::DEFAULT_RETENTION.into(),
+            home: None,
+            areas_enabled: false,
+            areas: Vec::new(),
+            nearest_first: false,
+            radius_km: None,
+            rate_radius_km: 50.0,
+            alerts: AlertSettings::default().into(),
+            cells: CellSettings::default().into(),
+            window: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Parse a settings file, migrating it from whichever version wrote it.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let mut value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or("settings have no version")?;
+        if version > SETTINGS_VERSION {
+            tracing::warn!(
+                version,
+                "settings are from a newer version, ignoring what it added"
+            );
+        }
+        migrate(&mut value, version, MIGRATIONS);
+        let mut settings: Settings = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        settings.version = SETTINGS_VERSION;
+        Ok(settings)
+    }
+
+    /// The settings saved at `path`, or the defaults if there are none yet.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Write the settings to `path`, replacing the old file in one go so a
+    /// crash never leaves half of one behind.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, json)?;
+        std::fs::rename(&partial, path)
+    }
+}
+
+/// Apply the migrations a version `from` file needs, in order.
+fn migrate(value: &mut Value, from: u64, migrations: &[fn(&mut Value)]) {
+    let done = from.saturating_sub(1) as usize;
+    for (i, migration) in migrations.iter().enumerate().skip(done) {
+        migration(value);
+        value["version"] = Value::from(i as u64 + 2);
+    }
+}
+
+/// A [`Retention`], with the age in minutes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetentionSettings {
+    pub max_count: usize,
+    pub max_age_minutes: Option<u64>,
+}
+
+impl From<Retention> for RetentionSettings {
+    fn from(retention: Retention) -> Self {
+        Self {
+            max_count: retention.max_count,
+            max_age_minutes: retention.max_age.map(|age| age.as_secs() / 60),
+        }
+    }
+}
+
+impl From<RetentionSettings> for Retention {
+    fn from(settings: RetentionSettings) -> Self {
+        Self {
+            max_count: settings.max_count,
+            max_age: settings
+                .max_age_minutes
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+        }
+    }
+}
+
+/// [`AlertSettings`], with times in minutes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRuleSettings {
+    pub rules: Vec<RuleSettings>,
+    pub all_clear_minutes: u64,
+    pub pop_up: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RuleSettings {
+    pub radius_km: f64,
+    pub min_strikes: usize,
+    pub window_minutes: u64,
+}
+
+impl From<AlertSettings> for AlertRuleSettings {
+    fn from(settings: AlertSettings) -> Self {
+        Self {
+            rules: settings
+                .rules
+                .iter()
+                .map(|rule| RuleSettings {
+                    radius_km: rule.radius_km,
+                    min_strikes: rule.min_strikes,
+                    window_minutes: rule.window.as_secs() / 60,
+                })
+                .collect(),
+            all_clear_minutes: settings.all_clear_after.as_secs() / 60,
+            pop_up: settings.pop_up,
+        }
+    }
+}
+
+impl From<AlertRuleSettings> for AlertSettings {
+    fn from(settings: AlertRuleSettings) -> Self {
+        Self {
+            rules: settings
+                .rules
+                .iter()
+                .map(|rule| AlertRule {
+                    radius_km: rule.radius_km,
+                    min_strikes: rule.min_strikes,
+                    window: Duration::from_secs(rule.window_minutes * 60),
+                })
+                .collect(),
+            all_clear_after: Duration::from_secs(settings.all_clear_minutes * 60),
+            pop_up: settings.pop_up,
+        }
+    }
+}
+
+/// [`CellSettings`], with the window in minutes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CellRuleSettings {
+    pub radius_km: f64,
+    pub min_strikes: usize,
+    pub window_minutes: u64,
+}
+
+impl From<CellSettings> for CellRuleSettings {
+    fn from(settings: CellSettings) -> Self {
+        Self {
+            radius_km: settings.radius_km,
+            min_strikes: settings.min_strikes,
+            window_minutes: settings.window.as_secs() / 60,
+        }
+    }
+}
+
+impl From<CellRuleSettings> for CellSettings {
+    fn from(settings: CellRuleSettings) -> Self {
+        Self {
+            radius_km: settings.radius_km,
+            min_strikes: settings.min_strikes,
+            window: Duration::from_secs(settings.window_minutes * 60),
+        }
+    }
+}
+
+/// Where the popup window was, in screen points with the origin at the
+/// bottom left as Cocoa has it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowFrame {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl WindowFrame {
+    /// How much of a restored window has to be on a screen, in points each
+    /// way, to count as reachable.
+    const MIN_VISIBLE: f64 = 40.0;
+
+    /// Whether enough of the frame is on one of `screens` to be seen and
+    /// dragged, which it may not be after a display went away or changed
+    /// resolution.
+    pub fn is_on_screen(&self, screens: &[WindowFrame]) -> bool {
+        screens.iter().any(|screen| {
+            let width = (self.x + self.width).min(screen.x + screen.width) - self.x.max(screen.x);
+            let height =
+                (self.y + self.height).min(screen.y + screen.height) - self.y.max(screen.y);
+            width >= Self::MIN_VISIBLE && height >= Self::MIN_VISIBLE
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("popuppp").join("settings.json");
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+
+        let settings = Settings {
+            auto_connect: false,
+            server: Some("wss://ws7.blitzortung.org".to_string()),
+            home: Some(Location::new(45.46, 9.19)),
+            areas: vec!["circle 45,9 50".to_string()],
+            window: Some(WindowFrame {
+                x: 100.0,
+                y: 200.0,
+                width: 400.0,
+                height: 300.0,
+            }),
+            ..Default::default()
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path).unwrap(), settings);
+
+        // The domain types come back as they were
+        let alerts = AlertSettings::default();
+        assert_eq!(
+            AlertSettings::from(AlertRuleSettings::from(alerts.clone())),
+            alerts
+        );
+        assert_eq!(
+            Retention::from(RetentionSettings::from(Retention::default())),
+            Retention::default()
+        );
+
+        std::fs::write(&path, "{").unwrap();
+        assert!(Settings::load(&path).is_err());
+        assert!(Settings::from_json(r#"{"auto_connect":false}"#).is_err());
+    }
+
+    #[test]
+    fn old_and_new_files_still_load() {
+        // Written before most settings existed: the rest are defaults
+        let settings = Settings::from_json(r#"{"version":1,"auto_connect":false}"#).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                auto_connect: false,
+                ..Default::default()
+            }
+        );
+
+        // Written by a later version: what it added is ignored
+        let settings =
+            Settings::from_json(r#"{"version":99,"background":true,"theme":"dark"}"#).unwrap();
+        assert!(settings.background);
+        assert_eq!(settings.version, SETTINGS_VERSION);
+
+        // Each migration runs once, in order, from the file's version on
+        let migrations: &[fn(&mut Value)] = &[
+            |v| v["steps"] = Value::from("a"),
+            |v| v["steps"] = Value::from(format!("{}b", v["steps"].as_str().unwrap_or(""))),
+        ];
+        let mut v1 = serde_json::json!({"version": 1});
+        migrate(&mut v1, 1, migrations);
+        assert_eq!(v1, serde_json::json!({"version": 3, "steps": "ab"}));
+        let mut v2 = serde_json::json!({"version": 2});
+        migrate(&mut v2, 2, migrations);
+        assert_eq!(v2, serde_json::json!({"version": 3, "steps": "b"}));
+        let mut v3 = serde_json::json!({"version": 3});
+        migrate(&mut v3, 3, migrations);
+        assert_eq!(v3, serde_json::json!({"version": 3}));
+    }
+
+    #[test]
+    fn frames_off_every_screen_are_not_restored() {
+        let frame = |x, y, width, height| WindowFrame {
+            x,
+            y,
+            width,
+            height,
+        };
+        // A laptop screen with an external one to its right
+        let screens = [
+            frame(0.0, 0.0, 1440.0, 875.0),
+            frame(1440.0, 0.0, 2560.0, 1415.0),
+        ];
+        let popup = |x, y| frame(x, y, 400.0, 300.0);
+        assert!(popup(100.0, 200.0).is_on_screen(&screens));
+        assert!(popup(3000.0, 1000.0).is_on_screen(&screens));
+        // Mostly off the edge, with a corner left to grab
+        assert!(popup(-360.0, 835.0).is_on_screen(&screens));
+
+        // Where the external screen was, once it is unplugged
+        assert!(!popup(3000.0, 1000.0).is_on_screen(&screens[..1]));
+        assert!(!popup(-380.0, 200.0).is_on_screen(&screens));
+        assert!(!popup(100.0, 860.0).is_on_screen(&screens[..1]));
+        assert!(!popup(100.0, 200.0).is_on_screen(&[]));
+    }
+}
@@ -0,0 +1,234 @@
+//! Strikes counted per minute for the rate charts, and by polarity.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use egui::{Color32, Rect, Sense, pos2, vec2};
+
+use crate::blitzortung::LightningStrike;
+
+/// How many minutes of counts a [`RateHistogram`] keeps.
+pub const MINUTES: usize = 60;
+
+/// Strikes per minute over the last [`MINUTES`] minutes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateHistogram {
+    /// Ring of counts, indexed by minute modulo [`MINUTES`], along with the
+    /// minute each slot is counting.
+    counts: [u32; MINUTES],
+    minutes: [u64; MINUTES],
+    newest: u64,
+}
+
+impl Default for RateHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; MINUTES],
+            minutes: [u64::MAX; MINUTES],
+            newest: 0,
+        }
+    }
+}
+
+impl RateHistogram {
+    /// Count a strike in `minute`, unless it is older than the histogram.
+    pub fn add(&mut self, minute: u64) {
+        if minute + (MINUTES as u64) <= self.newest {
+            return;
+        }
+        self.newest = self.newest.max(minute);
+        let slot = minute as usize % MINUTES;
+        if self.minutes[slot] != minute {
+            self.minutes[slot] = minute;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] += 1;
+    }
+
+    /// Counts for the [`MINUTES`] minutes up to and including `minute`,
+    /// oldest first.
+    pub fn counts(&self, minute: u64) -> [u32; MINUTES] {
+        std::array::from_fn(|i| {
+            let Some(m) = (minute + i as u64 + 1).checked_sub(MINUTES as u64) else {
+                return 0;
+            };
+            let slot = m as usize % MINUTES;
+            if self.minutes[slot] == m {
+                self.counts[slot]
+            } else {
+                0
+            }
+        })
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Strikes counted by the sign of their polarity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PolarityCounts {
+    pub positive: u64,
+    pub negative: u64,
+    /// Polarity zero, i.e. not determined.
+    pub unknown: u64,
+}
+
+impl PolarityCounts {
+    pub fn add(&mut self, pol: i32) {
+        match pol.signum() {
+            1 => self.positive += 1,
+            -1 => self.negative += 1,
+            _ => self.unknown += 1,
+        }
+    }
+}
+
+impl std::fmt::Display for PolarityCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "+{} −{} ·{}", self.positive, self.negative, self.unknown)
+    }
+}
+
+/// Rates and polarity counts worldwide, per Blitzortung region and near
+/// home.
+#[derive(Debug, Clone, Default)]
+pub struct StrikeStats {
+    pub global: RateHistogram,
+    pub regions: BTreeMap<u32, RateHistogram>,
+    pub home: RateHistogram,
+    pub polarity: PolarityCounts,
+    pub home_polarity: PolarityCounts,
+}
+
+impl StrikeStats {
+    /// Count `strike`, near home too if `near_home`.
+    pub fn record(&mut self, strike: &LightningStrike, near_home: bool) {
+        let minute = minute_of(strike.timestamp());
+        self.global.add(minute);
+        self.regions.entry(strike.region).or_default().add(minute);
+        self.polarity.add(strike.pol);
+        if near_home {
+            self.home.add(minute);
+            self.home_polarity.add(strike.pol);
+        }
+    }
+
+    /// Count the strikes near home over from `strikes`, after home or what
+    /// counts as near changed.
+    pub fn recount_home<'a>(&mut self, near_home: impl IntoIterator<Item = &'a LightningStrike>) {
+        self.home.clear();
+        self.home_polarity = PolarityCounts::default();
+        for strike in near_home {
+            self.home.add(minute_of(strike.timestamp()));
+            self.home_polarity.add(strike.pol);
+        }
+    }
+}
+
+/// Minutes since the epoch.
+pub fn minute_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 60)
+}
+
+/// Draws `counts` as bars filling the available width, scaled to the
+/// highest, newest on the right.
+pub fn sparkline(ui: &mut egui::Ui, counts: &[u32], height: f32, color: Color32) -> egui::Response {
+    let (response, painter) =
+        ui.allocate_painter(vec2(ui.available_width(), height), Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let width = rect.width() / counts.len().max(1) as f32;
+    for (i, &count) in counts.iter().enumerate() {
+        if count > 0 {
+            let left = rect.left() + i as f32 * width;
+            let top = rect.bottom() - rect.height() * count as f32 / max;
+            let bar = Rect::from_min_max(pos2(left, top), pos2(left + width * 0.8, rect.bottom()));
+            painter.rect_filled(bar, 0.0, color);
+        }
+    }
+
+    let hovered = response
+        .hover_pos()
+        .map(|pos| (((pos.x - rect.left()) / width) as usize).min(counts.len().saturating_sub(1)));
+    match hovered {
+        Some(i) if !counts.is_empty() => response.on_hover_text(format!(
+            "{} min ago: {} strikes",
+            counts.len() - 1 - i,
+            counts[i]
+        )),
+        _ => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_at;
+
+    #[test]
+    fn strikes_are_counted_per_minute() {
+        let mut histogram = RateHistogram::default();
+        for minute in [100, 100, 101, 103, 103, 103] {
+            histogram.add(minute);
+        }
+        let counts = histogram.counts(103);
+        assert_eq!(counts[MINUTES - 4..], [2, 1, 0, 3]);
+        assert_eq!(counts[..MINUTES - 4].iter().sum::<u32>(), 0);
+        // Shifted along as time goes by, with nothing new yet
+        assert_eq!(histogram.counts(104)[MINUTES - 2..], [3, 0]);
+
+        // A late strike lands where it belongs, one older than the
+        // histogram is dropped
+        histogram.add(102);
+        histogram.add(103 - MINUTES as u64);
+        assert_eq!(histogram.counts(103)[MINUTES - 4..], [2, 1, 1, 3]);
+        assert_eq!(histogram.counts(103).iter().sum::<u32>(), 7);
+
+        // An hour later, the old minutes have made way
+        histogram.add(103 + MINUTES as u64);
+        assert_eq!(
+            histogram.counts(103 + MINUTES as u64).iter().sum::<u32>(),
+            1
+        );
+        assert_eq!(histogram.counts(3), [0; MINUTES]);
+    }
+
+    #[test]
+    fn strikes_are_counted_by_region_and_polarity() {
+        let mut stats = StrikeStats::default();
+        for (secs, region, pol, near) in [
+            (0, 1, 1, true),
+            (30, 1, -1, false),
+            (60, 3, -1, true),
+            (70, 3, 0, false),
+        ] {
+            let mut strike = strike_at(secs, 0.0, 0.0);
+            strike.region = region;
+            strike.pol = pol;
+            stats.record(&strike, near);
+        }
+
+        assert_eq!(stats.global.counts(1)[MINUTES - 2..], [2, 2]);
+        assert_eq!(stats.regions[&1].counts(1)[MINUTES - 2..], [2, 0]);
+        assert_eq!(stats.regions[&3].counts(1)[MINUTES - 2..], [0, 2]);
+        assert_eq!(stats.home.counts(1)[MINUTES - 2..], [1, 1]);
+        assert_eq!(stats.polarity.to_string(), "+1 −2 ·1");
+        assert_eq!(
+            stats.home_polarity,
+            PolarityCounts {
+                positive: 1,
+                negative: 1,
+                unknown: 0
+            }
+        );
+
+        stats.recount_home([]);
+        assert_eq!(stats.home.counts(1), [0; MINUTES]);
+        assert_eq!(stats.home_polarity, PolarityCounts::default());
+    }
+}
@@ -0,0 +1,258 @@
+//! In-memory strike history that the list, the map and any other view
+//! query, instead of each keeping its own copy.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::blitzortung::LightningStrike;
+
+/// How much history a [`StrikeStore`] keeps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    /// Never hold more strikes than this.
+    pub max_count: usize,
+    /// Drop strikes this much older than the newest one; `None` keeps them
+    /// until `max_count` pushes them out.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_count: 100_000,
+            max_age: Some(Duration::from_secs(30 * 60)),
+        }
+    }
+}
+
+/// Recent strikes, ordered by strike time, oldest first.
+///
+/// A ring buffer: strikes mostly arrive in order, so inserting is constant
+/// time, and eviction only ever pops the oldest end. The few strikes that
+/// arrive late are slotted in where they belong.
+#[derive(Debug, Clone)]
+pub struct StrikeStore {
+    strikes: VecDeque<LightningStrike>,
+    retention: Retention,
+}
+
+impl StrikeStore {
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            strikes: VecDeque::with_capacity(retention.max_count.min(1024)),
+            retention,
+        }
+    }
+
+    /// A store holding at most `capacity` strikes, of any age.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new(Retention {
+            max_count: capacity,
+            max_age: None,
+        })
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Switch to `retention`, dropping whatever it no longer covers.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.evict();
+    }
+
+    pub fn insert(&mut self, strike: LightningStrike) {
+        if self
+            .strikes
+            .back()
+            .is_none_or(|last| last.time <= strike.time)
+        {
+            self.strikes.push_back(strike);
+        } else {
+            let at = self.strikes.partition_point(|s| s.time <= strike.time);
+            self.strikes.insert(at, strike);
+        }
+        self.evict();
+    }
+
+    /// Drop strikes older than the retention age as of `now`. Inserting only
+    /// ages strikes against the newest one, so call this when the feed may
+    /// have gone quiet. Returns how many were dropped.
+    pub fn expire(&mut self, now: SystemTime) -> usize {
+        let Some(cutoff) = self.retention.max_age.and_then(|age| now.checked_sub(age)) else {
+            return 0;
+        };
+        let cutoff = nanos(cutoff);
+        let before = self.strikes.len();
+        while self.strikes.front().is_some_and(|s| s.time < cutoff) {
+            self.strikes.pop_front();
+        }
+        before - self.strikes.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.strikes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strikes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.strikes.clear();
+    }
+
+    /// The strike at `index`, counting from the oldest.
+    pub fn get(&self, index: usize) -> Option<&LightningStrike> {
+        self.strikes.get(index)
+    }
+
+    /// All strikes, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &LightningStrike> + ExactSizeIterator {
+        self.strikes.iter()
+    }
+
+    /// The most recent strike.
+    pub fn latest(&self) -> Option<&LightningStrike> {
+        self.strikes.back()
+    }
+
+    /// Strikes from `from` up to but not including `to`, oldest first.
+    pub fn between(
+        &self,
+        from: SystemTime,
+        to: SystemTime,
+    ) -> impl DoubleEndedIterator<Item = &LightningStrike> {
+        let start = self.position(from);
+        let end = self.position(to).max(start);
+        self.strikes.range(start..end)
+    }
+
+    /// Strikes at or after `from`, oldest first.
+    pub fn since(&self, from: SystemTime) -> impl DoubleEndedIterator<Item = &LightningStrike> {
+        self.strikes.range(self.position(from)..)
+    }
+
+    /// Index of the first strike at or after `time`.
+    fn position(&self, time: SystemTime) -> usize {
+        let nanos = nanos(time);
+        self.strikes.partition_point(|s| s.time < nanos)
+    }
+
+    fn evict(&mut self) {
+        while self.strikes.len() > self.retention.max_count {
+            self.strikes.pop_front();
+        }
+        if let (Some(age), Some(newest)) = (self.retention.max_age, self.strikes.back()) {
+            let cutoff = newest.time.saturating_sub(age.as_nanos() as u64);
+            while self.strikes.front().is_some_and(|s| s.time < cutoff) {
+                self.strikes.pop_front();
+            }
+        }
+    }
+}
+
+impl Default for StrikeStore {
+    fn default() -> Self {
+        Self::new(Retention::default())
+    }
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::strike_at;
+
+    fn times(store: &StrikeStore) -> Vec<u64> {
+        store.iter().map(|s| s.time / 1_000_000_000).collect()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn strikes_are_kept_in_time_order() {
+        let mut store = StrikeStore::with_capacity(10);
+        for secs in [10, 20, 15, 30, 5] {
+            store.insert(strike_at(secs, 0.0, 0.0));
+        }
+        assert_eq!(times(&store), [5, 10, 15, 20, 30]);
+        assert_eq!(store.latest().map(|s| s.time), Some(30_000_000_000));
+        assert_eq!(store.get(1).map(|s| s.time), Some(10_000_000_000));
+    }
+
+    #[test]
+    fn the_oldest_strikes_are_dropped_first() {
+        let mut store = StrikeStore::with_capacity(3);
+        for secs in 1..=5 {
+            store.insert(strike_at(secs, 0.0, 0.0));
+        }
+        assert_eq!(times(&store), [3, 4, 5]);
+
+        // A straggler older than everything kept does not push anything out
+        store.insert(strike_at(1, 0.0, 0.0));
+        assert_eq!(times(&store), [3, 4, 5]);
+    }
+
+    #[test]
+    fn strikes_age_out() {
+        let mut store = StrikeStore::new(Retention {
+            max_count: 100,
+            max_age: Some(Duration::from_secs(60)),
+        });
+        for secs in [0, 30, 60, 90] {
+            store.insert(strike_at(secs, 0.0, 0.0));
+        }
+        // Aged against the newest strike, so replays of old sessions work
+        assert_eq!(times(&store), [30, 60, 90]);
+
+        // ... and against the clock once the feed goes quiet
+        assert_eq!(store.expire(at(145)), 2);
+        assert_eq!(times(&store), [90]);
+        assert_eq!(store.expire(at(145)), 0);
+    }
+
+    #[test]
+    fn tighter_retention_applies_right_away() {
+        let mut store = StrikeStore::default();
+        for secs in 0..1000 {
+            store.insert(strike_at(secs, 0.0, 0.0));
+        }
+        assert_eq!(store.len(), 1000);
+
+        store.set_retention(Retention {
+            max_count: 500,
+            max_age: None,
+        });
+        assert_eq!(times(&store).first(), Some(&500));
+        store.set_retention(Retention {
+            max_count: 500,
+            max_age: Some(Duration::from_secs(10)),
+        });
+        assert_eq!(times(&store), (989..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn strikes_can_be_queried_by_time() {
+        let mut store = StrikeStore::with_capacity(100);
+        for secs in 0..10 {
+            store.insert(strike_at(secs * 10, 0.0, 0.0));
+        }
+
+        let between: Vec<_> = store
+            .between(at(20), at(50))
+            .map(|s| s.time / 1_000_000_000)
+            .collect();
+        assert_eq!(between, [20, 30, 40]);
+        assert_eq!(store.between(at(55), at(50)).count(), 0);
+        assert_eq!(store.since(at(85)).count(), 1);
+        assert_eq!(store.since(UNIX_EPOCH).count(), 10);
+    }
+}
@@ -1,25 +1,272 @@
-use crate::blitzortung::{BLITZ_HANDSHAKE, BLITZSERVERS, LightningStrike};
-use crate::utils::ll;
-use crate::websocket::WebSocketManager;
-use egui;
-use std::sync::Arc;
-use std::sync::Mutex;
+use crate::alerts::{AlertEvent, AlertMonitor, AlertRule};
+use crate::areas::{Area, AreaFilter, StrikeFilter};
+use crate::blitzortung::{BLITZSERVERS, LightningStrike};
+use crate::cells::{CellTracker, Eta, StormCell};
+use crate::client::{BlitzEvent, BlitzortungClient};
+use crate::config::{self, Config};
+use crate::database::{self, DatabaseError, StrikeDatabase, StrikeWriter};
+use crate::export::{self, ExportFormat};
+use crate::geo::{Location, compass_point};
+use crate::logging::{self, LogConfig};
+use crate::map::MapView;
+use crate::recording::ReplaySpeed;
+use crate::settings::{Settings, WindowFrame};
+use crate::stats::{self, MINUTES, StrikeStats};
+use crate::store::{Retention, StrikeStore};
+use crate::utils::format_utc;
+use crate::websocket::Backoff;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+/// Speeds offered by the replay controls.
+const REPLAY_SPEEDS: [ReplaySpeed; 4] = [
+    ReplaySpeed::Scaled(1.0),
+    ReplaySpeed::Scaled(10.0),
+    ReplaySpeed::Scaled(100.0),
+    ReplaySpeed::Max,
+];
 
 #[derive(Debug)]
 pub struct TrrpyApp {
-    name: String,
     counter: i32,
     text_input: String,
     mouse_pos: egui::Pos2,
     last_key: Option<String>,
     pub esc_pressed: bool,
+    /// Set when an alert wants the hidden popup shown; the host clears it.
+    pub show_requested: bool,
     pub prev_pid: Option<u32>,
-    websocket_manager: Option<Arc<Mutex<WebSocketManager>>>,
+    client: Option<BlitzortungClient>,
     connection_status: ConnectionStatus,
-    lightning_strikes: Vec<String>,
-    max_strikes: usize,
+    strikes: StrikeStore,
+    home: Option<Location>,
+    home_input: String,
+    home_error: Option<String>,
+    areas: AreaFilter,
+    area_input: String,
+    area_error: Option<String>,
+    /// Strikes the area filter kept out of history.
+    filtered_out: usize,
+    order: StrikeOrder,
+    /// Only list strikes this close to home, when set.
+    radius_km: Option<f64>,
+    alerts: AlertMonitor,
+    banner: Banner,
+    cells: CellTracker,
+    stats: StrikeStats,
+    /// What counts as near home for the rate chart.
+    rate_radius_km: f64,
+    map: MapView,
+    decode_errors: FeedErrors,
+    invalid_strikes: FeedErrors,
+    truncated: FeedErrors,
     visible: bool,
     auto_connect: bool,
+    /// A replay is on in place of the feed, so showing the popup must not
+    /// auto-connect over it. Not saved, unlike `auto_connect`.
+    replaying: bool,
+    /// Keep the feed open and strikes coming while the popup is hidden.
+    background: bool,
+    /// Strikes that arrived since the popup was last hidden, and when that
+    /// was.
+    missed: usize,
+    hidden_at: Option<SystemTime>,
+    /// The Blitzortung server to use, or all of them in turn.
+    server: Option<String>,
+    /// The servers and most strikes `config.toml` allows, the hotkey it
+    /// set and what was wrong with it last time it was loaded.
+    servers: Vec<String>,
+    max_strikes: usize,
+    hotkey: String,
+    /// The retention the user asked for. The store keeps it only up to
+    /// `max_strikes`, and this is what gets saved.
+    retention: Retention,
+    config_error: Option<String>,
+    /// Where the host last had the window.
+    window: Option<WindowFrame>,
+    /// Where settings are saved, what was saved last and when they have
+    /// been changing since.
+    settings_path: Option<PathBuf>,
+    saved_settings: Option<Settings>,
+    settings_changed_at: Option<Instant>,
+    show_replay: bool,
+    replay_path: String,
+    replay_speed: ReplaySpeed,
+    recording: bool,
+    recording_path: String,
+    history: HistoryPanel,
+    export: ExportPanel,
+    log_panel: LogPanel,
+}
+
+/// Settings are saved once they have stopped changing for this long, so
+/// dragging a value does not write the file every frame.
+const SAVE_SETTINGS_AFTER: Duration = Duration::from_secs(1);
+
+/// How many strikes a history search lists at most.
+const HISTORY_ROWS: usize = 500;
+
+/// The strike database, and what to look up in it.
+#[derive(Debug)]
+struct HistoryPanel {
+    /// Where strikes are saved, once [`TrrpyApp::open_history`] was called.
+    path: Option<PathBuf>,
+    /// Our own connection, for searches; the client writes through another.
+    db: Option<StrikeDatabase>,
+    retention: Retention,
+    /// Search the last this many hours, or everything when 0.
+    hours: u64,
+    bbox_input: String,
+    results: Vec<LightningStrike>,
+    /// How many strikes the database held at the last search.
+    stored: usize,
+    error: Option<String>,
+}
+
+impl Default for HistoryPanel {
+    fn default() -> Self {
+        Self {
+            path: None,
+            db: None,
+            retention: database::DEFAULT_RETENTION,
+            hours: 1,
+            bbox_input: String::new(),
+            results: Vec::new(),
+            stored: 0,
+            error: None,
+        }
+    }
+}
+
+/// What to export the strike history as, and where.
+#[derive(Debug)]
+struct ExportPanel {
+    format: ExportFormat,
+    path: String,
+    signals: bool,
+    /// Only the last this many minutes, or everything retained when 0.
+    minutes: u64,
+    /// Only strikes in the areas, even when the area filter is off.
+    in_areas: bool,
+    /// From the database rather than what is in memory.
+    from_history: bool,
+    /// How the last export went.
+    result: Option<Result<String, String>>,
+}
+
+impl Default for ExportPanel {
+    fn default() -> Self {
+        let format = ExportFormat::GeoJson;
+        Self {
+            format,
+            path: std::env::temp_dir()
+                .join("strikes")
+                .with_extension(format.extension())
+                .display()
+                .to_string(),
+            signals: false,
+            minutes: 0,
+            in_areas: false,
+            from_history: false,
+            result: None,
+        }
+    }
+}
+
+/// Edits to the logging setup that have not been applied yet.
+#[derive(Debug)]
+struct LogPanel {
+    draft: LogConfig,
+    to_file: bool,
+    dir: String,
+    error: Option<String>,
+}
+
+impl LogPanel {
+    fn new() -> Self {
+        let draft = logging::handle()
+            .map(|handle| handle.config())
+            .unwrap_or_default();
+        let dir = draft
+            .file
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("popuppp-logs"));
+        Self {
+            to_file: draft.file.is_some(),
+            dir: dir.display().to_string(),
+            draft,
+            error: None,
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        let Some(handle) = logging::handle() else {
+            ui.label("Logging is not set up");
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.draft.filter)
+                .on_hover_text("e.g. info,popuppp::websocket=debug");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.draft.stderr, "stderr");
+            if cfg!(target_os = "macos") {
+                ui.checkbox(&mut self.draft.oslog, "Unified logging");
+            }
+            ui.checkbox(&mut self.to_file, "Files in");
+            ui.add_enabled(self.to_file, egui::TextEdit::singleline(&mut self.dir));
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                self.draft.file = self.to_file.then(|| PathBuf::from(&self.dir));
+                self.error = handle
+                    .reconfigure(self.draft.clone())
+                    .err()
+                    .map(|e| e.to_string());
+            }
+            if ui.button("Revert").clicked() {
+                *self = LogPanel::new();
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+}
+
+/// What the banner at the top of the popup says, if anything.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum Banner {
+    #[default]
+    None,
+    /// The alert in progress.
+    Alert,
+    AllClear(SystemTime),
+}
+
+/// How the strike list is sorted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum StrikeOrder {
+    #[default]
+    Newest,
+    Nearest,
+}
+
+/// Frames that had to be dropped, counted by what was wrong with them.
+#[derive(Debug, Default)]
+struct FeedErrors {
+    count: usize,
+    last: Option<String>,
+}
+
+impl FeedErrors {
+    fn record(&mut self, message: String) {
+        self.count += 1;
+        self.last = Some(message);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -27,47 +274,324 @@ enum ConnectionStatus {
     Disconnected,
     Connecting,
     Connected,
+    Replaying(PathBuf),
+    Reconnecting {
+        attempt: u32,
+        url: String,
+        retry_at: Instant,
+    },
     Error(String),
 }
 
 impl Default for TrrpyApp {
     fn default() -> Self {
         Self {
-            name: "Trrpy".to_owned(),
             counter: 0,
             text_input: "Type something here...".to_owned(),
             mouse_pos: egui::Pos2::ZERO,
             last_key: None,
             esc_pressed: false,
+            show_requested: false,
             prev_pid: None,
-            websocket_manager: None,
+            client: None,
             connection_status: ConnectionStatus::Disconnected,
-            lightning_strikes: Vec::new(),
-            max_strikes: 100, // Keep only the last 100 strikes
+            strikes: StrikeStore::default(),
+            home: None,
+            home_input: String::new(),
+            home_error: None,
+            areas: AreaFilter::default(),
+            area_input: String::new(),
+            area_error: None,
+            filtered_out: 0,
+            order: StrikeOrder::default(),
+            radius_km: None,
+            alerts: AlertMonitor::default(),
+            banner: Banner::default(),
+            cells: CellTracker::default(),
+            stats: StrikeStats::default(),
+            rate_radius_km: 50.0,
+            map: MapView::default(),
+            decode_errors: FeedErrors::default(),
+            invalid_strikes: FeedErrors::default(),
+            truncated: FeedErrors::default(),
             visible: false,
             auto_connect: true,
+            replaying: false,
+            background: false,
+            missed: 0,
+            hidden_at: None,
+            server: None,
+            servers: BLITZSERVERS.iter().map(|s| s.to_string()).collect(),
+            max_strikes: Retention::default().max_count,
+            hotkey: config::DEFAULT_HOTKEY.to_string(),
+            retention: Retention::default(),
+            config_error: None,
+            window: None,
+            settings_path: None,
+            saved_settings: None,
+            settings_changed_at: None,
+            show_replay: false,
+            replay_path: default_recording_path(),
+            replay_speed: ReplaySpeed::default(),
+            recording: false,
+            recording_path: default_recording_path(),
+            history: HistoryPanel::default(),
+            export: ExportPanel::default(),
+            log_panel: LogPanel::new(),
         }
     }
 }
 
 impl TrrpyApp {
+    /// Save every strike the feed delivers to the database at `path`, and
+    /// search it from the History section.
+    pub fn open_history(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        // Nothing shows as open until the count and the writer are there too
+        let retention = self.history.retention;
+        let client = self.client.as_ref();
+        let opened = StrikeDatabase::open(path).and_then(|db| {
+            let stored = db.len()?;
+            let writer = client
+                .map(|_| StrikeWriter::open(path, retention))
+                .transpose()?;
+            Ok((db, stored, writer))
+        });
+        let (db, stored, writer) = match opened {
+            Ok(opened) => opened,
+            Err(error) => {
+                self.history.error = Some(match &error {
+                    DatabaseError::NewerSchema { .. } => format!(
+                        "{} was written by a newer version, leaving it alone",
+                        path.display()
+                    ),
+                    DatabaseError::Io(_) | DatabaseError::Sqlite(_) => {
+                        format!("Cannot open {}: {}", path.display(), error)
+                    }
+                });
+                return Err(error);
+            }
+        };
+        self.history.db = Some(db);
+        self.history.path = Some(path.to_path_buf());
+        self.history.stored = stored;
+        self.history.error = None;
+        if let (Some(client), Some(writer)) = (&self.client, writer) {
+            client.start_persisting(writer);
+        }
+        info!(path = %path.display(), "saving strike history");
+        Ok(())
+    }
+
+    /// Load the settings saved at `path`, and save them there whenever they
+    /// change. A file that cannot be read is left alone.
+    pub fn open_settings(&mut self, path: &Path) -> Result<(), String> {
+        let settings = Settings::load(path)?;
+        info!(path = %path.display(), "loaded settings");
+        self.apply_settings(settings);
+        self.saved_settings = Some(self.settings());
+        self.settings_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// The settings as they are now.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            auto_connect: self.auto_connect,
+            background: self.background,
+            server: self.server.clone(),
+            retention: self.retention.into(),
+            history_retention: self.history.retention.into(),
+            home: self.home,
+            areas_enabled: self.areas.enabled,
+            areas: self.areas.areas.iter().map(Area::to_string).collect(),
+            nearest_first: self.order == StrikeOrder::Nearest,
+            radius_km: self.radius_km,
+            rate_radius_km: self.rate_radius_km,
+            alerts: self.alerts.settings.clone().into(),
+            cells: self.cells.settings().into(),
+            window: self.window,
+            ..Default::default()
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: Settings) {
+        self.auto_connect = settings.auto_connect;
+        self.background = settings.background;
+        self.server = settings.server;
+        self.set_retention(settings.retention.into());
+        self.history.retention = settings.history_retention.into();
+        if let Some(client) = &self.client {
+            client.set_history_retention(self.history.retention);
+        }
+        self.home = settings.home;
+        self.home_input = settings
+            .home
+            .map(|home| home.to_string())
+            .unwrap_or_default();
+        self.alerts.reset();
+        self.areas.enabled = settings.areas_enabled;
+        self.areas.areas = settings
+            .areas
+            .iter()
+            .filter_map(|area| {
+                area.parse()
+                    .inspect_err(|error| warn!(%error, "ignoring saved area"))
+                    .ok()
+            })
+            .collect();
+        self.order = if settings.nearest_first {
+            StrikeOrder::Nearest
+        } else {
+            StrikeOrder::Newest
+        };
+        self.radius_km = settings.radius_km;
+        self.rate_radius_km = settings.rate_radius_km;
+        self.alerts.settings = settings.alerts.into();
+        self.cells.set_settings(settings.cells.into());
+        self.window = settings.window;
+        self.recount_home_rate();
+    }
+
+    /// Take up what `config.toml` says about the feed and the hotkey.
+    pub fn apply_config(&mut self, config: &Config) {
+        let reconnect = self.servers != config.feed.servers && self.feed_open();
+        self.servers = config.feed.servers.clone();
+        if self
+            .server
+            .as_ref()
+            .is_some_and(|server| !self.servers.contains(server))
+        {
+            self.server = None;
+        }
+        self.max_strikes = config.feed.max_strikes;
+        self.set_retention(self.retention);
+        self.hotkey = config.hotkey.to_string();
+        self.config_error = None;
+        if reconnect {
+            self.connect_blitzortung();
+        }
+    }
+
+    /// Show what is wrong with `config.toml`, while the last good one stays
+    /// in effect.
+    pub fn set_config_error(&mut self, error: Option<String>) {
+        self.config_error = error;
+    }
+
+    /// Keep `retention`, up to the most strikes the config allows.
+    fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        self.strikes.set_retention(Retention {
+            max_count: retention.max_count.min(self.max_strikes),
+            ..retention
+        });
+    }
+
+    /// Where the host should put the window, if it has been somewhere.
+    pub fn window_frame(&self) -> Option<WindowFrame> {
+        self.window
+    }
+
+    pub fn set_window_frame(&mut self, frame: WindowFrame) {
+        self.window = Some(frame);
+    }
+
+    /// Save the settings if they changed, once they have settled or right
+    /// away when `now`.
+    fn save_settings(&mut self, now: bool) {
+        let Some(path) = &self.settings_path else {
+            return;
+        };
+        let settings = self.settings();
+        if self.saved_settings.as_ref() == Some(&settings) {
+            self.settings_changed_at = None;
+            return;
+        }
+        let changed_at = *self.settings_changed_at.get_or_insert_with(Instant::now);
+        if !now && changed_at.elapsed() < SAVE_SETTINGS_AFTER {
+            return;
+        }
+        match settings.save(path) {
+            Ok(()) => debug!(path = %path.display(), "saved settings"),
+            Err(error) => warn!(%error, path = %path.display(), "saving settings failed"),
+        }
+        // Not retried until they change again, failed or not
+        self.saved_settings = Some(settings);
+        self.settings_changed_at = None;
+    }
+
     pub fn set_popup_visible(&mut self, visible: bool) {
         if self.visible != visible {
+            if visible {
+                // Whatever arrived since the host last polled, before the
+                // first frame shows a stale list
+                self.handle_blitz_events();
+            } else {
+                self.missed = 0;
+                self.hidden_at = Some(SystemTime::now());
+                self.save_settings(true);
+            }
             self.visible = visible;
 
-            if visible && self.auto_connect {
+            if visible && self.auto_connect && !self.replaying && !self.feed_open() {
                 self.connect_blitzortung();
+            } else if !visible && self.background {
+                info!("collecting in the background");
+            } else if !visible && self.watching() {
+                info!("keeping the feed open for alerts");
             } else if !visible {
                 self.disconnect_blitzortung();
             }
         }
     }
 
+    /// Process whatever the feed sent and check on the alert in progress.
+    /// Runs every frame while shown; the host calls it while hidden, so
+    /// strikes keep being collected and alerts can bring the popup up.
+    pub fn poll(&mut self) {
+        self.handle_blitz_events();
+        let now = self.clock();
+        if !matches!(self.connection_status, ConnectionStatus::Replaying(_)) {
+            self.strikes.expire(now);
+            self.cells.expire(now);
+        }
+        self.cells.update();
+        if let Some(AlertEvent::AllClear) = self.alerts.tick(now) {
+            info!("all clear");
+            self.banner = Banner::AllClear(now);
+        }
+    }
+
+    /// A replay keeps its own clock, only live strikes age by the wall clock.
+    fn clock(&self) -> SystemTime {
+        match self.connection_status {
+            ConnectionStatus::Replaying(_) => self
+                .strikes
+                .latest()
+                .map_or_else(SystemTime::now, |strike| strike.timestamp()),
+            _ => SystemTime::now(),
+        }
+    }
+
+    /// Whether alerts need the feed while the popup is hidden.
+    fn watching(&self) -> bool {
+        self.home.is_some() && self.alerts.settings.pop_up && !self.alerts.settings.rules.is_empty()
+    }
+
+    fn feed_open(&self) -> bool {
+        matches!(
+            self.connection_status,
+            ConnectionStatus::Connecting
+                | ConnectionStatus::Connected
+                | ConnectionStatus::Reconnecting { .. }
+        )
+    }
+
     pub fn update(&mut self, ctx: &egui::Context) {
         self.esc_pressed = false;
 
-        // Handle incoming WebSocket messages
-        self.handle_websocket_messages();
+        // Handle incoming Blitzortung events
+        self.poll();
 
         // Always request repaint to ensure continuous message processing
         // This solves the issue where lightning data only updates on mouse movement
@@ -99,7 +623,21 @@ impl TrrpyApp {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("⚡ Lightning Strike Monitor");
+            ui.horizontal(|ui| {
+                ui.heading("⚡ Lightning Strike Monitor");
+                self.missed_ui(ui);
+            });
+            self.banner_ui(ui);
+            if let Some(home) = self.home {
+                let now = self.clock();
+                for cell in self.cells.cells() {
+                    if cell_eta(cell, home, now).is_some()
+                        && let Some(summary) = motion_summary(cell, Some(home), now)
+                    {
+                        ui.colored_label(egui::Color32::ORANGE, format!("⛈ {}", summary));
+                    }
+                }
+            }
             ui.separator();
 
             ui.horizontal(|ui| {
@@ -111,12 +649,110 @@ impl TrrpyApp {
                     self.disconnect_blitzortung();
                     self.auto_connect = false;
                 }
+                ui.toggle_value(&mut self.show_replay, "Replay file…");
             });
+            ui.horizontal(|ui| {
+                ui.label("Server:");
+                let before = self.server.clone();
+                egui::ComboBox::from_id_salt("server")
+                    .selected_text(self.server.as_deref().unwrap_or("Any, in turn"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.server, None, "Any, in turn");
+                        for url in &self.servers {
+                            ui.selectable_value(&mut self.server, Some(url.clone()), url);
+                        }
+                    });
+                if self.server != before && self.feed_open() {
+                    self.connect_blitzortung();
+                }
+            });
+
+            if self.show_replay {
+                ui.horizontal(|ui| {
+                    ui.label("Recording:");
+                    ui.text_edit_singleline(&mut self.replay_path);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Speed:");
+                    for speed in REPLAY_SPEEDS {
+                        ui.selectable_value(&mut self.replay_speed, speed, speed.to_string());
+                    }
+                    if ui.button("▶ Replay").clicked() {
+                        self.replay_blitzortung();
+                    }
+                });
+            }
 
             ui.separator();
 
             ui.horizontal(|ui| {
                 ui.checkbox(&mut self.auto_connect, "Auto-connect when popup opens");
+                ui.checkbox(&mut self.background, "Keep collecting while hidden")
+                    .on_hover_text("Otherwise hiding the popup disconnects the feed");
+            });
+
+            ui.horizontal(|ui| {
+                let mut retention = self.strikes.retention();
+                let mut minutes = retention.max_age.map_or(0, |age| age.as_secs() / 60);
+                ui.label("Keep up to");
+                let count = ui.add(
+                    egui::DragValue::new(&mut retention.max_count)
+                        .range(1..=self.max_strikes)
+                        .speed(100),
+                );
+                ui.label("strikes for");
+                let age = ui
+                    .add(
+                        egui::DragValue::new(&mut minutes)
+                            .range(0..=24 * 60)
+                            .suffix(" min"),
+                    )
+                    .on_hover_text("0 keeps strikes until the count pushes them out");
+                if count.changed() || age.changed() {
+                    // Only a new count replaces the one asked for beyond the cap
+                    if !count.changed() {
+                        retention.max_count = self.retention.max_count;
+                    }
+                    retention.max_age = (minutes > 0).then(|| Duration::from_secs(minutes * 60));
+                    self.set_retention(retention);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("📍 Home:");
+                let input = ui.add(
+                    egui::TextEdit::singleline(&mut self.home_input)
+                        .hint_text("lat,lon")
+                        .desired_width(140.0),
+                );
+                let enter = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("Set").clicked() || enter {
+                    self.set_home(self.home_input.clone());
+                }
+                if self.home.is_some() && ui.button("Clear").clicked() {
+                    self.home = None;
+                    self.home_input.clear();
+                    self.alerts.reset();
+                    self.recount_home_rate();
+                }
+            });
+            if let Some(error) = &self.home_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            egui::CollapsingHeader::new("🔔 Alerts").show(ui, |ui| self.alerts_ui(ui));
+            egui::CollapsingHeader::new("🔍 Areas").show(ui, |ui| self.areas_ui(ui));
+
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut self.recording, "Record session to")
+                    .changed()
+                {
+                    self.update_recording();
+                }
+                ui.add_enabled(
+                    !self.recording,
+                    egui::TextEdit::singleline(&mut self.recording_path),
+                );
             });
 
             ui.separator();
@@ -124,10 +760,34 @@ impl TrrpyApp {
             // Connection status indicator
             ui.horizontal(|ui| {
                 let (color, text) = match &self.connection_status {
-                    ConnectionStatus::Disconnected => (egui::Color32::GRAY, "Disconnected"),
-                    ConnectionStatus::Connecting => (egui::Color32::YELLOW, "Connecting..."),
-                    ConnectionStatus::Connected => (egui::Color32::GREEN, "Connected"),
-                    ConnectionStatus::Error(err) => (egui::Color32::RED, err.as_str()),
+                    ConnectionStatus::Disconnected => {
+                        (egui::Color32::GRAY, "Disconnected".to_string())
+                    }
+                    ConnectionStatus::Connecting => {
+                        (egui::Color32::YELLOW, "Connecting...".to_string())
+                    }
+                    ConnectionStatus::Connected => (egui::Color32::GREEN, "Connected".to_string()),
+                    ConnectionStatus::Replaying(path) => (
+                        egui::Color32::LIGHT_BLUE,
+                        format!("Replaying {}", path.display()),
+                    ),
+                    ConnectionStatus::Reconnecting {
+                        attempt,
+                        url,
+                        retry_at,
+                    } => {
+                        let wait = retry_at.saturating_duration_since(Instant::now());
+                        (
+                            egui::Color32::ORANGE,
+                            format!(
+                                "Reconnecting to {} in {:.0}s (attempt {})",
+                                url,
+                                wait.as_secs_f32().ceil(),
+                                attempt
+                            ),
+                        )
+                    }
+                    ConnectionStatus::Error(err) => (egui::Color32::RED, err.clone()),
                 };
 
                 // Draw status dot
@@ -141,25 +801,128 @@ impl TrrpyApp {
 
             ui.separator();
 
+            egui::CollapsingHeader::new(format!("⛈ Storm cells ({})", self.cells.cells().len()))
+                .id_salt("storm cells")
+                .show(ui, |ui| self.cells_ui(ui));
+
+            egui::CollapsingHeader::new("📊 Strike rate").show(ui, |ui| self.rates_ui(ui));
+            egui::CollapsingHeader::new("📜 History").show(ui, |ui| self.history_ui(ui));
+            egui::CollapsingHeader::new("💾 Export").show(ui, |ui| self.export_ui(ui));
+
             // Lightning strikes display
-            ui.label(format!(
-                "⚡ Lightning Strikes ({} total)",
-                self.lightning_strikes.len()
-            ));
+            let listed = listed_strikes(&self.strikes, self.home, self.order, self.radius_km);
+            match (self.home, self.radius_km) {
+                (Some(_), Some(radius)) => ui.label(format!(
+                    "⚡ Lightning Strikes ({} within {:.0} km, {} total)",
+                    listed.len(),
+                    radius,
+                    self.strikes.len()
+                )),
+                _ => ui.label(format!(
+                    "⚡ Lightning Strikes ({} total)",
+                    self.strikes.len()
+                )),
+            };
+            if self.home.is_some() {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.order, StrikeOrder::Newest, "Newest first");
+                    ui.selectable_value(&mut self.order, StrikeOrder::Nearest, "Nearest first");
+                    let mut within = self.radius_km.is_some();
+                    let mut radius = self.radius_km.unwrap_or(50.0);
+                    ui.checkbox(&mut within, "Only within");
+                    ui.add_enabled(
+                        within,
+                        egui::DragValue::new(&mut radius)
+                            .range(1.0..=20_000.0)
+                            .suffix(" km"),
+                    );
+                    self.radius_km = within.then_some(radius);
+                });
+            }
 
-            egui::ScrollArea::vertical()
-                .max_height(250.0)
-                .stick_to_bottom(true)
-                .show(ui, |ui| {
-                    if self.lightning_strikes.is_empty() {
-                        ui.label("Waiting for lightning strikes...");
-                    } else {
-                        // Show most recent strikes first
-                        for strike in self.lightning_strikes.iter().rev() {
-                            ui.label(strike);
+            // Dropped frames, split by whether the LZW stream or the JSON was
+            // bad, or the message was cut short
+            let feed_errors = [
+                ("undecodable", &self.decode_errors),
+                ("not strikes", &self.invalid_strikes),
+                ("cut short", &self.truncated),
+            ];
+            if feed_errors.iter().any(|(_, errors)| errors.count > 0) {
+                ui.horizontal(|ui| {
+                    for (label, errors) in feed_errors {
+                        let response = ui.colored_label(
+                            egui::Color32::ORANGE,
+                            format!("{} {}", errors.count, label),
+                        );
+                        if let Some(last) = &errors.last {
+                            response.on_hover_text(last);
                         }
                     }
                 });
+            }
+
+            // Replayed strikes fade by the replay's clock
+            let now = self.clock();
+            egui::CollapsingHeader::new("🗺 Map")
+                .default_open(true)
+                .show(ui, |ui| {
+                    let (home, radius_km) = (self.home, self.radius_km);
+                    let cells = self.cells.cells();
+                    let areas = &self.areas;
+                    self.map.ui(ui, self.strikes.iter(), now, |map| {
+                        if areas.enabled {
+                            let stroke = egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN);
+                            for area in &areas.areas {
+                                map.line(area.outline(), stroke);
+                            }
+                        }
+                        let stroke = egui::Stroke::new(1.5, egui::Color32::ORANGE);
+                        for cell in cells {
+                            map.circle(cell.centroid, cell.radius_km.max(1.0), stroke);
+                            // Where it will be in half an hour
+                            if let Some(motion) = cell.motion()
+                                && !motion.is_stationary()
+                            {
+                                let from = map.pos(cell.centroid);
+                                let to = map.pos(
+                                    cell.centroid
+                                        .destination(motion.heading(), motion.speed_kmh() / 2.0),
+                                );
+                                map.painter().arrow(from, to - from, stroke);
+                            }
+                            map.label(
+                                cell.centroid,
+                                format!("#{}", cell.id),
+                                egui::Color32::ORANGE,
+                            );
+                        }
+                        if let Some(home) = home {
+                            map.marker(home, egui::Color32::WHITE);
+                            if let Some(radius) = radius_km {
+                                map.circle(
+                                    home,
+                                    radius,
+                                    egui::Stroke::new(1.0, egui::Color32::WHITE),
+                                );
+                            }
+                        }
+                    });
+                });
+
+            if self.strikes.is_empty() {
+                ui.label("Waiting for lightning strikes...");
+            } else {
+                let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                egui::ScrollArea::vertical()
+                    .max_height(250.0)
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, listed.len(), |ui, rows| {
+                        for &strike in &listed[rows] {
+                            ui.label(strike_row(strike, self.home))
+                                .on_hover_text(strike_details(strike));
+                        }
+                    });
+            }
 
             ui.separator();
 
@@ -199,136 +962,1378 @@ impl TrrpyApp {
                     ui.label("Text input test:");
                     ui.text_edit_singleline(&mut self.text_input);
                 });
+
+                ui.separator();
+                ui.label("📝 Logging");
+                self.log_panel.ui(ui);
             });
 
             ui.separator();
+            if let Some(error) = &self.config_error {
+                ui.colored_label(egui::Color32::RED, format!("⚠ {}", error));
+            }
             ui.colored_label(
                 egui::Color32::from_rgb(100, 149, 237),
-                "💡 Press ESC or hotkey (Cmd+Shift+K) to hide",
+                format!("💡 Press ESC or hotkey ({}) to hide", self.hotkey),
             );
         });
+
+        self.save_settings(false);
     }
 
-    fn connect_blitzortung(&mut self) {
-        ll("⚡ Connecting to Blitzortung...");
+    /// A badge counting the strikes that came in while hidden; clicking it
+    /// dismisses it.
+    fn missed_ui(&mut self, ui: &mut egui::Ui) {
+        if self.missed == 0 {
+            return;
+        }
+        let badge = egui::Button::new(
+            egui::RichText::new(format!("+{}", self.missed))
+                .strong()
+                .color(egui::Color32::WHITE),
+        )
+        .fill(egui::Color32::from_rgb(200, 60, 40))
+        .corner_radius(8.0);
+        let since = self
+            .hidden_at
+            .map(|at| format!(" since {}", &format_utc(at)[11..19]))
+            .unwrap_or_default();
+        if ui
+            .add(badge)
+            .on_hover_text(format!(
+                "{} strikes arrived while hidden{}",
+                self.missed, since
+            ))
+            .clicked()
+        {
+            self.missed = 0;
+        }
+    }
 
-        if self.websocket_manager.is_none() {
-            let manager = Arc::new(Mutex::new(WebSocketManager::new()));
-            self.websocket_manager = Some(manager.clone());
+    fn banner_ui(&mut self, ui: &mut egui::Ui) {
+        let (fill, text) = match (self.banner, self.alerts.active()) {
+            (Banner::Alert, Some(alert)) => (
+                egui::Color32::from_rgb(170, 40, 40),
+                format!(
+                    "⚠ Lightning {:.0} km {} of home: {}",
+                    alert.nearest_km,
+                    compass_point(alert.bearing),
+                    alert.rule
+                ),
+            ),
+            (Banner::AllClear(at), _) => (
+                egui::Color32::from_rgb(40, 120, 60),
+                format!("✅ All clear since {} UTC", &format_utc(at)[11..16]),
+            ),
+            _ => return,
+        };
+        egui::Frame::new()
+            .fill(fill)
+            .inner_margin(egui::Margin::same(6))
+            .corner_radius(4.0)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::WHITE, text);
+                    if ui.small_button("✕").clicked() {
+                        self.banner = Banner::None;
+                    }
+                });
+            });
+    }
+
+    fn alerts_ui(&mut self, ui: &mut egui::Ui) {
+        if self.home.is_none() {
+            ui.label("Set a home location to get alerts");
         }
+        let settings = &mut self.alerts.settings;
+        let mut remove = None;
+        for (i, rule) in settings.rules.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut rule.min_strikes).range(1..=1000));
+                ui.label(if rule.min_strikes == 1 {
+                    "strike within"
+                } else {
+                    "strikes within"
+                });
+                ui.add(
+                    egui::DragValue::new(&mut rule.radius_km)
+                        .range(1.0..=1000.0)
+                        .suffix(" km"),
+                );
+                if rule.min_strikes > 1 {
+                    let mut minutes = (rule.window.as_secs() / 60).max(1);
+                    ui.label("in");
+                    ui.add(
+                        egui::DragValue::new(&mut minutes)
+                            .range(1..=24 * 60)
+                            .suffix(" min"),
+                    );
+                    rule.window = Duration::from_secs(minutes * 60);
+                }
+                if ui.small_button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            settings.rules.remove(i);
+        }
+        if ui.button("➕ Add rule").clicked() {
+            settings.rules.push(AlertRule::any_within(20.0));
+        }
+        ui.horizontal(|ui| {
+            let mut minutes = settings.all_clear_after.as_secs() / 60;
+            ui.label("All clear after");
+            ui.add(
+                egui::DragValue::new(&mut minutes)
+                    .range(1..=24 * 60)
+                    .suffix(" min"),
+            );
+            ui.label("without strikes");
+            settings.all_clear_after = Duration::from_secs(minutes * 60);
+        });
+        ui.checkbox(&mut settings.pop_up, "Pop up when an alert goes off")
+            .on_hover_text("Keeps the feed open while the popup is hidden");
+    }
 
-        if let Some(ref manager) = self.websocket_manager {
-            if let Ok(mgr) = manager.lock() {
-                // Try the first server
-                mgr.connect(BLITZSERVERS[0].to_string());
-                self.connection_status = ConnectionStatus::Connecting;
+    fn areas_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.areas.enabled, "Only keep strikes in these areas");
+        let mut remove = None;
+        for (i, area) in self.areas.areas.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(area.to_string());
+                if ui.small_button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.areas.areas.remove(i);
+        }
+        ui.horizontal(|ui| {
+            let input = ui
+                .add(
+                    egui::TextEdit::singleline(&mut self.area_input)
+                        .hint_text("box W,S,E,N")
+                        .desired_width(220.0),
+                )
+                .on_hover_text("box W,S,E,N\ncircle LAT,LON KM\npolygon LAT,LON LAT,LON LAT,LON …");
+            let enter = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Add").clicked() || enter {
+                self.add_area();
             }
+        });
+        if let Some(error) = &self.area_error {
+            ui.colored_label(egui::Color32::RED, error);
         }
+        ui.weak(format!(
+            "Blitzortung sends every strike, they are filtered as they arrive ({} dropped)",
+            self.filtered_out
+        ));
     }
 
-    fn disconnect_blitzortung(&mut self) {
-        ll("⚡ Disconnecting from Blitzortung...");
-
-        if let Some(ref manager) = self.websocket_manager {
-            if let Ok(mgr) = manager.lock() {
-                mgr.disconnect();
-                self.connection_status = ConnectionStatus::Disconnected;
+    fn add_area(&mut self) {
+        match self.area_input.parse::<Area>() {
+            Ok(area) => {
+                info!(%area, "area added");
+                self.areas.areas.push(area);
+                self.areas.enabled = true;
+                self.area_input.clear();
+                self.area_error = None;
             }
+            Err(error) => self.area_error = Some(error),
         }
     }
 
-    fn send_blitz_handshake(&self) {
-        if let Some(ref manager) = self.websocket_manager {
-            if let Ok(mgr) = manager.lock() {
-                ll("⚡ Sending Blitzortung handshake...");
-                mgr.send_raw(BLITZ_HANDSHAKE.to_vec());
+    fn cells_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut settings = self.cells.settings();
+            let mut minutes = settings.window.as_secs() / 60;
+            ui.label("At least");
+            let count = ui.add(egui::DragValue::new(&mut settings.min_strikes).range(2..=500));
+            ui.label("strikes");
+            let radius = ui.add(
+                egui::DragValue::new(&mut settings.radius_km)
+                    .range(1.0..=100.0)
+                    .suffix(" km"),
+            );
+            ui.label("apart over");
+            let window = ui.add(
+                egui::DragValue::new(&mut minutes)
+                    .range(1..=120)
+                    .suffix(" min"),
+            );
+            if count.changed() || radius.changed() || window.changed() {
+                settings.window = Duration::from_secs(minutes * 60);
+                self.cells.set_settings(settings);
+            }
+        });
+        if self.cells.cells().is_empty() {
+            ui.label("No storm cells");
+        }
+        let now = self.clock();
+        for cell in self.cells.cells() {
+            ui.label(cell_row(cell, self.home));
+            if let Some(summary) = motion_summary(cell, self.home, now) {
+                ui.weak(summary);
             }
         }
     }
 
-    fn handle_websocket_messages(&mut self) {
-        let messages: Vec<_> = if let Some(ref manager) = self.websocket_manager {
-            if let Ok(mgr) = manager.lock() {
-                let mut msgs = Vec::new();
-                let mut msg_count = 0;
-                while let Some(message) = mgr.try_recv_message() {
-                    msgs.push(message);
-                    msg_count += 1;
-                    // Process up to 50 messages per frame to avoid blocking the UI
-                    if msg_count >= 50 {
-                        break;
+    fn rates_ui(&mut self, ui: &mut egui::Ui) {
+        let minute = stats::minute_of(self.clock());
+        // The minute in progress is still filling up
+        let per_minute = |counts: &[u32; MINUTES]| counts[MINUTES - 2];
+
+        let global = self.stats.global.counts(minute);
+        ui.label(format!(
+            "Worldwide: {}/min, polarity {}",
+            per_minute(&global),
+            self.stats.polarity
+        ));
+        stats::sparkline(ui, &global, 32.0, egui::Color32::LIGHT_BLUE);
+
+        if self.home.is_some() {
+            let home = self.stats.home.counts(minute);
+            ui.horizontal(|ui| {
+                ui.label("Within");
+                let radius = ui.add(
+                    egui::DragValue::new(&mut self.rate_radius_km)
+                        .range(1.0..=1000.0)
+                        .suffix(" km"),
+                );
+                if radius.changed() {
+                    self.recount_home_rate();
+                }
+                ui.label(format!(
+                    "of home: {}/min, polarity {}",
+                    per_minute(&home),
+                    self.stats.home_polarity
+                ));
+            });
+            stats::sparkline(ui, &home, 32.0, egui::Color32::ORANGE);
+        }
+
+        for (region, histogram) in &self.stats.regions {
+            let counts = histogram.counts(minute);
+            ui.label(format!("Region {}: {}/min", region, per_minute(&counts)));
+            stats::sparkline(ui, &counts, 16.0, egui::Color32::GRAY);
+        }
+        ui.weak(format!(
+            "Strikes per minute over the last {} minutes",
+            MINUTES
+        ));
+    }
+
+    fn history_ui(&mut self, ui: &mut egui::Ui) {
+        let history = &mut self.history;
+        let (Some(path), Some(_)) = (&history.path, &history.db) else {
+            ui.label("Strikes are not being saved");
+            return;
+        };
+        ui.weak(format!(
+            "{} strikes saved in {}",
+            history.stored,
+            path.display()
+        ));
+        ui.horizontal(|ui| {
+            ui.label("Keep");
+            let mut days = history
+                .retention
+                .max_age
+                .map_or(0, |age| age.as_secs() / 86_400);
+            let changed = ui
+                .add(
+                    egui::DragValue::new(&mut days)
+                        .range(1..=365)
+                        .suffix(" days"),
+                )
+                .changed();
+            ui.label("or");
+            let changed = ui
+                .add(
+                    egui::DragValue::new(&mut history.retention.max_count)
+                        .range(1_000..=100_000_000)
+                        .speed(10_000),
+                )
+                .changed()
+                || changed;
+            ui.label("strikes");
+            if changed {
+                history.retention.max_age = Some(Duration::from_secs(days * 86_400));
+                if let Some(client) = &self.client {
+                    client.set_history_retention(history.retention);
+                }
+            }
+        });
+        let mut search = false;
+        ui.horizontal(|ui| {
+            ui.label("Last");
+            ui.add(
+                egui::DragValue::new(&mut history.hours)
+                    .range(0..=24 * 365)
+                    .suffix(" h"),
+            )
+            .on_hover_text("0 searches everything saved");
+            ui.label("in");
+            let input = ui.add(
+                egui::TextEdit::singleline(&mut history.bbox_input)
+                    .hint_text("W,S,E,N (anywhere)")
+                    .desired_width(140.0),
+            );
+            let enter = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            search = ui.button("Search").clicked() || enter;
+        });
+        if search {
+            self.search_history();
+        }
+        if let Some(error) = &self.history.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        if !self.history.results.is_empty() {
+            ui.label(format!(
+                "{} most recent matches",
+                self.history.results.len()
+            ));
+            egui::ScrollArea::vertical()
+                .id_salt("history")
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    for strike in self.history.results.iter().rev() {
+                        ui.label(strike_row(strike, self.home))
+                            .on_hover_text(strike_details(strike));
                     }
+                });
+        }
+    }
+
+    /// Look up the saved strikes in the History section's time range and
+    /// bounding box.
+    fn search_history(&mut self) {
+        let since = (self.history.hours > 0)
+            .then(|| {
+                self.clock()
+                    .checked_sub(Duration::from_secs(self.history.hours * 3600))
+            })
+            .flatten();
+        let history = &mut self.history;
+        let Some(db) = &history.db else {
+            return;
+        };
+        let bbox = match history.bbox_input.trim() {
+            "" => None,
+            input => match input.parse() {
+                Ok(bbox) => Some(bbox),
+                Err(error) => {
+                    history.error = Some(error);
+                    return;
                 }
-                msgs
+            },
+        };
+        let filter = StrikeFilter {
+            bbox,
+            since,
+            ..Default::default()
+        };
+        match db
+            .query(&filter, Some(HISTORY_ROWS))
+            .and_then(|results| Ok((results, db.len()?)))
+        {
+            Ok((results, stored)) => {
+                history.results = results;
+                history.stored = stored;
+                history.error = None;
+            }
+            Err(error) => {
+                warn!(%error, "searching strike history failed");
+                history.error = Some(error.to_string());
+            }
+        }
+    }
+
+    fn export_ui(&mut self, ui: &mut egui::Ui) {
+        let panel = &mut self.export;
+        ui.horizontal(|ui| {
+            for format in ExportFormat::ALL {
+                if ui
+                    .selectable_value(&mut panel.format, format, format.to_string())
+                    .changed()
+                {
+                    panel.path = PathBuf::from(&panel.path)
+                        .with_extension(format.extension())
+                        .display()
+                        .to_string();
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("To");
+            ui.text_edit_singleline(&mut panel.path);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Last");
+            ui.add(
+                egui::DragValue::new(&mut panel.minutes)
+                    .range(0..=24 * 60)
+                    .suffix(" min"),
+            )
+            .on_hover_text("0 exports everything retained");
+            ui.add_enabled(
+                !self.areas.areas.is_empty(),
+                egui::Checkbox::new(&mut panel.in_areas, "Only strikes in the areas"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut panel.signals, "Include station signals");
+            ui.add_enabled(
+                self.history.db.is_some(),
+                egui::Checkbox::new(&mut panel.from_history, "From saved history"),
+            )
+            .on_hover_text("Everything saved, rather than what is in memory");
+        });
+        if ui.button("Export").clicked() {
+            self.export_history();
+        }
+        match &self.export.result {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+    }
+
+    /// Write the retained strikes that pass the export panel's filters.
+    fn export_history(&mut self) {
+        let panel = &self.export;
+        let filter = StrikeFilter {
+            areas: if panel.in_areas {
+                self.areas.areas.clone()
             } else {
                 Vec::new()
+            },
+            since: (panel.minutes > 0)
+                .then(|| {
+                    self.clock()
+                        .checked_sub(Duration::from_secs(panel.minutes * 60))
+                })
+                .flatten(),
+            ..Default::default()
+        };
+        let path = PathBuf::from(&panel.path);
+        let result = match &self.history.db {
+            Some(db) if panel.from_history => db
+                .query(&filter, None)
+                .map_err(std::io::Error::other)
+                .and_then(|strikes| {
+                    export::export_file(&path, panel.format, &strikes, panel.signals)
+                }),
+            _ => {
+                let strikes = self.strikes.iter().filter(|strike| filter.matches(strike));
+                export::export_file(&path, panel.format, strikes, panel.signals)
+            }
+        };
+        self.export.result = Some(match result {
+            Ok(count) => {
+                info!(count, path = %path.display(), format = %panel.format, "exported strikes");
+                Ok(format!("Wrote {} strikes to {}", count, path.display()))
+            }
+            Err(error) => {
+                warn!(%error, path = %path.display(), "export failed");
+                Err(format!("Could not write {}: {}", path.display(), error))
+            }
+        });
+    }
+
+    fn connect_blitzortung(&mut self) {
+        info!("connecting to Blitzortung");
+        self.replaying = false;
+
+        // The client rotates through the servers and reconnects on its own
+        match self.server.clone() {
+            Some(url) => self
+                .client()
+                .connect_to(&[url.as_str()], Backoff::default()),
+            None => {
+                let servers = self.servers.clone();
+                let servers: Vec<&str> = servers.iter().map(String::as_str).collect();
+                self.client().connect_to(&servers, Backoff::default());
             }
+        }
+        self.connection_status = ConnectionStatus::Connecting;
+    }
+
+    fn replay_blitzortung(&mut self) {
+        info!(path = %self.replay_path, speed = %self.replay_speed, "replaying");
+
+        // A replay replaces the live feed until it ends or is disconnected
+        self.replaying = true;
+        let (path, speed) = (PathBuf::from(&self.replay_path), self.replay_speed);
+        self.client().replay(path, speed);
+        self.connection_status = ConnectionStatus::Connecting;
+    }
+
+    fn update_recording(&mut self) {
+        let path = PathBuf::from(&self.recording_path);
+        if self.recording {
+            self.client().start_recording(path);
         } else {
-            Vec::new()
+            self.client().stop_recording();
+        }
+    }
+
+    /// The client, started on first use, saving strikes if history is open.
+    fn client(&mut self) -> &BlitzortungClient {
+        let history = &self.history;
+        self.client.get_or_insert_with(|| {
+            let client = BlitzortungClient::new();
+            if let Some(path) = &history.path {
+                match StrikeWriter::open(path, history.retention) {
+                    Ok(writer) => client.start_persisting(writer),
+                    Err(error) => warn!(%error, "cannot save strike history"),
+                }
+            }
+            client
+        })
+    }
+
+    fn disconnect_blitzortung(&mut self) {
+        info!("disconnecting from Blitzortung");
+        self.replaying = false;
+
+        if let Some(ref client) = self.client {
+            client.disconnect();
+            self.connection_status = ConnectionStatus::Disconnected;
+        }
+    }
+
+    fn handle_blitz_events(&mut self) {
+        let Some(ref client) = self.client else {
+            return;
         };
 
-        for message in messages {
-            match message.mtype.as_str() {
-                "connection_status" => {
-                    let payload_str = String::from_utf8_lossy(&message.payload);
-                    ll(&format!("⚡ Connection status: {}", payload_str));
-
-                    if payload_str.contains("connected") {
-                        self.connection_status = ConnectionStatus::Connected;
-                        // Send handshake once connected
-                        self.send_blitz_handshake();
-                    } else if payload_str.contains("failed") || payload_str.contains("error") {
-                        self.connection_status = ConnectionStatus::Error(payload_str.to_string());
-                    } else if payload_str.contains("disconnected") {
-                        self.connection_status = ConnectionStatus::Disconnected;
-                    }
+        // Process up to 50 events per frame to avoid blocking the UI; while
+        // hidden there is no frame to hold up, so catch up on everything
+        let budget = if self.visible { 50 } else { usize::MAX };
+        let events: Vec<_> = std::iter::from_fn(|| client.try_recv_event())
+            .take(budget)
+            .collect();
+
+        for event in events {
+            match event {
+                BlitzEvent::Connected { url } => {
+                    info!(%url, "connected");
+                    self.connection_status = ConnectionStatus::Connected;
+                }
+                BlitzEvent::ReplayStarted { path } => {
+                    info!(path = %path.display(), "replaying");
+                    self.connection_status = ConnectionStatus::Replaying(path);
                 }
-                "raw_text" => {
-                    let payload_str = String::from_utf8_lossy(&message.payload);
-                    self.handle_lightning_message(&payload_str);
+                BlitzEvent::ReplayFailed { path, error } => {
+                    warn!(path = %path.display(), %error, "cannot replay");
+                    self.replaying = false;
+                    self.connection_status = ConnectionStatus::Error(format!(
+                        "Cannot replay {}: {}",
+                        path.display(),
+                        error
+                    ));
                 }
-                _ => {
-                    ll(&format!("⚡ Unknown message type: {}", message.mtype));
+                BlitzEvent::ConnectFailed { url, error } => {
+                    warn!(%url, %error, "connecting failed");
+                    self.replaying = false;
+                    self.connection_status = ConnectionStatus::Error(error);
+                }
+                BlitzEvent::Disconnected => {
+                    info!("disconnected");
+                    self.replaying = false;
+                    self.connection_status = ConnectionStatus::Disconnected;
+                }
+                BlitzEvent::Reconnecting {
+                    attempt,
+                    url,
+                    retry_at,
+                } => {
+                    self.connection_status = ConnectionStatus::Reconnecting {
+                        attempt,
+                        url,
+                        retry_at,
+                    };
+                }
+                BlitzEvent::Strike(strike) => {
+                    self.handle_lightning_message(strike);
+                }
+                BlitzEvent::DecodeFailed { frame, error } => {
+                    warn!(%error, %frame, "undecodable frame");
+                    self.decode_errors.record(error.to_string());
+                }
+                BlitzEvent::InvalidStrike { decoded, error } => {
+                    debug!(%error, %decoded, "not a strike");
+                    self.invalid_strikes.record(error);
+                }
+                BlitzEvent::Truncated { decoded } => {
+                    debug!(%decoded, "truncated message");
+                    self.truncated.record(format!(
+                        "message truncated after {} characters",
+                        decoded.chars().count()
+                    ));
+                }
+                BlitzEvent::ProtocolError(error) => {
+                    warn!(%error, "protocol error");
                 }
             }
         }
     }
 
-    fn handle_lightning_message(&mut self, message: &str) {
-        // Try to decode the message
-        let decoded_message = crate::blitzortung::decode(message);
+    fn set_home(&mut self, input: String) {
+        match input.parse::<Location>() {
+            Ok(home) => {
+                info!(%home, "home location set");
+                self.home = Some(home);
+                self.alerts.reset();
+                self.recount_home_rate();
+                self.home_error = None;
+            }
+            Err(error) => self.home_error = Some(error),
+        }
+        self.home_input = input;
+    }
 
-        // Try to parse as JSON lightning strike
-        if let Ok(strike) = serde_json::from_str::<LightningStrike>(&decoded_message) {
-            let datetime = std::time::UNIX_EPOCH + std::time::Duration::from_micros(strike.time);
+    fn recount_home_rate(&mut self) {
+        let (home, radius) = (self.home, self.rate_radius_km);
+        self.stats
+            .recount_home(self.strikes.iter().filter(|strike| {
+                home.is_some_and(|home| home.distance_km(&Location::of(strike)) <= radius)
+            }));
+    }
 
-            let strike_info =
-                if let Ok(system_time) = datetime.duration_since(std::time::UNIX_EPOCH) {
-                    let secs = system_time.as_secs();
-                    let hours = (secs / 3600) % 24;
-                    let minutes = (secs / 60) % 60;
-                    let seconds = secs % 60;
+    fn handle_lightning_message(&mut self, strike: LightningStrike) {
+        if !self.areas.matches(&strike) {
+            self.filtered_out += 1;
+            return;
+        }
+        if !self.visible {
+            self.missed += 1;
+        }
+        self.cells.insert(&strike);
+        let near_home = self
+            .home
+            .is_some_and(|home| home.distance_km(&Location::of(&strike)) <= self.rate_radius_km);
+        self.stats.record(&strike, near_home);
+        if let Some(home) = self.home
+            && let Some(AlertEvent::Raised(alert)) = self.alerts.check(home, &strike)
+        {
+            warn!(rule = %alert.rule, km = alert.nearest_km, "proximity alert");
+            self.banner = Banner::Alert;
+            self.show_requested |= self.alerts.settings.pop_up && !self.visible;
+        }
+        self.strikes.insert(strike);
+    }
+}
 
-                    format!(
-                        "{:02}:{:02}:{:02} - Lat: {:.4}°, Lon: {:.4}°, Alt: {:.0}m",
-                        hours, minutes, seconds, strike.lat, strike.lon, strike.alt
-                    )
-                } else {
-                    format!(
-                        "Time: {} - Lat: {:.4}°, Lon: {:.4}°, Alt: {:.0}m",
-                        strike.time, strike.lat, strike.lon, strike.alt
-                    )
-                };
+/// The strikes to list: newest or nearest to `home` first, and only those
+/// within `radius_km` of it when set.
+fn listed_strikes(
+    strikes: &StrikeStore,
+    home: Option<Location>,
+    order: StrikeOrder,
+    radius_km: Option<f64>,
+) -> Vec<&LightningStrike> {
+    let Some(home) = home else {
+        return strikes.iter().rev().collect();
+    };
+    let mut listed: Vec<_> = strikes
+        .iter()
+        .rev()
+        .map(|strike| (strike, home.distance_km(&Location::of(strike))))
+        .filter(|&(_, km)| radius_km.is_none_or(|radius| km <= radius))
+        .collect();
+    if order == StrikeOrder::Nearest {
+        listed.sort_by(|a, b| a.1.total_cmp(&b.1));
+    }
+    listed.into_iter().map(|(strike, _)| strike).collect()
+}
+
+/// One line of the strike list, with where it is from `home` if known.
+fn strike_row(strike: &LightningStrike, home: Option<Location>) -> String {
+    let time = format_utc(strike.timestamp());
+    let mut row = format!(
+        "{} {} Lat: {:.4}°, Lon: {:.4}°, {} stations",
+        &time[11..19],
+        polarity(strike.pol),
+        strike.lat,
+        strike.lon,
+        strike.sig.len()
+    );
+    if let Some(home) = home {
+        let there = Location::of(strike);
+        row.push_str(&format!(
+            ", {:.0} km {}",
+            home.distance_km(&there),
+            compass_point(home.bearing_to(&there))
+        ));
+    }
+    row
+}
+
+/// One line of the storm cell list.
+fn cell_row(cell: &StormCell, home: Option<Location>) -> String {
+    let mut row = format!(
+        "#{} at {:.2}°, {:.2}°: {} strikes, {:.1}/min, {:.0} km wide",
+        cell.id,
+        cell.centroid.lat,
+        cell.centroid.lon,
+        cell.strikes,
+        cell.rate_per_min,
+        2.0 * cell.radius_km
+    );
+    if let Some(home) = home {
+        row.push_str(&format!(
+            ", {:.0} km {}",
+            home.distance_km(&cell.centroid),
+            compass_point(home.bearing_to(&cell.centroid))
+        ));
+    }
+    row
+}
+
+/// "Cell #3 moving NE at 35 km/h, ETA 42 min", or as much of it as is
+/// known.
+fn motion_summary(cell: &StormCell, home: Option<Location>, now: SystemTime) -> Option<String> {
+    let motion = cell.motion()?;
+    if motion.is_stationary() {
+        return Some(format!("Cell #{} stationary", cell.id));
+    }
+    let mut summary = format!(
+        "Cell #{} moving {} at {:.0} km/h",
+        cell.id,
+        compass_point(motion.heading()),
+        motion.speed_kmh()
+    );
+    if let Some(eta) = home.and_then(|home| cell_eta(cell, home, now)) {
+        summary.push_str(&format!(", ETA {} min", eta.arrival.as_secs() / 60));
+        if eta.error >= Duration::from_secs(60) {
+            summary.push_str(&format!(" ± {} min", eta.error.as_secs() / 60));
+        }
+    }
+    Some(summary)
+}
+
+fn cell_eta(cell: &StormCell, home: Location, now: SystemTime) -> Option<Eta> {
+    cell.motion()?.eta(home, cell.radius_km, now)
+}
+
+/// Everything else Blitzortung told us about a strike.
+fn strike_details(strike: &LightningStrike) -> String {
+    let mut details = format!(
+        "{}\nAltitude: {:.0} m\nPolarity: {}\nRegion: {}\nStations: {}",
+        format_utc(strike.timestamp()),
+        strike.alt,
+        strike.pol,
+        strike.region,
+        strike.sig.len()
+    );
+    if let Some(delay) = strike.delay {
+        details.push_str(&format!("\nDelay: {:.1} s", delay));
+    }
+    details
+}
 
-            self.lightning_strikes.push(strike_info);
+fn polarity(pol: i32) -> &'static str {
+    match pol.signum() {
+        1 => "+",
+        -1 => "−",
+        _ => "·",
+    }
+}
+
+fn default_recording_path() -> String {
+    std::env::temp_dir()
+        .join("blitzortung.blzrec")
+        .display()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::CellSettings;
+    use crate::fake_server::{
+        FakeServer, FakeServerConfig, strike_at, strike_fixture_records, strike_fixtures,
+    };
+
+    /// Pump the app's event handling until `condition` holds.
+    fn pump_until(app: &mut TrrpyApp, mut condition: impl FnMut(&TrrpyApp) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition(app) {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            app.handle_blitz_events();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn strikes_from_the_feed_end_up_in_the_list() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig {
+            malformed_every: Some(2),
+            invalid_every: Some(4),
+            ping_every: Some(3),
+            ..Default::default()
+        });
+
+        let mut app = TrrpyApp::default();
+        app.client
+            .get_or_insert_with(BlitzortungClient::new)
+            .connect_to(&[server.url.as_str()], Backoff::default());
+        pump_until(&mut app, |app| {
+            matches!(app.connection_status, ConnectionStatus::Connected)
+        });
+        pump_until(&mut app, |app| app.strikes.len() == fixtures.len());
+        // Every frame has been sent by the time the last strike arrives
+        pump_until(&mut app, |app| {
+            app.decode_errors.count == fixtures.len() / 2
+                && app.invalid_strikes.count == fixtures.len() / 4
+        });
+        assert!(
+            app.decode_errors
+                .last
+                .as_deref()
+                .is_some_and(|e| e.contains("invalid code"))
+        );
+
+        app.disconnect_blitzortung();
+        pump_until(&mut app, |app| {
+            matches!(app.connection_status, ConnectionStatus::Disconnected)
+        });
+    }
+
+    #[test]
+    fn strikes_are_formatted_when_shown() {
+        let mut strike = strike_at(1_754_911_261, 45.12344, -9.5);
+        strike.pol = -1;
+        strike.delay = Some(3.2);
+        assert_eq!(
+            strike_row(&strike, None),
+            "11:21:01 − Lat: 45.1234°, Lon: -9.5000°, 0 stations"
+        );
+        assert!(strike_row(&strike, Some(Location::new(45.12344, -8.5))).ends_with(", 78 km W"));
+        assert!(strike_details(&strike).starts_with("2025-08-11T11:21:01.000Z\n"));
+        assert!(strike_details(&strike).ends_with("Delay: 3.2 s"));
+    }
+
+    #[test]
+    fn strikes_can_be_listed_by_distance_from_home() {
+        let mut strikes = StrikeStore::default();
+        // Roughly 10, 200 and 50 km north of home, oldest first
+        for (secs, lat) in [(1, 0.09), (2, 1.8), (3, 0.45)] {
+            strikes.insert(strike_at(secs, lat, 0.0));
+        }
+        let home = Location::new(0.0, 0.0);
+        let lats = |listed: Vec<&LightningStrike>| listed.iter().map(|s| s.lat).collect::<Vec<_>>();
+
+        assert_eq!(
+            lats(listed_strikes(
+                &strikes,
+                None,
+                StrikeOrder::Nearest,
+                Some(1.0)
+            )),
+            [0.45, 1.8, 0.09]
+        );
+        assert_eq!(
+            lats(listed_strikes(
+                &strikes,
+                Some(home),
+                StrikeOrder::Nearest,
+                None
+            )),
+            [0.09, 0.45, 1.8]
+        );
+        assert_eq!(
+            lats(listed_strikes(
+                &strikes,
+                Some(home),
+                StrikeOrder::Newest,
+                Some(100.0)
+            )),
+            [0.45, 0.09]
+        );
+
+        let mut app = TrrpyApp::default();
+        app.set_home("nowhere".to_string());
+        assert!(app.home.is_none() && app.home_error.is_some());
+        app.set_home("0,0".to_string());
+        assert_eq!((app.home, app.home_error), (Some(home), None));
+    }
+
+    #[test]
+    fn close_strikes_raise_an_alert() {
+        let mut app = TrrpyApp::default();
+        app.handle_lightning_message(strike_at(1_000, 0.1, 0.0));
+        assert_eq!(app.banner, Banner::None);
+
+        app.set_home("0,0".to_string());
+        app.handle_lightning_message(strike_at(1_010, 1.0, 0.0));
+        assert_eq!(app.banner, Banner::None);
+        app.handle_lightning_message(strike_at(1_020, 0.1, 0.0));
+        assert_eq!(app.banner, Banner::Alert);
+        assert!(app.show_requested);
+        assert!(app.watching());
+
+        // The strikes are from 1970, so the wall clock says it has been quiet
+        app.poll();
+        assert!(matches!(app.banner, Banner::AllClear(_)));
+        assert!(app.alerts.active().is_none());
+    }
+
+    #[test]
+    fn bursts_of_strikes_show_up_as_storm_cells() {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut app = TrrpyApp::default();
+        app.set_home("45,9".to_string());
+        for n in 0..20 {
+            app.handle_lightning_message(strike_at(now - 60 + n, 45.5, 9.0 + n as f64 * 0.01));
+        }
+        app.poll();
+
+        let cells = app.cells.cells();
+        assert_eq!(cells.len(), 1);
+        let row = cell_row(&cells[0], app.home);
+        assert!(
+            row.starts_with("#1 at 45.50°, 9.10°: 20 strikes, 20.0/min, "),
+            "{row}"
+        );
+        assert!(row.ends_with(", 56 km N"), "{row}");
+    }
+
+    #[test]
+    fn approaching_cells_say_when_they_arrive() {
+        let home = Location::new(45.0, 9.0);
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        // Heading north-east at 35 km/h, 45 km south-west of home by now
+        let end = home.destination(225.0, 45.0);
+        let track = (0..3)
+            .rev()
+            .map(|n| {
+                (
+                    at(3600 - n * 600),
+                    end.destination(225.0, 35.0 * n as f64 / 6.0),
+                )
+            })
+            .collect();
+        let mut cell = StormCell {
+            id: 3,
+            centroid: end,
+            strikes: 100,
+            rate_per_min: 5.0,
+            radius_km: 10.0,
+            first_seen: at(2400),
+            last_strike: at(3600),
+            track,
+        };
+        assert_eq!(
+            motion_summary(&cell, Some(home), at(3600)).as_deref(),
+            Some("Cell #3 moving NE at 35 km/h, ETA 60 min")
+        );
+        assert_eq!(
+            motion_summary(&cell, None, at(3600)).as_deref(),
+            Some("Cell #3 moving NE at 35 km/h")
+        );
+
+        cell.track.truncate(1);
+        assert_eq!(motion_summary(&cell, Some(home), at(3600)), None);
+    }
+
+    #[test]
+    fn strikes_near_home_are_counted_again_when_home_moves() {
+        let mut app = TrrpyApp::default();
+        // 11 and 111 km north of the equator, in the same minute
+        for lat in [0.1, 1.0] {
+            app.handle_lightning_message(strike_at(1_000, lat, 0.0));
+        }
+        let near = |app: &TrrpyApp| app.stats.home.counts(16).iter().sum::<u32>();
+        assert_eq!(app.stats.global.counts(16)[MINUTES - 1], 2);
+        assert_eq!(near(&app), 0);
+
+        app.set_home("0,0".to_string());
+        assert_eq!(near(&app), 1);
+        app.rate_radius_km = 200.0;
+        app.recount_home_rate();
+        assert_eq!(near(&app), 2);
+        app.handle_lightning_message(strike_at(1_001, -1.0, 0.0));
+        assert_eq!(near(&app), 3);
+    }
+
+    #[test]
+    fn strikes_outside_the_areas_are_kept_out() {
+        let mut app = TrrpyApp {
+            area_input: "circle 0,0".to_string(),
+            ..Default::default()
+        };
+        app.add_area();
+        assert!(app.area_error.is_some() && app.areas.areas.is_empty());
+        app.area_input = "circle 0,0 100".to_string();
+        app.add_area();
+        assert!(app.areas.enabled && app.area_error.is_none());
+
+        app.handle_lightning_message(strike_at(0, 0.5, 0.5));
+        app.handle_lightning_message(strike_at(1, 5.0, 5.0));
+        assert_eq!((app.strikes.len(), app.filtered_out), (1, 1));
+        assert_eq!(app.stats.global.counts(0)[MINUTES - 1], 1);
+    }
+
+    #[test]
+    fn history_can_be_exported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = TrrpyApp {
+            area_input: "circle 0,0 100".to_string(),
+            ..Default::default()
+        };
+        app.add_area();
+        app.areas.enabled = false;
+        for (secs, lat) in [(0, 0.5), (60, 5.0), (600, 0.2)] {
+            app.handle_lightning_message(strike_at(secs, lat, 0.0));
+        }
+
+        app.export = ExportPanel {
+            format: ExportFormat::Csv,
+            path: dir.path().join("strikes.csv").display().to_string(),
+            in_areas: true,
+            ..Default::default()
+        };
+        app.export_history();
+        assert!(matches!(&app.export.result, Some(Ok(message)) if message.starts_with("Wrote 2 ")));
+        let csv = std::fs::read_to_string(dir.path().join("strikes.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+
+        // The last half hour, by the wall clock, is long after these strikes
+        app.export.minutes = 30;
+        app.export.in_areas = false;
+        app.export_history();
+        assert!(matches!(&app.export.result, Some(Ok(message)) if message.starts_with("Wrote 0 ")));
+
+        app.export.path = dir.path().join("missing/strikes.csv").display().to_string();
+        app.export_history();
+        assert!(matches!(app.export.result, Some(Err(_))));
+    }
+
+    #[test]
+    fn strikes_are_saved_and_searched() {
+        let fixtures = strike_fixture_records();
+        let server = FakeServer::start(FakeServerConfig::default());
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut app = TrrpyApp::default();
+        // A database that cannot be opened leaves the panel closed
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(app.open_history(&file.join("strikes.sqlite")).is_err());
+        assert!(app.history.path.is_none() && app.history.db.is_none());
+        assert!(app.history.error.is_some());
 
-            // Keep only the most recent strikes
-            if self.lightning_strikes.len() > self.max_strikes {
-                self.lightning_strikes.remove(0);
+        // The fixtures are older than any sensible retention
+        app.history.retention.max_age = None;
+        app.open_history(&dir.path().join("strikes.sqlite"))
+            .unwrap();
+        assert_eq!(app.history.error, None);
+        app.client()
+            .connect_to(&[server.url.as_str()], Backoff::default());
+        pump_until(&mut app, |app| app.strikes.len() == fixtures.len());
+        app.disconnect_blitzortung();
+
+        app.history.hours = 0;
+        app.search_history();
+        assert_eq!(app.history.stored, fixtures.len());
+        assert_eq!(app.history.results.len(), fixtures.len());
+
+        app.history.bbox_input = "-10,35,30,60".to_string();
+        app.search_history();
+        let europe = StrikeFilter {
+            bbox: Some("-10,35,30,60".parse().unwrap()),
+            ..Default::default()
+        };
+        let in_europe = fixtures.iter().filter(|s| europe.matches(s)).count();
+        assert_eq!(app.history.results.len(), in_europe);
+        app.history.bbox_input = "Europe".to_string();
+        app.search_history();
+        assert!(app.history.error.is_some());
+
+        // Exports can go beyond what is still in memory
+        app.strikes.clear();
+        app.export = ExportPanel {
+            format: ExportFormat::Csv,
+            path: dir.path().join("saved.csv").display().to_string(),
+            from_history: true,
+            ..Default::default()
+        };
+        app.export_history();
+        let csv = std::fs::read_to_string(dir.path().join("saved.csv")).unwrap();
+        assert_eq!(csv.lines().count(), fixtures.len() + 1);
+    }
+
+    #[test]
+    fn strikes_keep_coming_while_hidden() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig::default());
+
+        // Hidden from the start, as after the hotkey closed the popup
+        let mut app = TrrpyApp {
+            auto_connect: false,
+            background: true,
+            ..Default::default()
+        };
+        app.client()
+            .connect_to(&[server.url.as_str()], Backoff::default());
+        pump_until(&mut app, |app| app.strikes.len() == fixtures.len());
+        assert_eq!(app.missed, fixtures.len());
+
+        // Showing keeps the count for the badge, hiding again starts over
+        // without dropping the feed
+        app.set_popup_visible(true);
+        assert_eq!(app.missed, fixtures.len());
+        app.set_popup_visible(false);
+        assert_eq!(app.missed, 0);
+        assert!(app.feed_open());
+
+        app.disconnect_blitzortung();
+
+        // Without opting in, hiding disconnects as it always has
+        let mut app = TrrpyApp::default();
+        assert!(!app.background);
+        app.set_popup_visible(true);
+        app.client()
+            .connect_to(&[server.url.as_str()], Backoff::default());
+        pump_until(&mut app, |app| {
+            matches!(app.connection_status, ConnectionStatus::Connected)
+        });
+        app.set_popup_visible(false);
+        pump_until(&mut app, |app| {
+            matches!(app.connection_status, ConnectionStatus::Disconnected)
+        });
+    }
+
+    #[test]
+    fn settings_are_saved_and_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        let mut app = TrrpyApp::default();
+        app.open_settings(&path).unwrap();
+        assert_eq!(app.settings(), Settings::default());
+
+        app.set_home("45.46,9.19".to_string());
+        app.area_input = "circle 45,9 50".to_string();
+        app.add_area();
+        app.auto_connect = false;
+        app.server = Some(BLITZSERVERS[1].to_string());
+        app.order = StrikeOrder::Nearest;
+        app.cells.set_settings(CellSettings {
+            min_strikes: 20,
+            ..Default::default()
+        });
+        app.set_window_frame(WindowFrame {
+            x: 10.0,
+            y: 20.0,
+            width: 600.0,
+            height: 400.0,
+        });
+        // Still settling
+        app.save_settings(false);
+        assert!(!path.exists());
+        app.set_popup_visible(true);
+        app.set_popup_visible(false);
+        assert!(path.exists());
+
+        let mut restored = TrrpyApp::default();
+        restored.open_settings(&path).unwrap();
+        assert_eq!(restored.settings(), app.settings());
+        assert_eq!(restored.home, app.home);
+        assert_eq!(restored.home_input, "45.4600,9.1900");
+        assert_eq!(restored.areas, app.areas);
+        assert_eq!(restored.cells.settings().min_strikes, 20);
+
+        // A broken file is reported and left for the user to look at
+        std::fs::write(&path, "not json").unwrap();
+        let mut broken = TrrpyApp::default();
+        assert!(broken.open_settings(&path).is_err());
+        broken.auto_connect = false;
+        broken.save_settings(true);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+    }
+
+    #[test]
+    fn the_config_sets_servers_and_limits() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig::default());
+        let mut app = TrrpyApp {
+            server: Some(BLITZSERVERS[0].to_string()),
+            ..Default::default()
+        };
+
+        let mut config = Config::default();
+        config.feed.servers = vec![server.url.clone()];
+        config.feed.max_strikes = 2;
+        config.hotkey = "ctrl+alt+space".parse().unwrap();
+        app.set_config_error(Some("bad config".to_string()));
+        app.apply_config(&config);
+        // A server the config no longer lists falls back to its servers
+        assert_eq!(app.server, None);
+        assert_eq!(app.strikes.retention().max_count, 2);
+        assert_eq!(app.hotkey, "Ctrl+Alt+Space");
+        assert_eq!(app.config_error, None);
+
+        app.connect_blitzortung();
+        // Hidden, so every strike counts as missed
+        pump_until(&mut app, |app| app.missed == fixtures.len());
+        assert_eq!(app.strikes.len(), 2);
+
+        // Saved settings cannot go past it either
+        app.apply_settings(Settings::default());
+        assert_eq!(app.strikes.retention().max_count, 2);
+    }
+
+    #[test]
+    fn the_config_cap_leaves_the_saved_retention_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.json");
+        Settings {
+            retention: Retention {
+                max_count: 500_000,
+                max_age: None,
             }
-        } else {
-            // Log raw message for debugging
-            ll(&format!("⚡ Raw message: {}", decoded_message));
+            .into(),
+            ..Default::default()
         }
+        .save(&path)
+        .unwrap();
+
+        // Settings load before the config does, under the built-in cap
+        let mut app = TrrpyApp::default();
+        app.open_settings(&path).unwrap();
+        assert_eq!(app.strikes.retention().max_count, 100_000);
+        assert_eq!(app.settings().retention.max_count, 500_000);
+
+        let mut config = Config::default();
+        config.feed.max_strikes = 1_000_000;
+        app.apply_config(&config);
+        assert_eq!(app.strikes.retention().max_count, 500_000);
+
+        // Lowering the cap and raising it again gets the same back
+        config.feed.max_strikes = 10;
+        app.apply_config(&config);
+        assert_eq!(app.strikes.retention().max_count, 10);
+        app.save_settings(true);
+        config.feed.max_strikes = 1_000_000;
+        app.apply_config(&config);
+        assert_eq!(app.strikes.retention().max_count, 500_000);
+        assert_eq!(Settings::load(&path).unwrap().retention.max_count, 500_000);
+    }
+
+    #[test]
+    fn replayed_strikes_are_drawn_fresh() {
+        let mut app = TrrpyApp::default();
+        // The fixtures are older than the default retention
+        app.set_retention(Retention {
+            max_age: None,
+            ..Default::default()
+        });
+        for strike in strike_fixture_records() {
+            app.strikes.insert(strike);
+        }
+        let latest = app.strikes.latest().unwrap().clone();
+        // The strike dots the map draws at full alpha
+        let fresh_dots = |app: &mut TrrpyApp| {
+            let ctx = egui::Context::default();
+            let input = egui::RawInput {
+                screen_rect: Some(egui::Rect::from_min_size(
+                    egui::Pos2::ZERO,
+                    egui::vec2(800.0, 4000.0),
+                )),
+                ..Default::default()
+            };
+            let output = ctx.run(input, |ctx| app.update(ctx));
+            output
+                .shapes
+                .iter()
+                .filter(|clipped| {
+                    matches!(&clipped.shape, egui::Shape::Circle(circle)
+                        if circle.radius == 3.5
+                            && circle.fill == crate::map::polarity_color(latest.pol))
+                })
+                .count()
+        };
+
+        // Live, the fixtures are long faded
+        assert_eq!(fresh_dots(&mut app), 0);
+        app.connection_status = ConnectionStatus::Replaying(PathBuf::from("storm.blzrec"));
+        assert!(fresh_dots(&mut app) >= 1);
+    }
+
+    #[test]
+    fn recorded_sessions_can_be_replayed() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig::default());
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut app = TrrpyApp {
+            recording: true,
+            recording_path: dir.path().join("app.blzrec").display().to_string(),
+            ..Default::default()
+        };
+        app.update_recording();
+        app.client
+            .as_ref()
+            .unwrap()
+            .connect_to(&[server.url.as_str()], Backoff::default());
+        pump_until(&mut app, |app| app.strikes.len() == fixtures.len());
+        let live: Vec<_> = app.strikes.iter().cloned().collect();
+
+        app.strikes.clear();
+        app.replay_path = app.recording_path.clone();
+        app.replay_speed = ReplaySpeed::Max;
+        app.replay_blitzortung();
+        // Held off for the replay, without touching the saved setting
+        assert!(app.replaying);
+        assert!(app.settings().auto_connect);
+        pump_until(&mut app, |app| {
+            app.strikes.len() == live.len()
+                && matches!(app.connection_status, ConnectionStatus::Disconnected)
+        });
+        assert!(app.strikes.iter().eq(&live));
+        assert!(!app.replaying);
+
+        app.replay_path = dir.path().join("missing.blzrec").display().to_string();
+        app.replay_blitzortung();
+        pump_until(&mut app, |app| {
+            matches!(&app.connection_status, ConnectionStatus::Error(error)
+                if error.starts_with("Cannot replay"))
+        });
+        assert!(!app.replaying);
+    }
+
+    #[test]
+    fn dropped_connections_are_retried() {
+        let server = FakeServer::start(FakeServerConfig {
+            close_after: Some(1),
+            ..Default::default()
+        });
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            ..Default::default()
+        };
+
+        let mut app = TrrpyApp::default();
+        app.client
+            .get_or_insert_with(BlitzortungClient::new)
+            .connect_to(&[server.url.as_str()], backoff);
+        pump_until(&mut app, |app| {
+            matches!(app.connection_status, ConnectionStatus::Reconnecting { .. })
+        });
+        pump_until(&mut app, |app| app.strikes.len() >= 2);
+        assert!(server.handshakes() >= 2);
     }
 }
@@ -1,5 +1,3 @@
-use crate::trrpy::TrrpyApp;
-use crate::utils::ll;
 use egui::ViewportInfo;
 use egui::{self, Context, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Vec2};
 use egui_wgpu::Renderer;
@@ -15,12 +13,15 @@ use objc2::runtime::{AnyClass, AnyObject};
 use objc2::{DefinedClass, MainThreadMarker, MainThreadOnly, define_class, msg_send};
 use objc2_app_kit::NSView;
 use objc2_foundation::{NSPoint, NSRect};
+use popuppp::accelerator;
+use popuppp::trrpy::TrrpyApp;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::CStr;
 use std::fmt::Debug;
 use std::sync::OnceLock;
 use std::time::{Instant, SystemTime};
+use tracing::{debug, error, info, warn};
 
 /// This struct will hold the state for our custom egui view.
 /// It's stored in an Ivar in the `EguiView` Objective-C object.
@@ -69,59 +70,7 @@ impl EguiViewState {
 
     /// Convert NSEvent keycode to egui Key
     fn ns_keycode_to_egui_key(&self, keycode: u16) -> Option<Key> {
-        match keycode {
-            // Letters
-            0 => Some(Key::A),
-            1 => Some(Key::S),
-            2 => Some(Key::D),
-            3 => Some(Key::F),
-            4 => Some(Key::H),
-            5 => Some(Key::G),
-            6 => Some(Key::Z),
-            7 => Some(Key::X),
-            8 => Some(Key::C),
-            9 => Some(Key::V),
-            11 => Some(Key::B),
-            12 => Some(Key::Q),
-            13 => Some(Key::W),
-            14 => Some(Key::E),
-            15 => Some(Key::R),
-            17 => Some(Key::T),
-            16 => Some(Key::Y),
-            32 => Some(Key::U),
-            34 => Some(Key::I),
-            31 => Some(Key::O),
-            35 => Some(Key::P),
-            45 => Some(Key::N),
-            46 => Some(Key::M),
-
-            // Numbers
-            18 => Some(Key::Num1),
-            19 => Some(Key::Num2),
-            20 => Some(Key::Num3),
-            21 => Some(Key::Num4),
-            23 => Some(Key::Num5),
-            22 => Some(Key::Num6),
-            26 => Some(Key::Num7),
-            28 => Some(Key::Num8),
-            25 => Some(Key::Num9),
-            29 => Some(Key::Num0),
-
-            // Special keys
-            36 => Some(Key::Enter),
-            48 => Some(Key::Tab),
-            49 => Some(Key::Space),
-            51 => Some(Key::Backspace),
-            53 => Some(Key::Escape),
-
-            // Arrow keys
-            123 => Some(Key::ArrowLeft),
-            124 => Some(Key::ArrowRight),
-            125 => Some(Key::ArrowDown),
-            126 => Some(Key::ArrowUp),
-
-            _ => None,
-        }
+        accelerator::key_for_keycode(keycode)
     }
 
     /// Convert NSEvent modifier flags to egui Modifiers
@@ -160,7 +109,7 @@ define_class!(
         #[unsafe(method(drawRect:))]
         fn draw_rect(&self, _dirty_rect: NSRect) {
             let Some(state) = self.ivars().state.get() else {
-                ll("❌ EguiView state not initialized, skipping draw.");
+                error!("EguiView state not initialized, skipping draw.");
                 return;
             };
 
@@ -423,15 +372,15 @@ impl EguiView {
     /// view has been added to a window, because we need a window handle to create the
     /// `wgpu` surface.
     pub(crate) fn init_state(&self) {
-        ll("🚀 Initializing EguiView state...");
+        info!("Initializing EguiView state...");
 
         let Some(_window) = self.window() else {
-            ll("❌ EguiView must be in a window to initialize state");
+            error!("EguiView must be in a window to initialize state");
             return;
         };
 
         if self.ivars().state.get().is_some() {
-            ll("⚠️ EguiView state is already initialized.");
+            warn!("EguiView state is already initialized.");
             return;
         }
 
@@ -486,7 +435,18 @@ impl EguiView {
         let renderer = Renderer::new(&device, surface_format, None, 1, false);
 
         // 5. Create the user app state and wrap fields in RefCell
-        let app = RefCell::new(TrrpyApp::default());
+        let mut app = TrrpyApp::default();
+        if let Some(path) = popuppp::settings::default_path()
+            && let Err(error) = app.open_settings(&path)
+        {
+            tracing::warn!(%error, "cannot load settings, using the defaults");
+        }
+        if let Some(path) = popuppp::database::default_path()
+            && let Err(error) = app.open_history(&path)
+        {
+            tracing::warn!(%error, path = %path.display(), "cannot open strike history");
+        }
+        let app = RefCell::new(app);
         let renderer = RefCell::new(renderer);
 
         // 6. Store the state
@@ -505,9 +465,9 @@ impl EguiView {
         };
 
         if self.ivars().state.set(state).is_err() {
-            ll("❌ Failed to set EguiView state because it was already set.");
+            error!("Failed to set EguiView state because it was already set.");
         } else {
-            ll("✅ EguiView state initialized and set successfully.");
+            info!("EguiView state initialized and set successfully.");
             // Request a redraw now that we are initialized.
             unsafe { self.setNeedsDisplay(true) };
         }
@@ -518,17 +478,14 @@ impl EguiView {
         match state.surface.get_current_texture() {
             Ok(frame) => Some(frame),
             Err(wgpu::SurfaceError::Lost) => {
-                ll("⚠️ wgpu surface lost, reconfiguring...");
+                warn!("wgpu surface lost, reconfiguring...");
                 state
                     .surface
                     .configure(&state.device, &state.surface_config);
                 None
             }
             Err(e) => {
-                ll(&format!(
-                    "❌ Failed to acquire next swap chain texture: {}",
-                    e
-                ));
+                error!("Failed to acquire next swap chain texture: {}", e);
                 None
             }
         }
@@ -596,7 +553,7 @@ impl EguiView {
                 for command in &viewport_output.commands {
                     match command {
                         egui::ViewportCommand::Close => {
-                            ll("🚪 Egui requested window close - hiding window...");
+                            debug!("Egui requested window close - hiding window...");
                             self.hide_window_and_restore_focus(state);
                             return true;
                         }
@@ -614,7 +571,9 @@ impl EguiView {
             window.orderOut(None);
             crate::hotkey::restore_focus(&*window);
             // Notify app that popup is now hidden
-            state.app.borrow_mut().set_popup_visible(false);
+            let mut app = state.app.borrow_mut();
+            crate::hotkey::remember_frame(&window, &mut app);
+            app.set_popup_visible(false);
         }
 
         // Restore focus to previous app if PID is available (from TrrpyApp)
@@ -622,7 +581,7 @@ impl EguiView {
         if let Some(pid) = prev_app_pid {
             self.restore_previous_app_by_pid(pid);
         } else {
-            ll(&format!("No previous app PID: {:?}", prev_app_pid))
+            debug!("No previous app PID: {:?}", prev_app_pid)
         }
         state.app.borrow_mut().esc_pressed = false; // Reset flag
     }
@@ -636,7 +595,7 @@ impl EguiView {
         if !prev_app.is_null() {
             let _: bool = unsafe { objc2::msg_send![prev_app, activateWithOptions: 1u64 << 1] }; // NSApplicationActivateIgnoringOtherApps
         } else {
-            ll(&format!("prev_app is null"))
+            debug!("prev_app is null")
         }
     }
 
@@ -1,48 +1,134 @@
-use objc2::{msg_send, sel};
-use objc2::{rc::Retained, runtime::AnyObject};
-use objc2_foundation::{NSObject, NSString};
-use std::ffi::c_int;
-use std::sync::atomic::Ordering;
-
-#[link(name = "Foundation", kind = "framework")]
-unsafe extern "C" {
-    unsafe fn NSLog(format: *const objc2::runtime::AnyObject, ...);
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Format `time` as an RFC 3339 UTC timestamp with milliseconds, e.g.
+/// `2025-08-11T11:20:00.381Z`.
+pub fn format_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
 }
 
-pub(crate) fn ll(msg: &str) {
-    let ns_msg: Retained<AnyObject> = NSString::from_str(msg).into();
-    unsafe {
-        NSLog(Retained::as_ptr(&ns_msg));
+/// Parse an RFC 3339 UTC timestamp like `2025-08-11T11:20:00Z`, with
+/// optional fractional seconds. Offsets other than `Z` are not supported.
+pub fn parse_utc(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix(['Z', 'z'])?;
+    let (date, time) = s.split_once(['T', 't', ' '])?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
     }
-}
 
-// Signal handler for SIGINT (Ctrl+C)
-pub(crate) extern "C" fn sigint_handler(_signal: c_int) {
-    ll("🪧 Received SIGINT (Ctrl+C) - initiating proper app termination...");
-
-    let app_ptr = super::APP_INSTANCE.load(Ordering::SeqCst);
-    if !app_ptr.is_null() {
-        unsafe {
-            let app = &*app_ptr;
-            // Use performSelectorOnMainThread to ensure thread safety
-            let selector = sel!(terminate:);
-            let _: () = msg_send![app, performSelectorOnMainThread: selector, withObject:std::ptr::null::<NSObject>(), waitUntilDone:false];
-            ll("✅ Cocoa exit.");
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, Some(fraction)),
+        None => (time, None),
+    };
+    let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+    let (hours, minutes, seconds) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let nanos = match fraction {
+        Some(f) if !f.is_empty() && f.len() <= 9 && f.bytes().all(|b| b.is_ascii_digit()) => {
+            f.parse::<u32>().ok()? * 10u32.pow(9 - f.len() as u32)
         }
-    } else {
-        ll("☑ No app instance available, exiting directly");
-        std::process::exit(0);
+        Some(_) => return None,
+        None => 0,
+    };
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
     }
 }
 
-pub(crate) unsafe fn setup_signal_handler() {
-    unsafe extern "C" {
-        fn signal(sig: c_int, handler: extern "C" fn(c_int)) -> extern "C" fn(c_int);
+// Conversions between days since the Unix epoch and proleptic Gregorian
+// dates, after Howard Hinnant's `chrono`-compatible date algorithms.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_timestamps_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_754_911_200_381);
+        assert_eq!(format_utc(time), "2025-08-11T11:20:00.381Z");
+        assert_eq!(parse_utc("2025-08-11T11:20:00.381Z"), Some(time));
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            parse_utc("2024-02-29T23:59:59Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_709_251_199))
+        );
+        // The last day of the month, leap years included
+        assert!(parse_utc("2000-02-29T00:00:00Z").is_some());
+        assert!(parse_utc("2023-04-30T00:00:00Z").is_some());
+        for days in [0, 59, 60, 365, 10_956, 20_000, 50_000] {
+            let time = UNIX_EPOCH + Duration::from_secs(days * 86_400 + 3723);
+            assert_eq!(parse_utc(&format_utc(time)), Some(time));
+        }
     }
 
-    const SIGINT: c_int = 2;
-    unsafe {
-        signal(SIGINT, sigint_handler);
+    #[test]
+    fn malformed_timestamps_are_rejected() {
+        for s in [
+            "",
+            "2025-08-11",
+            "2025-08-11T11:20:00",
+            "2025-13-11T11:20:00Z",
+            // Days the month does not have
+            "2024-02-30T00:00:00Z",
+            "2024-02-31T00:00:00Z",
+            "2023-02-29T00:00:00Z",
+            "2100-02-29T00:00:00Z",
+            "2023-04-31T00:00:00Z",
+            "2025-08-32T00:00:00Z",
+            "2025-08-11T24:00:00Z",
+            "2025-08-11T11:20:00.Z",
+            "2025-08-11T11:20:00+02:00",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(parse_utc(s), None, "{s}");
+        }
     }
-    ll("✅ Set up SIGINT handler for graceful Ctrl+C handling");
 }
@@ -1,344 +1,710 @@
-use base64::Engine;
 use futures_util::{SinkExt, StreamExt};
-use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
 
-use crate::utils::ll;
+use crate::recording::{FramePayload, RecordedFrame, Recorder, RecordingReader, ReplaySpeed};
+use tracing::{Instrument, Span, debug, error, info, info_span, trace, warn};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WebSocketMessage {
-    pub mtype: String,
-    pub payload: Vec<u8>,
-    pub ts: u64,
+/// Events produced by the WebSocket worker thread.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebSocketEvent {
+    /// The connection to `url` was established.
+    Connected { url: String },
+    /// The recording at `path` started playing in place of a connection.
+    ReplayStarted { path: PathBuf },
+    /// The recording at `path` could not be played.
+    ReplayFailed { path: PathBuf, error: String },
+    /// Connecting to `url` failed.
+    ConnectFailed { url: String, error: String },
+    /// The connection was closed, either by us or by the server.
+    Disconnected,
+    /// A supervised connection will retry `url` at `retry_at`. `attempt`
+    /// counts the retries since the last healthy connection.
+    Reconnecting {
+        attempt: u32,
+        url: String,
+        retry_at: Instant,
+    },
+    /// A text frame was received.
+    Text(String),
+    /// A binary frame was received.
+    Binary(Vec<u8>),
+    /// The connection failed after it was established.
+    Error(String),
 }
 
-#[derive(Debug)]
-pub struct WebSocketManager {
-    tx: mpsc::Sender<WebSocketCommand>,
-    rx: mpsc::Receiver<WebSocketMessage>,
+/// Jittered exponential backoff used by supervised connections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backoff {
+    /// Delay before the first retry.
+    pub initial: Duration,
+    /// Upper bound for the delay, jitter included.
+    pub max: Duration,
+    /// Growth factor between consecutive retries.
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomised, in `0.0..=1.0`.
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.25,
+        }
+    }
+}
+
+impl Backoff {
+    /// Un-jittered delay before retry number `attempt` (starting at 1).
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(32) as i32;
+        let secs = self.initial.as_secs_f64() * self.multiplier.powi(exp);
+        Duration::from_secs_f64(secs.min(self.max.as_secs_f64()))
+    }
+
+    /// Delay before retry number `attempt`, spread by ±`jitter` so clients
+    /// that dropped together do not all come back at the same instant.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (2.0 * fastrand::f64() - 1.0);
+        let secs = self.base_delay(attempt).as_secs_f64() * factor;
+        Duration::from_secs_f64(secs.min(self.max.as_secs_f64()))
+    }
 }
 
 #[derive(Debug)]
 pub enum WebSocketCommand {
-    Connect(String),
+    /// Connect to the first of `urls`. With a `reconnect` policy the
+    /// connection is supervised: on failure the worker rotates through
+    /// `urls` and retries until told to disconnect.
+    Connect {
+        urls: Vec<String>,
+        handshake: Option<Vec<u8>>,
+        reconnect: Option<Backoff>,
+    },
     Disconnect,
-    Send(WebSocketMessage),
     SendRaw(Vec<u8>),
+    /// Append every frame received from now on to the recording at `path`.
+    StartRecording(PathBuf),
+    StopRecording,
+    /// Drop any connection and play back the recording at `path` instead,
+    /// as if its frames were arriving from a server.
+    Replay {
+        path: PathBuf,
+        speed: ReplaySpeed,
+    },
+}
+
+/// Cloneable command side of a [`WebSocketManager`].
+#[derive(Debug, Clone)]
+pub struct WebSocketHandle {
+    tx: mpsc::Sender<WebSocketCommand>,
+}
+
+impl WebSocketHandle {
+    pub fn connect(&self, url: String) {
+        self.send(WebSocketCommand::Connect {
+            urls: vec![url],
+            handshake: None,
+            reconnect: None,
+        });
+    }
+
+    /// Connect to `url` and send `handshake` as a binary frame as soon as the
+    /// connection is up.
+    pub fn connect_with_handshake(&self, url: String, handshake: Vec<u8>) {
+        self.send(WebSocketCommand::Connect {
+            urls: vec![url],
+            handshake: Some(handshake),
+            reconnect: None,
+        });
+    }
+
+    /// Keep a connection to one of `urls` up until [`disconnect`] is called,
+    /// re-sending `handshake` after every reconnect.
+    ///
+    /// [`disconnect`]: WebSocketHandle::disconnect
+    pub fn connect_supervised(
+        &self,
+        urls: Vec<String>,
+        handshake: Option<Vec<u8>>,
+        backoff: Backoff,
+    ) {
+        self.send(WebSocketCommand::Connect {
+            urls,
+            handshake,
+            reconnect: Some(backoff),
+        });
+    }
+
+    pub fn disconnect(&self) {
+        self.send(WebSocketCommand::Disconnect);
+    }
+
+    pub fn send_raw(&self, payload: Vec<u8>) {
+        self.send(WebSocketCommand::SendRaw(payload));
+    }
+
+    /// Record received frames to `path` until [`stop_recording`] is called.
+    /// Recording carries on across reconnects.
+    ///
+    /// [`stop_recording`]: WebSocketHandle::stop_recording
+    pub fn start_recording(&self, path: PathBuf) {
+        self.send(WebSocketCommand::StartRecording(path));
+    }
+
+    pub fn stop_recording(&self) {
+        self.send(WebSocketCommand::StopRecording);
+    }
+
+    /// Play back a recording made with [`start_recording`]. Consumers see
+    /// `Connected`, the recorded frames and finally `Disconnected`.
+    ///
+    /// [`start_recording`]: WebSocketHandle::start_recording
+    pub fn replay(&self, path: PathBuf, speed: ReplaySpeed) {
+        self.send(WebSocketCommand::Replay { path, speed });
+    }
+
+    fn send(&self, cmd: WebSocketCommand) {
+        if let Err(e) = self.tx.send(cmd) {
+            error!(error = %e, "failed to send websocket command");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebSocketManager {
+    handle: WebSocketHandle,
+    rx: mpsc::Receiver<WebSocketEvent>,
 }
 
 impl WebSocketManager {
     pub fn new() -> Self {
         let (cmd_tx, cmd_rx) = mpsc::channel::<WebSocketCommand>();
-        let (msg_tx, msg_rx) = mpsc::channel::<WebSocketMessage>();
+        let (event_tx, event_rx) = mpsc::channel::<WebSocketEvent>();
+
+        // The worker logs wherever the thread creating it does
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
 
         // Spawn the async worker thread
         thread::spawn(move || {
+            let _dispatch = tracing::dispatcher::set_default(&dispatch);
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .expect("Failed to create Tokio runtime");
 
             rt.block_on(async {
-                WebSocketWorker::new(cmd_rx, msg_tx).run().await;
+                WebSocketWorker::new(cmd_rx, event_tx).run().await;
             });
         });
 
         Self {
-            tx: cmd_tx,
-            rx: msg_rx,
+            handle: WebSocketHandle { tx: cmd_tx },
+            rx: event_rx,
         }
     }
 
+    /// Split the manager so commands and events can be handled on different
+    /// threads. The worker shuts down once every handle has been dropped.
+    pub fn into_split(self) -> (WebSocketHandle, mpsc::Receiver<WebSocketEvent>) {
+        (self.handle, self.rx)
+    }
+
     pub fn connect(&self, url: String) {
-        if let Err(e) = self.tx.send(WebSocketCommand::Connect(url)) {
-            ll(&format!("❌ Failed to send connect command: {}", e));
-        }
+        self.handle.connect(url);
     }
 
-    pub fn disconnect(&self) {
-        if let Err(e) = self.tx.send(WebSocketCommand::Disconnect) {
-            ll(&format!("❌ Failed to send disconnect command: {}", e));
-        }
+    pub fn connect_with_handshake(&self, url: String, handshake: Vec<u8>) {
+        self.handle.connect_with_handshake(url, handshake);
     }
 
-    pub fn send_message(&self, message: WebSocketMessage) {
-        if let Err(e) = self.tx.send(WebSocketCommand::Send(message)) {
-            ll(&format!("❌ Failed to send message command: {}", e));
-        }
+    pub fn connect_supervised(
+        &self,
+        urls: Vec<String>,
+        handshake: Option<Vec<u8>>,
+        backoff: Backoff,
+    ) {
+        self.handle.connect_supervised(urls, handshake, backoff);
+    }
+
+    pub fn disconnect(&self) {
+        self.handle.disconnect();
     }
 
     pub fn send_raw(&self, payload: Vec<u8>) {
-        if let Err(e) = self.tx.send(WebSocketCommand::SendRaw(payload)) {
-            ll(&format!("❌ Failed to send raw message command: {}", e));
-        }
+        self.handle.send_raw(payload);
+    }
+
+    pub fn start_recording(&self, path: PathBuf) {
+        self.handle.start_recording(path);
+    }
+
+    pub fn stop_recording(&self) {
+        self.handle.stop_recording();
+    }
+
+    pub fn replay(&self, path: PathBuf, speed: ReplaySpeed) {
+        self.handle.replay(path, speed);
     }
 
-    pub fn try_recv_message(&self) -> Option<WebSocketMessage> {
+    pub fn try_recv_event(&self) -> Option<WebSocketEvent> {
         self.rx.try_recv().ok()
     }
 
-    pub fn recv_message_blocking(&self) -> Result<WebSocketMessage, mpsc::RecvError> {
+    pub fn recv_event_blocking(&self) -> Result<WebSocketEvent, mpsc::RecvError> {
         self.rx.recv()
     }
+
+    pub fn recv_event_timeout(&self, timeout: Duration) -> Option<WebSocketEvent> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+}
+
+impl Default for WebSocketManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the worker should be connected to.
+struct Target {
+    urls: Vec<String>,
+    handshake: Option<Vec<u8>>,
+    reconnect: Option<Backoff>,
+    /// Index into `urls` of the server to dial next.
+    server: usize,
+    /// Retries since the last healthy connection.
+    attempt: u32,
+}
+
+/// A recording being played back in place of a connection.
+struct Replay {
+    reader: RecordingReader,
+    speed: ReplaySpeed,
+    /// When the first frame was played and its recorded offset.
+    first: Option<(tokio::time::Instant, Duration)>,
+    /// A frame read ahead of its time.
+    pending: Option<RecordedFrame>,
+}
+
+impl Replay {
+    /// When `frame` is due, relative to the first frame played.
+    fn due(&mut self, frame: &RecordedFrame) -> Option<tokio::time::Instant> {
+        let (played_at, offset) = *self
+            .first
+            .get_or_insert((tokio::time::Instant::now(), frame.offset));
+        let delay = self.speed.delay(frame.offset.saturating_sub(offset))?;
+        Some(played_at + delay)
+    }
 }
 
 struct WebSocketWorker {
     cmd_rx: mpsc::Receiver<WebSocketCommand>,
-    msg_tx: mpsc::Sender<WebSocketMessage>,
+    event_tx: mpsc::Sender<WebSocketEvent>,
     connection: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    target: Option<Target>,
+    retry_at: Option<tokio::time::Instant>,
+    recorder: Option<Recorder>,
+    replay: Option<Replay>,
+    /// Span of the current connection or replay.
+    span: Span,
 }
 
 impl WebSocketWorker {
     fn new(
         cmd_rx: mpsc::Receiver<WebSocketCommand>,
-        msg_tx: mpsc::Sender<WebSocketMessage>,
+        event_tx: mpsc::Sender<WebSocketEvent>,
     ) -> Self {
         Self {
             cmd_rx,
-            msg_tx,
+            event_tx,
             connection: None,
+            target: None,
+            retry_at: None,
+            recorder: None,
+            replay: None,
+            span: Span::none(),
         }
     }
 
     async fn run(&mut self) {
-        ll("🌐 WebSocket worker thread started");
+        debug!("websocket worker started");
 
         loop {
-            // Handle commands from the main thread
-            while let Ok(cmd) = self.cmd_rx.try_recv() {
-                match cmd {
-                    WebSocketCommand::Connect(url) => {
-                        self.connect(&url).await;
-                    }
-                    WebSocketCommand::Disconnect => {
-                        self.disconnect().await;
-                    }
-                    WebSocketCommand::Send(message) => {
-                        self.send_message(message).await;
+            // Everything that happens on behalf of a connection is logged in its span
+            let span = self.span.clone();
+            if !self.step().instrument(span).await {
+                return;
+            }
+        }
+    }
+
+    /// Handle pending commands, then wait up to 100ms for a frame or a retry.
+    /// Returns false once every handle has been dropped.
+    async fn step(&mut self) -> bool {
+        // Handle commands from the main thread
+        loop {
+            match self.cmd_rx.try_recv() {
+                Ok(WebSocketCommand::Connect {
+                    urls,
+                    handshake,
+                    reconnect,
+                }) => {
+                    self.disconnect().await;
+                    if urls.is_empty() {
+                        warn!("connect requested without any url");
+                        continue;
                     }
-                    WebSocketCommand::SendRaw(payload) => {
-                        self.send_raw(payload).await;
+                    self.target = Some(Target {
+                        urls,
+                        handshake,
+                        reconnect,
+                        server: 0,
+                        attempt: 0,
+                    });
+                    self.connect().await;
+                }
+                Ok(WebSocketCommand::Disconnect) => {
+                    self.disconnect().await;
+                }
+                Ok(WebSocketCommand::SendRaw(payload)) => {
+                    self.send_raw(payload).await;
+                }
+                Ok(WebSocketCommand::StartRecording(path)) => {
+                    self.start_recording(path);
+                }
+                Ok(WebSocketCommand::StopRecording) => {
+                    if let Some(recorder) = self.recorder.take() {
+                        match recorder.finish() {
+                            Ok(()) => info!("recording stopped"),
+                            Err(e) => {
+                                error!(error = %e, "recording failed");
+                                self.emit(WebSocketEvent::Error(format!(
+                                    "recording failed: {}",
+                                    e
+                                )));
+                            }
+                        }
                     }
                 }
+                Ok(WebSocketCommand::Replay { path, speed }) => {
+                    self.disconnect().await;
+                    // The replay may well be of the recording, so it gets all of it
+                    self.record(Recorder::flush);
+                    self.start_replay(path, speed);
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    debug!("all websocket handles dropped, stopping worker");
+                    self.disconnect().await;
+                    return false;
+                }
             }
+        }
 
-            // Handle incoming messages if connected
-            if let Some(ref mut ws_stream) = self.connection {
-                // Use a timeout to avoid blocking indefinitely
-                match tokio::time::timeout(
-                    tokio::time::Duration::from_millis(100),
-                    ws_stream.next(),
-                )
+        // Handle incoming messages if connected
+        if let Some(ref mut ws_stream) = self.connection {
+            // Use a timeout to avoid blocking indefinitely
+            match tokio::time::timeout(tokio::time::Duration::from_millis(100), ws_stream.next())
                 .await
-                {
-                    Ok(Some(msg_result)) => match msg_result {
-                        Ok(msg) => {
-                            self.handle_incoming_message(msg).await;
-                        }
-                        Err(e) => {
-                            ll(&format!("❌ WebSocket error: {}", e));
-                            self.disconnect().await;
-                        }
-                    },
-                    Ok(None) => {
-                        ll("🔌 WebSocket connection closed");
-                        self.disconnect().await;
+            {
+                Ok(Some(msg_result)) => match msg_result {
+                    Ok(msg) => {
+                        self.handle_incoming_message(msg).await;
                     }
-                    Err(_) => {
-                        // Timeout - continue the loop to check for commands
+                    Err(e) => {
+                        warn!(error = %e, "websocket error");
+                        self.emit(WebSocketEvent::Error(e.to_string()));
+                        self.connection_lost();
                     }
+                },
+                Ok(None) => {
+                    info!("connection closed by the server");
+                    self.connection_lost();
                 }
+                Err(_) => {
+                    // Timeout - continue the loop to check for commands
+                }
+            }
+        } else if self.replay.is_some() {
+            self.replay_next().await;
+        } else if let Some(retry_at) = self.retry_at {
+            let now = tokio::time::Instant::now();
+            if retry_at <= now {
+                self.retry_at = None;
+                self.connect().await;
             } else {
-                // Not connected, sleep a bit to avoid busy waiting
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                // Wake up for the retry or to check for commands, whichever comes first
+                let tick = now + tokio::time::Duration::from_millis(100);
+                tokio::time::sleep_until(retry_at.min(tick)).await;
             }
+        } else {
+            // Not connected, sleep a bit to avoid busy waiting
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        self.record(Recorder::flush_if_due);
+        true
+    }
+
+    fn emit(&self, event: WebSocketEvent) {
+        if let Err(e) = self.event_tx.send(event) {
+            debug!(error = %e, "failed to forward websocket event");
         }
     }
 
-    async fn connect(&mut self, url: &str) {
-        ll(&format!("🌐 Connecting to WebSocket: {}", url));
+    /// Dial the current server of the target, scheduling a retry on failure.
+    async fn connect(&mut self) {
+        let Some(target) = self.target.as_ref() else {
+            return;
+        };
+        let url = target.urls[target.server].clone();
+        let handshake = target.handshake.clone();
+        self.span = info_span!(
+            parent: None,
+            "connection",
+            url = %url,
+            server = target.server,
+            attempt = target.attempt
+        );
+
+        let span = self.span.clone();
+        self.dial(url, handshake).instrument(span).await;
+    }
+
+    async fn dial(&mut self, url: String, handshake: Option<Vec<u8>>) {
+        info!("connecting");
 
-        match connect_async(url).await {
+        match connect_async(url.as_str()).await {
             Ok((ws_stream, response)) => {
-                ll(&format!(
-                    "✅ Connected to WebSocket. Response: {:?}",
-                    response.status()
-                ));
+                info!(status = %response.status(), "connected");
                 self.connection = Some(ws_stream);
+                self.emit(WebSocketEvent::Connected { url });
 
-                // Send a connection success message
-                let msg = WebSocketMessage {
-                    mtype: "connection_status".to_string(),
-                    payload: serde_json::to_vec(&serde_json::json!({
-                        "status": "connected",
-                        "url": url
-                    }))
-                    .unwrap(),
-                    ts: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                };
-
-                if let Err(e) = self.msg_tx.send(msg) {
-                    ll(&format!("❌ Failed to send connection status: {}", e));
+                if let Some(handshake) = handshake {
+                    self.send_raw(handshake).await;
                 }
             }
             Err(e) => {
-                ll(&format!("❌ Failed to connect to WebSocket: {}", e));
-
-                // Send a connection failure message
-                let msg = WebSocketMessage {
-                    mtype: "connection_status".to_string(),
-                    payload: serde_json::to_vec(&serde_json::json!({
-                        "status": "failed",
-                        "error": e.to_string(),
-                        "url": url
-                    }))
-                    .unwrap(),
-                    ts: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                };
-
-                if let Err(send_err) = self.msg_tx.send(msg) {
-                    ll(&format!(
-                        "❌ Failed to send connection failure status: {}",
-                        send_err
-                    ));
-                }
+                warn!(error = %e, "failed to connect");
+                self.emit(WebSocketEvent::ConnectFailed {
+                    url,
+                    error: e.to_string(),
+                });
+                self.schedule_retry();
             }
         }
     }
 
+    /// Move on to the next server and arm the retry timer, or give up if the
+    /// target is not supervised.
+    fn schedule_retry(&mut self) {
+        let Some(target) = self.target.as_mut() else {
+            return;
+        };
+        let Some(ref backoff) = target.reconnect else {
+            self.target = None;
+            return;
+        };
+
+        target.attempt = target.attempt.saturating_add(1);
+        target.server = (target.server + 1) % target.urls.len();
+        let delay = backoff.delay(target.attempt);
+        let event = WebSocketEvent::Reconnecting {
+            attempt: target.attempt,
+            url: target.urls[target.server].clone(),
+            retry_at: Instant::now() + delay,
+        };
+        info!(
+            attempt = target.attempt,
+            next = %target.urls[target.server],
+            delay = ?delay,
+            "reconnecting"
+        );
+
+        self.retry_at = Some(tokio::time::Instant::now() + delay);
+        self.emit(event);
+    }
+
+    /// The server went away or the stream errored: drop it and maybe retry.
+    fn connection_lost(&mut self) {
+        if self.connection.take().is_some() {
+            self.emit(WebSocketEvent::Disconnected);
+        }
+        self.schedule_retry();
+    }
+
+    /// Close the connection on request and stop any supervision or replay.
     async fn disconnect(&mut self) {
+        self.target = None;
+        self.retry_at = None;
+
+        if self.replay.take().is_some() {
+            info!("replay stopped");
+            self.emit(WebSocketEvent::Disconnected);
+        }
+
         if let Some(mut ws_stream) = self.connection.take() {
-            ll("🔌 Disconnecting from WebSocket");
+            info!("disconnecting");
 
             if let Err(e) = ws_stream.close(None).await {
-                ll(&format!("⚠️ Error closing WebSocket: {}", e));
+                warn!(error = %e, "error closing websocket");
             }
 
-            // Send a disconnection message
-            let msg = WebSocketMessage {
-                mtype: "connection_status".to_string(),
-                payload: serde_json::to_vec(&serde_json::json!({
-                    "status": "disconnected"
-                }))
-                .unwrap(),
-                ts: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-            };
+            self.emit(WebSocketEvent::Disconnected);
+        }
+        self.span = Span::none();
+    }
 
-            if let Err(e) = self.msg_tx.send(msg) {
-                ll(&format!("❌ Failed to send disconnection status: {}", e));
+    fn start_recording(&mut self, path: PathBuf) {
+        match Recorder::create(&path) {
+            Ok(recorder) => {
+                info!(path = %path.display(), "recording");
+                self.recorder = Some(recorder);
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "cannot record");
+                self.emit(WebSocketEvent::Error(format!(
+                    "cannot record to {}: {}",
+                    path.display(),
+                    e
+                )));
             }
         }
     }
 
-    async fn send_message(&mut self, message: WebSocketMessage) {
-        if let Some(ref mut ws_stream) = self.connection {
-            ll(&format!("Sending {:?}", serde_json::to_string(&message)));
-            match serde_json::to_string(&message) {
-                Ok(json_str) => {
-                    if let Err(e) = ws_stream.send(Message::Text(json_str)).await {
-                        ll(&format!("❌ Failed to send WebSocket message: {}", e));
-                    } else {
-                        ll(&format!("📤 Sent WebSocket message"));
-                    }
+    /// Write a received frame to the recording, if there is one. A failing
+    /// recorder is dropped so it cannot get in the way of the live feed.
+    fn record(&mut self, write: impl FnOnce(&mut Recorder) -> std::io::Result<()>) {
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+        if let Err(e) = write(recorder) {
+            error!(error = %e, "recording failed, stopping");
+            self.recorder = None;
+            self.emit(WebSocketEvent::Error(format!("recording failed: {}", e)));
+        }
+    }
+
+    fn start_replay(&mut self, path: PathBuf, speed: ReplaySpeed) {
+        self.span = info_span!(parent: None, "replay", path = %path.display());
+        let _guard = self.span.clone().entered();
+        match RecordingReader::open(&path) {
+            Ok(reader) => {
+                info!(%speed, "replaying");
+                self.replay = Some(Replay {
+                    reader,
+                    speed,
+                    first: None,
+                    pending: None,
+                });
+                self.emit(WebSocketEvent::ReplayStarted { path });
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "cannot replay");
+                self.emit(WebSocketEvent::ReplayFailed {
+                    path,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Play the next frame of the replay once it is due. Waits at most 100ms
+    /// so commands are still picked up during long gaps.
+    async fn replay_next(&mut self) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        let frame = match replay.pending.take() {
+            Some(frame) => frame,
+            None => match replay.reader.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    info!("replay finished");
+                    self.replay = None;
+                    self.emit(WebSocketEvent::Disconnected);
+                    return;
                 }
                 Err(e) => {
-                    ll(&format!("❌ Failed to serialize message: {}", e));
+                    error!(error = %e, "replay failed");
+                    self.replay = None;
+                    self.emit(WebSocketEvent::Error(e.to_string()));
+                    self.emit(WebSocketEvent::Disconnected);
+                    return;
                 }
+            },
+        };
+
+        if let Some(due) = replay.due(&frame) {
+            let now = tokio::time::Instant::now();
+            if due > now {
+                replay.pending = Some(frame);
+                let tick = now + tokio::time::Duration::from_millis(100);
+                tokio::time::sleep_until(due.min(tick)).await;
+                return;
             }
-        } else {
-            ll("⚠️ Cannot send message: WebSocket not connected");
+        }
+
+        match frame.payload {
+            FramePayload::Text(text) => self.emit(WebSocketEvent::Text(text)),
+            FramePayload::Binary(data) => self.emit(WebSocketEvent::Binary(data)),
         }
     }
 
     async fn send_raw(&mut self, payload: Vec<u8>) {
         if let Some(ref mut ws_stream) = self.connection {
             match ws_stream.send(Message::Binary(payload)).await {
-                Ok(r) => ll(&format!("raw send ok: {r:?}")),
-                Err(e) => ll(&format!("raw send fail: {e:?}")),
+                Ok(()) => trace!("raw frame sent"),
+                Err(e) => warn!(error = %e, "failed to send raw frame"),
             };
+        } else {
+            warn!("cannot send, not connected");
         }
-        ()
     }
 
     async fn handle_incoming_message(&mut self, msg: Message) {
+        // Data flowing means the connection is healthy; start backing off from scratch next time
+        if matches!(msg, Message::Text(_) | Message::Binary(_))
+            && let Some(ref mut target) = self.target
+        {
+            target.attempt = 0;
+        }
+
         match msg {
             Message::Text(text) => {
-                // ll(&format!("📥 Received text message: {}", text));
-
-                match serde_json::from_str::<WebSocketMessage>(&text) {
-                    Ok(ws_message) => {
-                        if let Err(e) = self.msg_tx.send(ws_message) {
-                            ll(&format!(
-                                "❌ Failed to forward message to main thread: {}",
-                                e
-                            ));
-                        }
-                    }
-                    Err(_e) => {
-                        // Send a raw message for unstructured data
-                        let raw_message = WebSocketMessage {
-                            mtype: "raw_text".to_string(),
-                            payload: text.into_bytes(),
-                            ts: std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs(),
-                        };
-
-                        if let Err(send_err) = self.msg_tx.send(raw_message) {
-                            ll(&format!("❌ Failed to send raw message: {}", send_err));
-                        }
-                    }
-                }
+                self.record(|recorder| recorder.record_text(&text));
+                self.emit(WebSocketEvent::Text(text));
             }
             Message::Binary(data) => {
-                ll(&format!("📥 Received binary message: {} bytes", data.len()));
-
-                let binary_message = WebSocketMessage {
-                    mtype: "binary".to_string(),
-                    payload: serde_json::to_vec(&serde_json::json!({
-                        "size": data.len(),
-                        "data": base64::engine::general_purpose::STANDARD.encode(&data)
-                    }))
-                    .unwrap(),
-                    ts: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
-                };
-
-                if let Err(e) = self.msg_tx.send(binary_message) {
-                    ll(&format!("❌ Failed to send binary message: {}", e));
-                }
+                debug!(bytes = data.len(), "received binary frame");
+                self.record(|recorder| recorder.record_binary(&data));
+                self.emit(WebSocketEvent::Binary(data));
             }
             Message::Ping(data) => {
-                ll("🏓 Received ping");
-                if let Some(ws_stream) = &mut self.connection {
-                    if let Err(e) = ws_stream.send(Message::Pong(data)).await {
-                        ll(&format!("❌ Failed to send pong: {}", e));
-                    }
+                trace!("received ping");
+                if let Some(ws_stream) = &mut self.connection
+                    && let Err(e) = ws_stream.send(Message::Pong(data)).await
+                {
+                    warn!(error = %e, "failed to send pong");
                 }
             }
             Message::Pong(_) => {
-                ll("🏓 Received pong");
+                trace!("received pong");
             }
             Message::Close(_) => {
-                ll("🔌 Received close message");
+                debug!("received close frame");
             }
             Message::Frame(_) => {
-                ll("📦 Received frame message (should not happen in this context)");
+                debug!("received raw frame");
             }
         }
     }
@@ -348,74 +714,369 @@ impl WebSocketWorker {
 mod tests {
     use super::*;
     use crate::blitzortung::{BLITZ_HANDSHAKE, BLITZSERVERS, LightningStrike, decode};
+    use crate::fake_server::{FakeServer, FakeServerConfig, strike_fixtures};
+
+    fn fast_backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            multiplier: 2.0,
+            jitter: 0.0,
+        }
+    }
+
+    fn next_event(ws_manager: &WebSocketManager) -> WebSocketEvent {
+        ws_manager
+            .recv_event_timeout(Duration::from_secs(5))
+            .expect("timed out waiting for a websocket event")
+    }
+
+    /// Collect the next `n` text frames, skipping every other event.
+    fn next_texts(ws_manager: &WebSocketManager, n: usize) -> Vec<String> {
+        let mut texts = Vec::new();
+        while texts.len() < n {
+            if let WebSocketEvent::Text(text) = next_event(ws_manager) {
+                texts.push(text);
+            }
+        }
+        texts
+    }
+
+    fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition not met in time");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
     #[test]
-    fn test_websocket_message() {
-        let message = WebSocketMessage {
-            mtype: "text".to_string(),
-            payload: serde_json::to_vec(&serde_json::json!({
-                "content": "Hello, world!"
-            }))
-            .unwrap(),
-            ts: 1678901234,
+    fn backoff_grows_and_is_capped() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.0,
         };
+        assert_eq!(backoff.base_delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.base_delay(3), Duration::from_secs(4));
+        assert_eq!(backoff.base_delay(10), Duration::from_secs(10));
+        assert_eq!(backoff.delay(2), Duration::from_secs(2));
 
-        assert_eq!(message.mtype, "text");
-        // assert_eq!(message.payload["content"], "Hello, world!");
-        assert_eq!(message.ts, 1678901234);
+        let jittered = Backoff {
+            jitter: 0.5,
+            ..backoff
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(3));
+        }
     }
 
-    #[tokio::test]
-    async fn ws_connection() {
+    #[test]
+    fn supervised_connection_fails_over_and_reconnects() {
+        let server = FakeServer::start(FakeServerConfig {
+            strikes: Vec::new(),
+            ..Default::default()
+        });
         let ws_manager = WebSocketManager::new();
-        ws_manager.connect("wss://echo.websocket.org/".to_string());
+        // Nothing listens on port 1, so the first server is refused.
+        ws_manager.connect_supervised(
+            vec!["ws://127.0.0.1:1".to_string(), server.url.clone()],
+            Some(BLITZ_HANDSHAKE.to_vec()),
+            fast_backoff(),
+        );
 
-        // Wait for connection to establish
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        assert!(matches!(
+            next_event(&ws_manager),
+            WebSocketEvent::ConnectFailed { .. }
+        ));
+        match next_event(&ws_manager) {
+            WebSocketEvent::Reconnecting { attempt, url, .. } => {
+                assert_eq!(attempt, 1);
+                assert_eq!(url, server.url);
+            }
+            other => panic!("expected Reconnecting, got {other:?}"),
+        }
+        assert_eq!(
+            next_event(&ws_manager),
+            WebSocketEvent::Connected {
+                url: server.url.clone()
+            }
+        );
+        wait_until(|| server.handshakes() == 1);
 
-        // Check for connection status message
-        if let Some(incoming) = ws_manager.try_recv_message() {
-            println!("Debug: Full message received: {:?}", incoming);
-            // assert_eq!(status_msg.message_type, "connection_status");
-            // assert_eq!(status_msg.data["status"], "connected");
+        // Losing the connection rotates back to the first server, which fails
+        // again, and then lands on the working one with a fresh handshake.
+        server.drop_connections();
+        loop {
+            match next_event(&ws_manager) {
+                WebSocketEvent::Connected { url } => {
+                    assert_eq!(url, server.url);
+                    break;
+                }
+                WebSocketEvent::Reconnecting { attempt, .. } => assert!(attempt >= 1),
+                WebSocketEvent::Disconnected
+                | WebSocketEvent::Error(_)
+                | WebSocketEvent::ConnectFailed { .. } => {}
+                other => panic!("unexpected event {other:?}"),
+            }
         }
+        wait_until(|| server.handshakes() == 2);
+        assert_eq!(server.connections(), 2);
+
+        // An explicit disconnect stops the supervision.
+        ws_manager.disconnect();
+        assert_eq!(next_event(&ws_manager), WebSocketEvent::Disconnected);
+        assert!(
+            ws_manager
+                .recv_event_timeout(Duration::from_millis(200))
+                .is_none()
+        );
     }
 
-    #[tokio::test]
-    async fn blitzortung_connect() {
-        const MAXMSGS: usize = 2;
+    #[test]
+    fn each_connection_attempt_is_logged_in_its_own_span() {
+        let server = FakeServer::start(FakeServerConfig {
+            strikes: Vec::new(),
+            ..Default::default()
+        });
+        let (dispatch, logged) = crate::logging::capture("popuppp::websocket=debug");
+        // The worker thread picks up the subscriber of the thread creating it
+        let ws_manager = tracing::dispatcher::with_default(&dispatch, WebSocketManager::new);
+        ws_manager.connect_supervised(
+            vec!["ws://127.0.0.1:1".to_string(), server.url.clone()],
+            Some(BLITZ_HANDSHAKE.to_vec()),
+            fast_backoff(),
+        );
+        while !matches!(next_event(&ws_manager), WebSocketEvent::Connected { .. }) {}
+        ws_manager.disconnect();
+        assert_eq!(next_event(&ws_manager), WebSocketEvent::Disconnected);
+
+        let logged = String::from_utf8(logged.lock().unwrap().clone()).unwrap();
+        let failed = logged
+            .lines()
+            .find(|line| line.contains("failed to connect"))
+            .unwrap();
+        assert!(
+            failed.contains("connection{url=ws://127.0.0.1:1 server=0 attempt=0}"),
+            "{failed}"
+        );
+        let connected = logged
+            .lines()
+            .find(|line| line.contains("connected status="))
+            .unwrap();
+        assert!(
+            connected.contains(&format!(
+                "connection{{url={} server=1 attempt=1}}",
+                server.url
+            )),
+            "{connected}"
+        );
+    }
 
+    #[test]
+    fn connect_failure_is_reported() {
         let ws_manager = WebSocketManager::new();
-        ws_manager.connect(BLITZSERVERS[0].into());
-
-        // TODO: Use async magic to block here until connection is established
-        // Wait for connection to establish
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-        // Check for connection status message
-        if let Some(incoming) = ws_manager.try_recv_message() {
-            println!("Debug: Full message received: {:?}", incoming);
-        }
-
-        // Send magic json bytes
-        ws_manager.send_raw(BLITZ_HANDSHAKE.to_vec());
-        // Read MAXMSGS from the socket, then hang up. Print each message.
-        let mut msg_count = 0;
-        while msg_count < MAXMSGS {
-            if let Some(incoming) = ws_manager.try_recv_message() {
-                // It's a `raw_text` message. Inside the `payload` there's a bag of bytes representing a
-                // println!("Message {}: {:?}", msg_count + 1, incoming);
-                let payload_str = std::str::from_utf8(&incoming.payload).unwrap_or("");
-                let decoded: String = decode(payload_str); // takes &str
-                // println!("decoded message: {decoded:?}");
-                let lightning = serde_json::from_str::<LightningStrike>(&decoded);
-                // println!("Deserialized LightningStrike: {lightning:?}");
-                assert!(lightning.is_ok());
-
-                msg_count += 1;
-            } else {
-                // Wait a bit before checking again
-                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        // Nothing listens on port 1, so the connection is refused right away.
+        ws_manager.connect("ws://127.0.0.1:1".to_string());
+
+        match ws_manager.recv_event_blocking() {
+            Ok(WebSocketEvent::ConnectFailed { url, .. }) => {
+                assert_eq!(url, "ws://127.0.0.1:1");
             }
+            other => panic!("expected ConnectFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ws_connection() {
+        let server = FakeServer::start(FakeServerConfig {
+            require_handshake: false,
+            ..Default::default()
+        });
+        let ws_manager = WebSocketManager::new();
+        ws_manager.connect(server.url.clone());
+
+        assert_eq!(
+            next_event(&ws_manager),
+            WebSocketEvent::Connected {
+                url: server.url.clone()
+            }
+        );
+        assert_eq!(next_texts(&ws_manager, 1).len(), 1);
+    }
+
+    #[test]
+    fn blitzortung_connect() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig::default());
+        let ws_manager = WebSocketManager::new();
+        // The worker sends the handshake as soon as the connection is up.
+        ws_manager.connect_with_handshake(server.url.clone(), BLITZ_HANDSHAKE.to_vec());
+
+        // Every frame decodes back to the fixture it was encoded from.
+        let texts = next_texts(&ws_manager, fixtures.len());
+        for (text, fixture) in texts.iter().zip(&fixtures) {
+            let decoded = decode(text).unwrap();
+            assert_eq!(&decoded, fixture);
+            assert!(serde_json::from_str::<LightningStrike>(&decoded).is_ok());
+        }
+        assert_eq!(server.handshakes(), 1);
+
+        ws_manager.disconnect();
+        assert_eq!(next_event(&ws_manager), WebSocketEvent::Disconnected);
+    }
+
+    #[test]
+    fn pings_and_binary_frames_do_not_interrupt_the_stream() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig {
+            ping_every: Some(1),
+            binary_every: Some(2),
+            malformed_every: Some(3),
+            ..Default::default()
+        });
+        let ws_manager = WebSocketManager::new();
+        ws_manager.connect_with_handshake(server.url.clone(), BLITZ_HANDSHAKE.to_vec());
+
+        let mut strikes = 0;
+        let mut malformed = 0;
+        let mut binary = 0;
+        while strikes < fixtures.len() {
+            match next_event(&ws_manager) {
+                WebSocketEvent::Text(text) => match decode(&text) {
+                    Ok(decoded) => {
+                        assert!(serde_json::from_str::<LightningStrike>(&decoded).is_ok());
+                        strikes += 1;
+                    }
+                    Err(_) => malformed += 1,
+                },
+                WebSocketEvent::Binary(data) => {
+                    assert_eq!(data, [0xde, 0xad, 0xbe, 0xef]);
+                    binary += 1;
+                }
+                WebSocketEvent::Connected { .. } => {}
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+        // The last strike's extra frames may still be in flight
+        assert!(binary >= fixtures.len() / 2 - 1);
+        assert!(malformed >= fixtures.len() / 3 - 1);
+    }
+
+    #[test]
+    fn abrupt_close_is_reported() {
+        let server = FakeServer::start(FakeServerConfig {
+            close_after: Some(2),
+            ..Default::default()
+        });
+        let ws_manager = WebSocketManager::new();
+        ws_manager.connect_with_handshake(server.url.clone(), BLITZ_HANDSHAKE.to_vec());
+
+        assert_eq!(next_texts(&ws_manager, 2).len(), 2);
+        loop {
+            match next_event(&ws_manager) {
+                WebSocketEvent::Disconnected => break,
+                WebSocketEvent::Error(_) => {}
+                other => panic!("unexpected event {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn recorded_sessions_replay_the_same_frames() {
+        let fixtures = strike_fixtures();
+        let server = FakeServer::start(FakeServerConfig {
+            binary_every: Some(4),
+            ..Default::default()
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("storm.blzrec");
+
+        let ws_manager = WebSocketManager::new();
+        ws_manager.start_recording(path.clone());
+        ws_manager.connect_with_handshake(server.url.clone(), BLITZ_HANDSHAKE.to_vec());
+        let mut live = Vec::new();
+        while live.len() < fixtures.len() + fixtures.len() / 4 {
+            let event = next_event(&ws_manager);
+            if matches!(event, WebSocketEvent::Text(_) | WebSocketEvent::Binary(_)) {
+                live.push(event);
+            }
+        }
+        ws_manager.stop_recording();
+        ws_manager.disconnect();
+        assert_eq!(next_event(&ws_manager), WebSocketEvent::Disconnected);
+
+        ws_manager.replay(path.clone(), ReplaySpeed::Max);
+        assert_eq!(
+            next_event(&ws_manager),
+            WebSocketEvent::ReplayStarted { path: path.clone() }
+        );
+        let mut replayed = Vec::new();
+        loop {
+            match next_event(&ws_manager) {
+                WebSocketEvent::Disconnected => break,
+                event => replayed.push(event),
+            }
+        }
+        assert_eq!(replayed, live);
+    }
+
+    #[test]
+    fn replays_keep_the_recorded_pace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("slow.blzrec");
+        let mut recorder = crate::recording::Recorder::create(&path).unwrap();
+        recorder.record_text("first").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        recorder.record_text("second").unwrap();
+        drop(recorder);
+
+        let ws_manager = WebSocketManager::new();
+        for (speed, min, max) in [
+            (ReplaySpeed::Scaled(1.0), 190, 1000),
+            (ReplaySpeed::Scaled(4.0), 45, 150),
+        ] {
+            ws_manager.replay(path.clone(), speed);
+            let texts = next_texts(&ws_manager, 1);
+            let started = Instant::now();
+            assert_eq!(texts, ["first"]);
+            assert_eq!(next_texts(&ws_manager, 1), ["second"]);
+            let elapsed = started.elapsed();
+            assert!(
+                elapsed >= Duration::from_millis(min) && elapsed < Duration::from_millis(max),
+                "{speed}: {elapsed:?}"
+            );
+            assert_eq!(next_event(&ws_manager), WebSocketEvent::Disconnected);
+        }
+    }
+
+    #[test]
+    fn missing_recordings_fail_to_replay() {
+        let ws_manager = WebSocketManager::new();
+        ws_manager.replay("/nonexistent/storm.blzrec".into(), ReplaySpeed::Max);
+        match next_event(&ws_manager) {
+            WebSocketEvent::ReplayFailed { path, .. } => {
+                assert_eq!(path, PathBuf::from("/nonexistent/storm.blzrec"));
+            }
+            other => panic!("expected ReplayFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    #[ignore = "talks to the live Blitzortung servers"]
+    fn blitzortung_live() {
+        const MAXMSGS: usize = 2;
+
+        let ws_manager = WebSocketManager::new();
+        ws_manager.connect_with_handshake(BLITZSERVERS[0].into(), BLITZ_HANDSHAKE.to_vec());
+
+        // Read MAXMSGS from the socket, then hang up.
+        for text in next_texts(&ws_manager, MAXMSGS) {
+            let decoded = decode(&text).unwrap();
+            let lightning = serde_json::from_str::<LightningStrike>(&decoded);
+            assert!(lightning.is_ok());
         }
 
         ws_manager.disconnect();